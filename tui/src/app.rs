@@ -1,15 +1,28 @@
 use crate::{
-    backend::{Bounds, PayloadType, UIEvent},
-    dataset::{Dataset, Datasets, Logs},
+    backend::{
+        cap_facets, logs_to_csv, project_columns, Application, HistogramPayload, LogPayload,
+        Payload, PayloadType, UIEvent,
+    },
+    bundle::{Bundle, BundleTheme},
+    dataset::{merge_facets, parse_time, Dataset, Datasets, Logs},
+    favorites::{self, Favorite},
     input::Inputs,
-    query::NRQL,
-    ui::{style_detail_line, ui},
+    parser::split_statements,
+    query::{
+        from_source, is_trace_attribute, missing_timeseries, quote_identifier, quote_value,
+        retention_warning, trace_logs_query, QueryType, NRQL, WINDOW_PRESETS,
+    },
+    session::SessionFile,
+    svg::render_svg,
+    ui::{apply_filter, facet_colour, style_detail_line, ui},
     Config,
 };
 
-use chrono::{Timelike, Utc};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Timelike, Utc};
 use crossbeam_channel::{Receiver as CrossBeamReceiver, Sender as CrossBeamSender};
-use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use log::warn;
 use rand::{thread_rng, Rng};
 use ratatui::{
     backend::Backend,
@@ -21,14 +34,109 @@ use ratatui::{
 use std::{
     collections::{
         btree_map::{Entry, OccupiedEntry},
-        BTreeMap, HashSet, VecDeque,
+        BTreeMap, BTreeSet, HashMap, HashSet, VecDeque,
     },
     fs::{self, OpenOptions},
     io::Write,
-    sync::mpsc::Receiver,
-    time::Duration,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
 };
-use tokio::io;
+use tokio::{io, sync::mpsc::Receiver};
+
+const QUERY_DEBOUNCE: Duration = Duration::from_secs(1);
+const PINS_FILE: &str = "pins.yaml";
+const FAVORITES_FILE: &str = "favorites.yaml";
+const RECOVERY_FILE: &str = "recovery.yaml";
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(30);
+// Longest the refreshing indicator stays up if no data comes back
+const REFRESH_INDICATOR: Duration = Duration::from_secs(5);
+const PIN_COLOURS: [Color; 6] = [
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+];
+
+// Builds a Log query filtered on the key/value of a pretty-printed JSON line
+fn drill_down_query(line: &str) -> Option<String> {
+    let field = line.trim().trim_end_matches(',');
+    let object: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(&format!("{{{}}}", field)).ok()?;
+    let (key, value) = object.into_iter().next()?;
+    let value = match value {
+        serde_json::Value::String(s) => s,
+        serde_json::Value::Number(n) => n.to_string(),
+        serde_json::Value::Bool(b) => b.to_string(),
+        _ => return None,
+    };
+
+    Some(format!(
+        "SELECT * FROM Log WHERE {} = {}",
+        quote_identifier(&key),
+        quote_value(&value)
+    ))
+}
+
+// Tries the platform clipboard tools in turn
+fn copy_to_clipboard(text: &str) -> Result<()> {
+    let tools: [&[&str]; 3] = [
+        &["pbcopy"],
+        &["wl-copy"],
+        &["xclip", "-selection", "clipboard"],
+    ];
+    for tool in tools {
+        let child = Command::new(tool[0])
+            .args(&tool[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        if let Ok(mut child) = child {
+            if let Some(stdin) = child.stdin.as_mut() {
+                stdin.write_all(text.as_bytes())?;
+            }
+            if child.wait()?.success() {
+                return Ok(());
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "No clipboard tool found (pbcopy, wl-copy or xclip)"
+    ))
+}
+
+// Redraw only when something changed, or on the tick to keep animations and the clock moving
+fn needs_draw(dirty: bool, since_draw: Duration, tick_rate: Duration) -> bool {
+    dirty || since_draw >= tick_rate
+}
+
+// Pinned facet colours are stored by name, e.g. `prod: red`
+fn load_pins(path: &Path) -> BTreeMap<String, Color> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|yaml| serde_yaml::from_str::<BTreeMap<String, String>>(&yaml).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(facet, colour)| colour.parse::<Color>().ok().map(|c| (facet, c)))
+        .collect()
+}
+
+fn save_pins(path: &Path, pins: &BTreeMap<String, Color>) -> Result<()> {
+    let pins = pins
+        .iter()
+        .map(|(facet, colour)| (facet.to_owned(), colour.to_string()))
+        .collect::<BTreeMap<String, String>>();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_yaml::to_string(&pins)?)?;
+
+    Ok(())
+}
 
 pub struct UIFocus {
     pub tab: Tab,
@@ -61,6 +169,23 @@ pub enum Focus {
     LogDetail = 7,
     Search = 8,
     NoResult = 9,
+    DashboardSave = 10,
+    DashboardLoad = 11,
+    Help = 12,
+    QuitConfirm = 13,
+    Keyset = 14,
+    QueryDetail = 15,
+    RecoveryLoad = 16,
+    AppSearch = 17,
+    GoToTime = 18,
+    MergeFacets = 19,
+    FavoriteSave = 20,
+    Favorites = 21,
+}
+
+impl Focus {
+    // Sizes tables indexed by focus, so new variants take the next discriminant after Favorites
+    pub const COUNT: usize = Focus::Favorites as usize + 1;
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -78,8 +203,69 @@ pub struct Theme {
 pub enum Tab {
     Graph = 0,
     Logs = 1,
+    Summary = 2,
 }
 
+// Keys that would change a followed session's queries, time windows, filters or hidden facets
+const FOLLOWER_READ_ONLY: &[char] = &[
+    'e', 'x', 'D', 'E', 'r', 'O', 'o', 'K', 'A', 'f', 't', 'h', '1', '2', '3', '4', '5', 'F', 'C',
+];
+
+// Tabs in display order, indexed by their discriminant
+const TABS: [Tab; 3] = [Tab::Graph, Tab::Logs, Tab::Summary];
+
+// (context, key, action) for every Normal mode binding, shown in the help overlay
+pub const KEYBINDINGS: &[(&str, &str, &str)] = &[
+    ("Global", "?", "Toggle this help"),
+    ("Global", "e", "Enter a query"),
+    ("Global", "c", "Cancel the latest running query"),
+    ("Global", "j / k", "Next / previous item"),
+    ("Global", "T / Shift+Tab", "Next / previous tab"),
+    ("Global", "Enter / Space", "Open the selection"),
+    ("Global", "Esc", "Back"),
+    ("Global", "F", "Search"),
+    ("Global", "C", "Clear search filters"),
+    ("Global", "L", "Reload config"),
+    ("Global", "R / F5", "Refresh all queries now"),
+    ("Global", "W", "Freeze / unfreeze query windows on refresh"),
+    ("Global", "B", "Export a session bundle"),
+    ("Global", "y", "Copy all active queries"),
+    ("Global", "K", "Pick an attribute to insert into the query"),
+    ("Global", "i", "Show the full selected query"),
+    (
+        "Global",
+        "A",
+        "Search applications to insert an entity guid",
+    ),
+    ("Global", "+", "Add the query to favorites"),
+    ("Global", "o", "Pick a favorite query"),
+    ("Global", "q", "Save session and quit"),
+    ("Graph", "x", "Delete query"),
+    ("Graph", "D", "Duplicate query"),
+    ("Graph", "E", "Edit query in place"),
+    ("Graph", "r", "Rename query"),
+    ("Graph", "d", "Toggle dashboard"),
+    ("Graph", "S / O", "Save / load dashboard"),
+    ("Graph", "] / [", "Next / previous facet"),
+    ("Graph", "p", "Pin a colour to the facet"),
+    ("Graph", "h", "Hide / show the facet"),
+    ("Graph", "m / M", "Mark the facet / merge marked facets"),
+    ("Graph", "s", "Show only the facet / show all"),
+    ("Graph", "f", "Query the facet on its own"),
+    ("Graph", "t", "Show the logs of a trace id facet"),
+    ("Graph", "a", "Toggle facet average/max lines"),
+    ("Graph", "X", "Export the graph as SVG"),
+    ("Graph", "1-5", "Last 15m / 1h / 6h / 24h / 7d"),
+    ("Logs", "l", "Toggle log scale"),
+    ("Logs", "f", "Fold / unfold the JSON at the cursor"),
+    ("Logs", "z", "Toggle compact layout"),
+    ("Logs", "X", "Export logs as CSV"),
+    ("Logs", "w", "Toggle wrapping in detail"),
+    ("Logs", "Left / Right", "Scroll unwrapped detail"),
+    ("Logs", "Enter", "Drill down on the detail field"),
+    ("Logs", "g", "Jump to the log nearest a time"),
+];
+
 pub struct App {
     pub config: Box<Config>,
     pub inputs: Inputs,
@@ -92,6 +278,32 @@ pub struct App {
     pub query_history: VecDeque<String>,
     pub logs: Logs,
     pub facet_colours: BTreeMap<String, Color>,
+    pub error: Option<String>,
+    pub recent_dispatches: HashMap<String, Instant>,
+    pub pinned_colours: BTreeMap<String, Color>,
+    pub help_scroll: u16,
+    pub in_flight: Vec<(String, String)>,
+    pub cancelled: HashSet<String>,
+    pub log_filters: BTreeMap<String, BTreeSet<String>>,
+    pub hidden_facets: BTreeMap<String, BTreeSet<String>>,
+    pub editing: Option<String>,
+    pub show_annotations: bool,
+    // Refreshes keep each query's window from when it was first run, rather than sliding it
+    pub frozen_windows: bool,
+    pub quit_return: Focus,
+    pub keyset: Vec<String>,
+    pub keyset_state: ListState,
+    pub favorites: Vec<Favorite>,
+    pub favorite_state: ListState,
+    pub applications: Vec<Application>,
+    pub application_state: ListState,
+    pub recovery: Option<SessionFile>,
+    pub last_snapshot: Instant,
+    pub refresh_requested: Option<Instant>,
+    // Shown when a log query comes back empty
+    pub last_log_query: Option<String>,
+    // Session file mirrored read-only with --follow
+    pub following: Option<PathBuf>,
 }
 
 impl App {
@@ -100,6 +312,16 @@ impl App {
         data_rx: Receiver<PayloadType>,
         ui_tx: CrossBeamSender<UIEvent>,
     ) -> Self {
+        let pinned_colours = load_pins(&config.config_dir.join(PINS_FILE));
+        let favorites = favorites::load(&config.config_dir.join(FAVORITES_FILE));
+        let logs = Logs {
+            compact: config.compact_logs,
+            ..Logs::default()
+        };
+        // Left behind by a run that didn't exit cleanly
+        let recovery = SessionFile::read(&config.config_dir.join(RECOVERY_FILE))
+            .ok()
+            .filter(|snapshot| !snapshot.is_empty());
         Self {
             inputs: Inputs::new(),
             config,
@@ -108,20 +330,62 @@ impl App {
             focus: UIFocus::default(),
             list_state: ListState::default(),
             datasets: Datasets::new(),
-            logs: Logs::default(),
+            logs,
             facet_colours: BTreeMap::default(),
-            tabs: vec!["Logs".into()],
+            tabs: vec!["Graph".into(), "Logs".into(), "Summary".into()],
             query_history: VecDeque::default(),
+            error: None,
+            recent_dispatches: HashMap::default(),
+            pinned_colours,
+            help_scroll: 0,
+            in_flight: vec![],
+            cancelled: HashSet::default(),
+            log_filters: BTreeMap::default(),
+            hidden_facets: BTreeMap::default(),
+            editing: None,
+            show_annotations: false,
+            frozen_windows: false,
+            quit_return: Focus::Default,
+            keyset: vec![],
+            keyset_state: ListState::default(),
+            favorites,
+            favorite_state: ListState::default(),
+            applications: vec![],
+            application_state: ListState::default(),
+            recovery,
+            last_snapshot: Instant::now(),
+            refresh_requested: None,
+            last_log_query: None,
+            following: None,
         }
     }
 
+    // Keeps a recovery snapshot unless the app was quit normally
     pub fn run<B: Backend>(mut self, terminal: &mut Terminal<B>) -> io::Result<()> {
-        let mut rng = thread_rng();
+        let result = self.event_loop(terminal);
+        match result {
+            Ok(()) => self.clear_recovery(),
+            Err(_) => self.write_recovery(),
+        }
+        result
+    }
+
+    fn event_loop<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> io::Result<()> {
+        let mut dirty = true;
+        let mut last_draw = Instant::now();
         loop {
-            terminal.draw(|f| ui(&mut self, f))?;
+            if self.last_snapshot.elapsed() >= RECOVERY_INTERVAL {
+                self.write_recovery();
+            }
 
-            // Session Load
-            if !self.config.session.is_loaded {
+            // Recovery Load, then Session Load
+            if self.recovery.is_some() {
+                self.set_focus(UIFocus {
+                    panel: Focus::RecoveryLoad,
+                    input_mode: InputMode::Input,
+                    ..self.focus
+                });
+            } else if !self.config.session.is_loaded {
                 self.set_focus(UIFocus {
                     panel: Focus::SessionLoad,
                     input_mode: InputMode::Input,
@@ -129,271 +393,1075 @@ impl App {
                 });
             }
 
+            if needs_draw(dirty, last_draw.elapsed(), self.config.tick_rate) {
+                terminal.draw(|f| ui(self, f))?;
+                dirty = false;
+                last_draw = Instant::now();
+            }
+
             // Event handlers
             if let Ok(true) = event::poll(Duration::from_millis(50)) {
-                if let Event::Key(key) = event::read()? {
-                    match self.focus.input_mode {
-                        // Normal Mode
-                        InputMode::Normal if key.kind == KeyEventKind::Press => match key.code {
-                            KeyCode::Char('q') => {
+                let event = event::read()?;
+                dirty = true;
+                // With bracketed paste, pasted text arrives whole rather than as key presses
+                if let Event::Paste(text) = &event {
+                    if self.focus.input_mode == InputMode::Input {
+                        self.inputs.paste(self.focus.panel, text);
+                        self.search_changed();
+                    }
+                }
+                if let Event::Key(key) = event {
+                    if self.handle_key(key) {
+                        return Ok(());
+                    }
+                }
+            }
+
+            if self.drain_payloads() > 0 {
+                dirty = true;
+            }
+        }
+    }
+
+    // Whether a Normal mode key would change the session, which a follower only mirrors
+    fn changes_session(&self, code: KeyCode) -> bool {
+        match code {
+            // Opening a log line drills down into a new query
+            KeyCode::Enter | KeyCode::Char(' ') if self.focus.panel == Focus::LogDetail => true,
+            KeyCode::Char(c) => FOLLOWER_READ_ONLY.contains(&c),
+            _ => false,
+        }
+    }
+
+    // Applies a key press for the focused panel and mode, returning whether to quit
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        match self.focus.input_mode {
+            // Normal Mode
+            InputMode::Normal if key.kind == KeyEventKind::Press => match key.code {
+                code if self.following.is_some() && self.changes_session(code) => {
+                    self.error = Some(String::from(
+                        "Read-only: this session is followed from another instance",
+                    ));
+                    self.set_focus(UIFocus {
+                        panel: Focus::NoResult,
+                        ..self.focus
+                    });
+                }
+                KeyCode::Char('q') => self.begin_quit(),
+                KeyCode::Char('F') => self.set_focus(UIFocus {
+                    panel: Focus::Search,
+                    input_mode: InputMode::Input,
+                    ..self.focus
+                }),
+                KeyCode::Char('e') => {
+                    self.set_focus(UIFocus {
+                        panel: Focus::QueryInput,
+                        input_mode: InputMode::Input,
+                        ..self.focus
+                    });
+                }
+                KeyCode::Char('?') => match self.focus.panel {
+                    Focus::Help => self.set_focus(UIFocus {
+                        panel: Focus::Default,
+                        ..self.focus
+                    }),
+                    _ => {
+                        self.help_scroll = 0;
+                        self.set_focus(UIFocus {
+                            panel: Focus::Help,
+                            ..self.focus
+                        })
+                    }
+                },
+                KeyCode::Char('j') | KeyCode::Down => match self.focus.panel {
+                    Focus::LogDetail => {}
+                    Focus::Help => self.help_scroll = self.help_scroll.saturating_add(1),
+                    _ => self.next(),
+                },
+                KeyCode::Char('k') | KeyCode::Up => match self.focus.panel {
+                    Focus::LogDetail => {}
+                    Focus::Help => self.help_scroll = self.help_scroll.saturating_sub(1),
+                    _ => self.previous(),
+                },
+                KeyCode::Char('x') => self.delete_query(),
+                KeyCode::Char('c') => self.cancel_query(),
+                KeyCode::Char('y') => {
+                    if let Err(e) = copy_to_clipboard(&self.collect_queries()) {
+                        self.error = Some(e.to_string());
+                        self.set_focus(UIFocus {
+                            panel: Focus::NoResult,
+                            ..self.focus
+                        });
+                    }
+                }
+                KeyCode::Char('B') => {
+                    if let Err(e) = self.export_bundle() {
+                        self.error = Some(e.to_string());
+                        self.set_focus(UIFocus {
+                            panel: Focus::NoResult,
+                            ..self.focus
+                        });
+                    }
+                }
+                KeyCode::Char('K') => self.open_keyset(),
+                KeyCode::Char('+') => match self.current_query() {
+                    Some(_) => self.set_focus(UIFocus {
+                        panel: Focus::FavoriteSave,
+                        input_mode: InputMode::Input,
+                        ..self.focus
+                    }),
+                    None => {
+                        self.error = Some(String::from("No query to add to favorites"));
+                        self.set_focus(UIFocus {
+                            panel: Focus::NoResult,
+                            ..self.focus
+                        });
+                    }
+                },
+                KeyCode::Char('o') => {
+                    self.favorite_state.select(Some(0));
+                    self.set_focus(UIFocus {
+                        panel: Focus::Favorites,
+                        input_mode: InputMode::Input,
+                        ..self.focus
+                    });
+                }
+                KeyCode::Char('A') => {
+                    self.applications.clear();
+                    self.set_focus(UIFocus {
+                        panel: Focus::AppSearch,
+                        input_mode: InputMode::Input,
+                        ..self.focus
+                    });
+                }
+                KeyCode::Char('i') => match self.focus.panel {
+                    Focus::QueryDetail => self.set_focus(UIFocus {
+                        panel: Focus::Default,
+                        ..self.focus
+                    }),
+                    _ => self.set_focus(UIFocus {
+                        panel: Focus::QueryDetail,
+                        ..self.focus
+                    }),
+                },
+                KeyCode::Char('D') => self.duplicate_query(),
+                KeyCode::Char('E') => {
+                    self.duplicate_query();
+                    if self.focus.panel == Focus::QueryInput {
+                        self.editing = Some(self.datasets.selected.to_owned());
+                    }
+                }
+                KeyCode::Char('S') => self.set_focus(UIFocus {
+                    panel: Focus::DashboardSave,
+                    input_mode: InputMode::Input,
+                    ..self.focus
+                }),
+                KeyCode::Char('O') => self.set_focus(UIFocus {
+                    panel: Focus::DashboardLoad,
+                    input_mode: InputMode::Input,
+                    ..self.focus
+                }),
+                KeyCode::Char('r') => match self.focus.panel {
+                    Focus::QueryInput => {}
+                    _ => {
+                        if !self.datasets.is_empty() {
+                            self.set_focus(UIFocus {
+                                panel: Focus::Rename,
+                                input_mode: InputMode::Input,
+                                ..self.focus
+                            });
+                        }
+                    }
+                },
+                KeyCode::Char('d') => match self.focus.panel {
+                    Focus::Dashboard => self.set_focus(UIFocus {
+                        panel: Focus::Default,
+                        ..self.focus
+                    }),
+                    _ => self.set_focus(UIFocus {
+                        panel: Focus::Dashboard,
+                        ..self.focus
+                    }),
+                },
+                KeyCode::Char('w') if self.focus.panel == Focus::LogDetail => {
+                    self.logs.no_wrap = !self.logs.no_wrap;
+                    self.logs.detail_offset = 0;
+                }
+                KeyCode::Left if self.focus.panel == Focus::LogDetail => {
+                    self.logs.detail_offset = self.logs.detail_offset.saturating_sub(4);
+                }
+                KeyCode::Right if self.focus.panel == Focus::LogDetail => {
+                    self.logs.detail_offset = self.logs.detail_offset.saturating_add(4);
+                }
+                KeyCode::Char('g') if self.focus.tab == Tab::Logs => {
+                    if !self.logs.is_empty() {
+                        self.set_focus(UIFocus {
+                            panel: Focus::GoToTime,
+                            input_mode: InputMode::Input,
+                            ..self.focus
+                        });
+                    }
+                }
+                KeyCode::Char('X') if self.focus.tab == Tab::Logs => {
+                    if let Err(e) = self.export_logs_csv() {
+                        self.error = Some(e.to_string());
+                        self.set_focus(UIFocus {
+                            panel: Focus::NoResult,
+                            ..self.focus
+                        });
+                    }
+                }
+                KeyCode::Char('X') if self.focus.tab == Tab::Graph => {
+                    if let Err(e) = self.export_graph_svg() {
+                        self.error = Some(e.to_string());
+                        self.set_focus(UIFocus {
+                            panel: Focus::NoResult,
+                            ..self.focus
+                        });
+                    }
+                }
+                KeyCode::Char('z') if self.focus.tab == Tab::Logs => {
+                    self.logs.compact = !self.logs.compact;
+                }
+                KeyCode::Char('f') if self.focus.panel == Focus::Log => {
+                    self.logs.toggle_fold();
+                }
+                KeyCode::Char('l') if self.focus.tab == Tab::Logs => {
+                    self.logs.log_scale = !self.logs.log_scale;
+                }
+                KeyCode::Char(']') if self.focus.tab == Tab::Graph => {
+                    if let Some(dataset) = self.datasets.selected_mut() {
+                        dataset.next_facet();
+                    }
+                }
+                KeyCode::Char('[') if self.focus.tab == Tab::Graph => {
+                    if let Some(dataset) = self.datasets.selected_mut() {
+                        dataset.previous_facet();
+                    }
+                }
+                KeyCode::Char(c @ '1'..='5') if self.focus.tab == Tab::Graph => {
+                    let (_, since) = WINDOW_PRESETS[c as usize - '1' as usize];
+                    if let Err(e) = self.apply_window(since) {
+                        self.error = Some(e.to_string());
+                        self.set_focus(UIFocus {
+                            panel: Focus::NoResult,
+                            ..self.focus
+                        });
+                    }
+                }
+                KeyCode::Char('f') if self.focus.tab == Tab::Graph => self.open_facet_query(),
+                KeyCode::Char('t') if self.focus.tab == Tab::Graph => self.open_trace_logs(),
+                KeyCode::Char('s') if self.focus.tab == Tab::Graph => {
+                    if let Some(dataset) = self.datasets.selected_mut() {
+                        dataset.toggle_solo_facet();
+                    }
+                }
+                KeyCode::Char('m') if self.focus.tab == Tab::Graph => {
+                    if let Some(dataset) = self.datasets.selected_mut() {
+                        dataset.toggle_marked_facet();
+                    }
+                }
+                KeyCode::Char('M') if self.focus.tab == Tab::Graph => {
+                    if self
+                        .datasets
+                        .selected()
+                        .is_some_and(|dataset| !dataset.marked_facets.is_empty())
+                    {
+                        self.set_focus(UIFocus {
+                            panel: Focus::MergeFacets,
+                            input_mode: InputMode::Input,
+                            ..self.focus
+                        });
+                    }
+                }
+                KeyCode::Char('h') if self.focus.tab == Tab::Graph => {
+                    self.toggle_hidden_facet();
+                }
+                KeyCode::Char('a') if self.focus.tab == Tab::Graph => {
+                    self.show_annotations = !self.show_annotations;
+                }
+                KeyCode::Char('p') if self.focus.tab == Tab::Graph => self.pin_colour(),
+                KeyCode::Char('T') => self.next_tab(),
+                KeyCode::BackTab => self.previous_tab(),
+                KeyCode::Char('L') => self.reload_config(),
+                KeyCode::Char('R') | KeyCode::F(5) => self.refresh_all(),
+                KeyCode::Char('W') => {
+                    self.frozen_windows = !self.frozen_windows;
+                    _ = self.ui_tx.send(UIEvent::FreezeWindows(self.frozen_windows));
+                }
+                KeyCode::Char('C') => self.clear_filters(),
+                KeyCode::Esc => self.set_focus(UIFocus {
+                    panel: Focus::Default,
+                    ..self.focus
+                }),
+                KeyCode::Enter | KeyCode::Char(' ') => match self.focus.panel {
+                    Focus::Log => self.set_focus(UIFocus {
+                        panel: Focus::LogDetail,
+                        ..self.focus
+                    }),
+                    Focus::LogDetail => {
+                        let log = self
+                            .logs
+                            .log_item_list_state
+                            .selected()
+                            .and_then(|i| self.logs.selected_lines().get(i).cloned())
+                            .unwrap_or_default();
+                        match drill_down_query(&log) {
+                            Some(query) => {
+                                self.add_query(query);
                                 self.set_focus(UIFocus {
-                                    panel: Focus::SessionSave,
-                                    input_mode: InputMode::Input,
+                                    panel: Focus::Default,
                                     ..self.focus
                                 });
                             }
-                            KeyCode::Char('F') => self.set_focus(UIFocus {
-                                panel: Focus::Search,
-                                input_mode: InputMode::Input,
-                                ..self.focus
-                            }),
-                            KeyCode::Char('e') => {
+                            None => {
+                                self.error = Some(format!("Cannot drill down on '{}'", log.trim()));
                                 self.set_focus(UIFocus {
-                                    panel: Focus::QueryInput,
-                                    input_mode: InputMode::Input,
+                                    panel: Focus::NoResult,
                                     ..self.focus
                                 });
                             }
-                            KeyCode::Char('j') | KeyCode::Down => match self.focus.panel {
-                                Focus::LogDetail => {}
-                                _ => self.next(),
-                            },
-                            KeyCode::Char('k') | KeyCode::Up => match self.focus.panel {
-                                Focus::LogDetail => {}
-                                _ => self.previous(),
-                            },
-                            KeyCode::Char('x') => self.delete_query(),
-                            KeyCode::Char('r') => match self.focus.panel {
-                                Focus::QueryInput => {}
-                                _ => {
-                                    if !self.datasets.is_empty() {
-                                        self.set_focus(UIFocus {
-                                            panel: Focus::Rename,
-                                            input_mode: InputMode::Input,
-                                            ..self.focus
-                                        });
-                                    }
-                                }
-                            },
-                            KeyCode::Char('d') => match self.focus.panel {
-                                Focus::Dashboard => self.set_focus(UIFocus {
-                                    panel: Focus::Default,
-                                    ..self.focus
-                                }),
-                                _ => self.set_focus(UIFocus {
-                                    panel: Focus::Dashboard,
-                                    ..self.focus
-                                }),
-                            },
-                            KeyCode::Char('T') => self.next_tab(),
-                            KeyCode::Char('C') => self.clear_filters(),
-                            KeyCode::Esc => self.set_focus(UIFocus {
+                        }
+                    }
+                    Focus::Default => self.set_focus(UIFocus {
+                        panel: Focus::Log,
+                        ..self.focus
+                    }),
+                    _ => {}
+                },
+                _ => (),
+            },
+
+            // Input Mode
+            InputMode::Input if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Enter => {
+                    let mut next_panel = Focus::Default;
+                    match self.focus.panel {
+                        Focus::QueryInput => match self.submit_query() {
+                            Some(panel) => next_panel = panel,
+                            None => return false,
+                        },
+                        Focus::Rename => {
+                            self.rename_query(
+                                self.datasets.selected.to_owned(),
+                                self.inputs.get(Focus::Rename).to_owned(),
+                            );
+                        }
+                        Focus::Search => {
+                            let filter = self.inputs.get(Focus::Search);
+                            self.add_filter(filter.into());
+                            self.set_focus(UIFocus {
                                 panel: Focus::Default,
                                 ..self.focus
-                            }),
-                            KeyCode::Enter | KeyCode::Char(' ') => match self.focus.panel {
-                                Focus::Log => self.set_focus(UIFocus {
-                                    panel: Focus::LogDetail,
-                                    ..self.focus
-                                }),
-                                Focus::LogDetail => {
-                                    let key_idx = self.logs.log_item_list_state.selected().unwrap();
-                                    let log = &self.logs.selected().unwrap()[key_idx].to_string();
-                                    let correlation_id = log
-                                        .split(' ')
-                                        .last()
-                                        .unwrap()
-                                        .trim_matches(|p| char::is_ascii_punctuation(&p));
-                                    let query = format!("SELECT * FROM Log WHERE allColumnSearch('{}', insensitive: true)", correlation_id);
-
-                                    self.add_query(query);
-                                    self.set_focus(UIFocus {
-                                        panel: Focus::Default,
-                                        ..self.focus
-                                    });
+                            });
+                        }
+                        Focus::GoToTime => {
+                            let input = self.inputs.get(Focus::GoToTime).to_owned();
+                            if let Err(e) = self.go_to_time(&input) {
+                                self.error = Some(e.to_string());
+                                next_panel = Focus::NoResult;
+                            }
+                        }
+                        Focus::MergeFacets => {
+                            let name = self.inputs.get(Focus::MergeFacets).trim();
+                            match (name, self.datasets.selected_mut()) {
+                                ("", _) => {
+                                    self.error = Some("Merged facets need a name".to_owned());
+                                    next_panel = Focus::NoResult;
                                 }
-                                Focus::Default => self.set_focus(UIFocus {
-                                    panel: Focus::Log,
-                                    ..self.focus
-                                }),
-                                _ => {}
-                            },
-                            _ => (),
-                        },
-
-                        // Input Mode
-                        InputMode::Input if key.kind == KeyEventKind::Press => match key.code {
-                            KeyCode::Enter => {
-                                match self.focus.panel {
-                                    Focus::QueryInput => {
-                                        let raw_query = self.inputs.get(Focus::QueryInput);
-                                        self.add_query(raw_query.to_owned());
-                                        self.set_focus(UIFocus {
-                                            loading: true,
-                                            ..self.focus
-                                        });
-                                    }
-                                    Focus::Rename => {
-                                        self.rename_query(
-                                            self.datasets.selected.to_owned(),
-                                            self.inputs.get(Focus::Rename).to_owned(),
-                                        );
-                                    }
-                                    Focus::Search => {
-                                        let filter = self.inputs.get(Focus::Search);
-                                        self.add_filter(filter.into());
-                                        self.set_focus(UIFocus {
-                                            panel: Focus::Default,
-                                            ..self.focus
-                                        });
-                                    }
-                                    Focus::SessionLoad => {
-                                        match self.inputs.get(Focus::SessionLoad) {
-                                            // Load session
-                                            "y" | "Y" => {
-                                                self.load_session();
-                                            }
-                                            // Don't load session
-                                            _ => {
-                                                self.config.session.is_loaded = true;
-                                            }
-                                        }
-                                        // Update focus to default
-                                        self.set_focus(UIFocus {
-                                            panel: Focus::Default,
-                                            ..self.focus
-                                        });
-                                    }
-                                    Focus::SessionSave => {
-                                        match self.inputs.get(Focus::SessionSave) {
-                                            // Save session
-                                            "y" | "Y" => {
-                                                self.save_session();
-                                            }
-                                            _ => {}
-                                        }
-                                        return Ok(());
-                                    }
-                                    _ => {}
-                                };
-                                self.inputs.clear(self.focus.panel);
-                                self.inputs.reset_cursor(self.focus.panel);
-                                self.set_focus(UIFocus {
-                                    panel: Focus::Default,
-                                    input_mode: InputMode::Normal,
-                                    ..self.focus
-                                });
+                                (name, Some(dataset)) => dataset.merge_marked_facets(name),
+                                (_, None) => {}
                             }
-                            KeyCode::Char(to_insert) => {
-                                self.inputs.enter_char(self.focus.panel, to_insert);
+                        }
+                        Focus::DashboardSave => {
+                            let name = self.inputs.get(Focus::DashboardSave).to_owned();
+                            if let Err(e) = self.save_dashboard(&name) {
+                                self.error = Some(e.to_string());
+                                next_panel = Focus::NoResult;
                             }
-                            KeyCode::Backspace => {
-                                self.inputs.delete_char(self.focus.panel);
+                        }
+                        Focus::FavoriteSave => {
+                            let name = self.inputs.get(Focus::FavoriteSave).to_owned();
+                            if let Err(e) = self.add_favorite(&name) {
+                                self.error = Some(e.to_string());
+                                next_panel = Focus::NoResult;
                             }
-                            KeyCode::Left => {
-                                self.inputs.move_cursor_left(self.focus.panel);
+                        }
+                        Focus::Favorites => {
+                            let query = self.favorite_state.selected().and_then(|i| {
+                                self.filtered_favorites().get(i).map(|f| f.query.to_owned())
+                            });
+                            self.inputs.clear(Focus::Favorites);
+                            self.inputs.reset_cursor(Focus::Favorites);
+                            if let Some(query) = query {
+                                self.inputs.set(Focus::QueryInput, query);
+                                self.inputs.move_cursor_end(Focus::QueryInput);
                             }
-                            KeyCode::Right => {
-                                self.inputs.move_cursor_right(self.focus.panel);
+                            self.set_focus(UIFocus {
+                                panel: Focus::QueryInput,
+                                ..self.focus
+                            });
+                            return false;
+                        }
+                        Focus::DashboardLoad => {
+                            let name = self.inputs.get(Focus::DashboardLoad).to_owned();
+                            if let Err(e) = self.load_dashboard(&name) {
+                                self.error = Some(e.to_string());
+                                next_panel = Focus::NoResult;
                             }
-                            KeyCode::Up => {
-                                self.inputs.clear(Focus::QueryInput);
-                                let query = self.query_history.pop_front().unwrap_or_default();
-                                self.inputs.set(Focus::QueryInput, query.clone());
-                                self.inputs.move_cursor_end(Focus::QueryInput);
-                                self.query_history.push_back(query);
+                        }
+                        Focus::RecoveryLoad => {
+                            if let Some(recovery) = self.recovery.take() {
+                                match self.inputs.get(Focus::RecoveryLoad) {
+                                    "y" | "Y" => self.restore(recovery),
+                                    _ => self.clear_recovery(),
+                                }
                             }
-                            KeyCode::Down => {
-                                let query = self.query_history.pop_back().unwrap_or_default();
-                                self.inputs.set(Focus::QueryInput, query.clone());
-                                self.inputs.move_cursor_end(Focus::QueryInput);
-                                self.query_history.push_front(query);
+                        }
+                        Focus::SessionLoad => {
+                            match self.inputs.get(Focus::SessionLoad) {
+                                // Load session
+                                "y" | "Y" => {
+                                    if let Err(e) = self.load_session() {
+                                        self.error = Some(e.to_string());
+                                        next_panel = Focus::NoResult;
+                                    }
+                                }
+                                // Don't load session
+                                _ => {
+                                    self.config.session.is_loaded = true;
+                                }
                             }
-                            KeyCode::Esc => match self.focus.panel {
-                                Focus::SessionLoad => {}
+                        }
+                        Focus::SessionSave => {
+                            match self.inputs.get(Focus::SessionSave) {
+                                // Save session
+                                "y" | "Y" => {
+                                    self.save_session();
+                                    return true;
+                                }
                                 _ => {
-                                    self.set_focus(UIFocus {
-                                        panel: Focus::Default,
-                                        input_mode: InputMode::Normal,
-                                        ..self.focus
-                                    });
+                                    self.decline_save();
+                                    return false;
                                 }
-                            },
-                            _ => {}
-                        },
+                            }
+                        }
+                        Focus::QuitConfirm => {
+                            if let "y" | "Y" = self.inputs.get(Focus::QuitConfirm) {
+                                return true;
+                            }
+                            self.cancel_quit();
+                            return false;
+                        }
+                        Focus::Keyset => {
+                            let key = self
+                                .keyset_state
+                                .selected()
+                                .and_then(|i| self.filtered_keyset().get(i).map(|k| k.to_string()));
+                            if let Some(key) = key {
+                                self.insert_into_query(&key);
+                            }
+                            self.inputs.clear(Focus::Keyset);
+                            self.inputs.reset_cursor(Focus::Keyset);
+                            self.set_focus(UIFocus {
+                                panel: Focus::QueryInput,
+                                ..self.focus
+                            });
+                            return false;
+                        }
+                        // Search first, then insert the chosen application's guid
+                        Focus::AppSearch if self.applications.is_empty() => {
+                            let name = self.inputs.get(Focus::AppSearch).to_owned();
+                            _ = self.ui_tx.send(UIEvent::Applications(name));
+                            return false;
+                        }
+                        Focus::AppSearch => {
+                            let guid = self
+                                .application_state
+                                .selected()
+                                .and_then(|i| self.applications.get(i))
+                                .map(|application| quote_value(&application.guid));
+                            if let Some(guid) = guid {
+                                self.insert_into_query(&guid);
+                            }
+                            self.applications.clear();
+                            self.inputs.clear(Focus::AppSearch);
+                            self.inputs.reset_cursor(Focus::AppSearch);
+                            self.set_focus(UIFocus {
+                                panel: Focus::QueryInput,
+                                ..self.focus
+                            });
+                            return false;
+                        }
                         _ => {}
-                    }
+                    };
+                    self.inputs.clear(self.focus.panel);
+                    self.inputs.reset_cursor(self.focus.panel);
+                    self.set_focus(UIFocus {
+                        panel: next_panel,
+                        input_mode: InputMode::Normal,
+                        ..self.focus
+                    });
+                }
+                KeyCode::Char(to_insert) => {
+                    self.inputs.enter_char(self.focus.panel, to_insert);
+                    self.search_changed();
+                }
+                KeyCode::Backspace => {
+                    self.inputs.delete_char(self.focus.panel);
+                    self.search_changed();
+                }
+                KeyCode::Left => {
+                    self.inputs.move_cursor_left(self.focus.panel);
+                }
+                KeyCode::Right => {
+                    self.inputs.move_cursor_right(self.focus.panel);
+                }
+                KeyCode::Up if self.focus.panel == Focus::Keyset => {
+                    let i = self.keyset_state.selected().unwrap_or_default();
+                    self.keyset_state.select(Some(i.saturating_sub(1)));
+                }
+                KeyCode::Down if self.focus.panel == Focus::Keyset => {
+                    let last = self.filtered_keyset().len().saturating_sub(1);
+                    let i = self.keyset_state.selected().unwrap_or_default();
+                    self.keyset_state.select(Some((i + 1).min(last)));
+                }
+                KeyCode::Up if self.focus.panel == Focus::Favorites => {
+                    let i = self.favorite_state.selected().unwrap_or_default();
+                    self.favorite_state.select(Some(i.saturating_sub(1)));
+                }
+                KeyCode::Down if self.focus.panel == Focus::Favorites => {
+                    let last = self.filtered_favorites().len().saturating_sub(1);
+                    let i = self.favorite_state.selected().unwrap_or_default();
+                    self.favorite_state.select(Some((i + 1).min(last)));
                 }
+                KeyCode::Up if self.focus.panel == Focus::AppSearch => {
+                    let i = self.application_state.selected().unwrap_or_default();
+                    self.application_state.select(Some(i.saturating_sub(1)));
+                }
+                KeyCode::Down if self.focus.panel == Focus::AppSearch => {
+                    let last = self.applications.len().saturating_sub(1);
+                    let i = self.application_state.selected().unwrap_or_default();
+                    self.application_state.select(Some((i + 1).min(last)));
+                }
+                KeyCode::Up => {
+                    self.inputs.clear(Focus::QueryInput);
+                    let query = self.query_history.pop_front().unwrap_or_default();
+                    self.inputs.set(Focus::QueryInput, query.clone());
+                    self.inputs.move_cursor_end(Focus::QueryInput);
+                    self.query_history.push_back(query);
+                }
+                KeyCode::Down => {
+                    let query = self.query_history.pop_back().unwrap_or_default();
+                    self.inputs.set(Focus::QueryInput, query.clone());
+                    self.inputs.move_cursor_end(Focus::QueryInput);
+                    self.query_history.push_front(query);
+                }
+                KeyCode::Esc => match self.focus.panel {
+                    Focus::SessionLoad | Focus::RecoveryLoad => {}
+                    Focus::QuitConfirm => self.cancel_quit(),
+                    _ => {
+                        self.editing = None;
+                        self.set_focus(UIFocus {
+                            panel: Focus::Default,
+                            input_mode: InputMode::Normal,
+                            ..self.focus
+                        });
+                    }
+                },
+                _ => {}
+            },
+            _ => {}
+        }
+        false
+    }
+
+    // Full text of the selected query, its parsed clauses and facets, for the detail popup
+    pub fn query_detail(&self) -> Vec<String> {
+        let (query, dataset) = match self.focus.tab {
+            Tab::Logs if !self.logs.query.is_empty() => (self.logs.query.to_owned(), None),
+            _ => match self.datasets.selected() {
+                Some(dataset) => (self.datasets.selected.to_owned(), Some(dataset)),
+                None => return vec![String::from("No query selected")],
+            },
+        };
+
+        let mut lines = vec![String::from("Query:"), query];
+        if let Some(alias) = dataset.and_then(|d| d.query_alias.as_ref()) {
+            lines.push(String::new());
+            lines.push(format!("Alias: {}", alias));
+        }
+        if let Some(nrql) = dataset.and_then(|d| d.nrql.as_ref()) {
+            lines.push(String::new());
+            lines.push(String::from("Resolved:"));
+            lines.extend(nrql.pretty().lines().map(|line| format!("  {}", line)));
+        }
+        if let Some(dataset) = dataset.filter(|d| !d.facets.is_empty()) {
+            lines.push(String::new());
+            lines.push(format!("Facets ({}):", dataset.facets.len()));
+            lines.extend(dataset.facets.keys().map(|facet| format!("  {}", facet)));
+        }
+
+        lines
+    }
+
+    // Looks up the attributes of the source being typed, or of the selected query
+    fn open_keyset(&mut self) {
+        let source = from_source(self.inputs.get(Focus::QueryInput))
+            .or_else(|| {
+                self.datasets
+                    .selected()
+                    .and_then(|dataset| dataset.nrql.as_ref())
+                    .map(|nrql| nrql.from.to_owned())
+            })
+            .unwrap_or(String::from("Log"));
+
+        self.keyset.clear();
+        self.keyset_state.select(Some(0));
+        _ = self.ui_tx.send(UIEvent::Keyset(source));
+        self.set_focus(UIFocus {
+            panel: Focus::Keyset,
+            input_mode: InputMode::Input,
+            ..self.focus
+        });
+    }
+
+    // Attribute names containing the search text, ignoring case
+    pub fn filtered_keyset(&self) -> Vec<&String> {
+        let search = self.inputs.get(Focus::Keyset).to_lowercase();
+        self.keyset
+            .iter()
+            .filter(|key| key.to_lowercase().contains(&search))
+            .collect()
+    }
+
+    // The log query on the Logs tab, otherwise the selected timeseries query
+    fn current_query(&self) -> Option<String> {
+        match self.focus.tab {
+            Tab::Logs if !self.logs.query.is_empty() => Some(self.logs.query.to_owned()),
+            _ => self
+                .datasets
+                .selected()
+                .map(|_| self.datasets.selected.to_owned()),
+        }
+    }
+
+    fn add_favorite(&mut self, name: &str) -> Result<()> {
+        let query = self
+            .current_query()
+            .ok_or(anyhow!("No query to add to favorites"))?;
+        favorites::add(&mut self.favorites, &query, Some(name));
+        favorites::save(
+            &self.config.config_dir.join(FAVORITES_FILE),
+            &self.favorites,
+        )
+    }
+
+    // Favorites whose name or query contains the search text, ignoring case
+    pub fn filtered_favorites(&self) -> Vec<&Favorite> {
+        let search = self.inputs.get(Focus::Favorites).to_lowercase();
+        self.favorites
+            .iter()
+            .filter(|favorite| {
+                favorite.label().to_lowercase().contains(&search)
+                    || favorite.query.to_lowercase().contains(&search)
+            })
+            .collect()
+    }
+
+    // Selects the log nearest the given time, moving the seek marker with it
+    fn go_to_time(&mut self, input: &str) -> Result<()> {
+        let latest = self.logs.bounds.maxes.0.max(0_f64);
+        let latest = DateTime::from_timestamp_millis(latest as i64).unwrap_or_else(Utc::now);
+        let target = parse_time(input, latest, Utc::now())
+            .ok_or(anyhow!("Could not understand the time '{}'", input.trim()))?;
+
+        if let Some(i) = self.logs.nearest(target) {
+            let i = self.logs.select(i);
+            self.logs.log_list_state.select(i);
+        }
+
+        Ok(())
+    }
+
+    // Typing in a picker resets its selection, and an application search has to be re-run
+    fn search_changed(&mut self) {
+        match self.focus.panel {
+            Focus::Keyset => self.keyset_state.select(Some(0)),
+            Focus::Favorites => self.favorite_state.select(Some(0)),
+            Focus::AppSearch => self.applications.clear(),
+            _ => {}
+        }
+    }
+
+    // Inserts text at the query box's cursor
+    fn insert_into_query(&mut self, text: &str) {
+        text.chars()
+            .for_each(|c| self.inputs.enter_char(Focus::QueryInput, c));
+    }
+
+    // Handles every payload that's ready rather than one per loop, returning how many there were
+    pub fn drain_payloads(&mut self) -> usize {
+        let mut received = 0;
+        while let Ok(payload) = self.data_rx.try_recv() {
+            self.receive(payload);
+            received += 1;
+        }
+        received
+    }
+
+    // Marks the payload's query as finished, whether it has data, none or failed
+    fn receive(&mut self, payload: PayloadType) {
+        if let Some(key) = payload.query() {
+            // Results that were already on their way when cancelled
+            if self.cancelled.remove(key) {
+                return;
             }
+            self.in_flight.retain(|(k, _)| k != key);
+        }
+
+        self.handle_payload(payload);
+    }
 
-            while let Some(payload) = self.data_rx.try_iter().next() {
-                match payload {
-                    PayloadType::None => self.set_focus(UIFocus {
+    fn handle_payload(&mut self, payload: PayloadType) {
+        let mut rng = thread_rng();
+        match payload {
+            PayloadType::None(_) => self.set_focus(UIFocus {
+                panel: Focus::NoResult,
+                loading: false,
+                ..self.focus
+            }),
+            PayloadType::SessionChanged => self.follow_session(),
+            PayloadType::Error { message, .. } => {
+                self.error = Some(message);
+                self.set_focus(UIFocus {
+                    panel: Focus::NoResult,
+                    loading: false,
+                    ..self.focus
+                });
+            }
+            PayloadType::Applications(applications) => {
+                if applications.is_empty() {
+                    self.error = Some(String::from("No matching applications"));
+                    self.set_focus(UIFocus {
                         panel: Focus::NoResult,
-                        loading: false,
+                        input_mode: InputMode::Normal,
                         ..self.focus
-                    }),
-                    PayloadType::Timeseries(payload) => {
-                        if let Entry::Vacant(e) = self.datasets.entry(payload.query.clone()) {
-                            e.insert(Dataset {
-                                query_alias: None,
-                                facets: payload.data,
-                                bounds: payload.bounds,
-                                selection: payload.selection,
-                                has_data: true,
-                            });
-                        } else {
-                            _ = self
-                                .datasets
-                                .entry(payload.query.to_owned())
-                                .and_modify(|data| {
-                                    data.facets = payload.data;
-                                    data.bounds = payload.bounds;
-                                    data.has_data = true
-                                })
-                        }
+                    });
+                }
+                self.applications = applications;
+                self.application_state.select(Some(0));
+            }
+            PayloadType::Keyset(keys) => {
+                self.keyset = keys;
+                self.keyset_state.select(Some(0));
+            }
+            PayloadType::Timeseries(payload) => {
+                self.refresh_requested = None;
+                let merged = self
+                    .datasets
+                    .datasets
+                    .get(&payload.query)
+                    .map(|data| data.merged_facets.clone())
+                    .unwrap_or_default();
+                let facets =
+                    cap_facets(merge_facets(payload.data, &merged), self.config.max_facets);
+                let facet_keys = facets.keys().cloned().collect::<Vec<String>>();
 
-                        for facet_key in payload.facets {
-                            // Only add facet key if not present
-                            if let Entry::Vacant(e) = self.facet_colours.entry(facet_key) {
-                                e.insert(Color::Rgb(
-                                    rng.gen::<u8>(),
-                                    rng.gen::<u8>(),
-                                    rng.gen::<u8>(),
-                                ));
+                if let Entry::Vacant(e) = self.datasets.entry(payload.query.clone()) {
+                    e.insert(Dataset {
+                        facets,
+                        bounds: payload.bounds,
+                        selection: payload.selection,
+                        has_data: true,
+                        window: payload.window,
+                        nrql: payload.nrql,
+                        rate_unit: payload.rate_unit,
+                        raw_facets: payload.raw_facets,
+                        ..Default::default()
+                    });
+                } else {
+                    _ = self
+                        .datasets
+                        .entry(payload.query.to_owned())
+                        .and_modify(|data| {
+                            data.previous_value = data.latest_value();
+                            data.previous_facet_values = data.latest_facet_values();
+                            data.facets = facets;
+                            data.bounds = payload.bounds;
+                            data.window = payload.window;
+                            data.nrql = payload.nrql;
+                            data.rate_unit = payload.rate_unit;
+                            data.raw_facets = payload.raw_facets;
+                            data.has_data = true;
+                            // A soloed facet that dropped out would leave the chart empty
+                            if data
+                                .solo_facet
+                                .as_ref()
+                                .is_some_and(|solo| !data.facets.contains_key(solo))
+                            {
+                                data.solo_facet = None;
                             }
-                        }
+                        })
+                }
+
+                for facet_key in facet_keys {
+                    // Only add facet key if not present
+                    if let Entry::Vacant(e) = self.facet_colours.entry(facet_key) {
+                        e.insert(Color::Rgb(
+                            rng.gen::<u8>(),
+                            rng.gen::<u8>(),
+                            rng.gen::<u8>(),
+                        ));
                     }
-                    PayloadType::Log(payload) => {
-                        let mut logs: BTreeMap<String, Vec<String>> = BTreeMap::new();
-                        for (timestamp, log) in payload.logs {
-                            logs.insert(timestamp, log.split('\n').map(|v| v.into()).collect());
-                        }
+                }
+                self.prune_facet_colours();
+            }
+            PayloadType::Histogram(payload) => {
+                self.refresh_requested = None;
+                if let Entry::Vacant(e) = self.datasets.entry(payload.query.clone()) {
+                    e.insert(Dataset {
+                        has_data: true,
+                        histogram: Some(payload.buckets),
+                        ..Default::default()
+                    });
+                } else {
+                    _ = self
+                        .datasets
+                        .entry(payload.query.to_owned())
+                        .and_modify(|data| {
+                            data.histogram = Some(payload.buckets);
+                            data.has_data = true
+                        })
+                }
 
-                        if !logs.is_empty() {
-                            self.logs = Logs {
-                                selected: logs.first_entry().unwrap().key().into(),
-                                logs,
-                                log_item_list_state: ListState::default(),
-                                chart_data: payload.chart_data,
-                                bounds: payload.bounds,
-                                filters: HashSet::default(),
-                                log_list_state: ListState::default(),
-                            };
-                        }
+                self.set_focus(UIFocus {
+                    loading: false,
+                    ..self.focus
+                });
+            }
+            PayloadType::Log(payload) => {
+                let mut logs: BTreeMap<String, Vec<String>> = BTreeMap::new();
+                let mut columns: BTreeMap<String, String> = BTreeMap::new();
+                for (timestamp, log) in payload.logs {
+                    // Nothing to list or show in the detail view
+                    if log.trim().is_empty() {
+                        continue;
+                    }
+                    if let Ok(value) = serde_json::from_str(&log) {
+                        columns.insert(
+                            timestamp.clone(),
+                            project_columns(&value, &self.config.log_columns),
+                        );
+                    }
+                    logs.insert(timestamp, log.split('\n').map(|v| v.into()).collect());
+                }
 
-                        self.set_focus(UIFocus {
-                            loading: false,
-                            ..self.focus
-                        });
+                if !logs.is_empty() {
+                    self.logs = Logs {
+                        query: payload.query.clone(),
+                        selected: String::new(),
+                        logs,
+                        columns,
+                        log_item_list_state: ListState::default(),
+                        chart_data: payload.chart_data,
+                        bounds: payload.bounds,
+                        filters: HashSet::default(),
+                        count: payload.count,
+                        log_list_state: ListState::default(),
+                        no_wrap: self.logs.no_wrap,
+                        detail_offset: 0,
+                        log_scale: self.logs.log_scale,
+                        compact: self.logs.compact,
+                        folds: self.logs.folds.clone(),
+                    };
+
+                    // Re-apply the filters this query had before
+                    let filters = self.log_filters.get(&payload.query).cloned();
+                    for filter in filters.unwrap_or_default() {
+                        self.add_filter(filter);
                     }
+                    self.logs.select_first();
                 }
+
+                self.set_focus(UIFocus {
+                    loading: false,
+                    ..self.focus
+                });
+            }
+        }
+    }
+
+    // Every active chart and log query, with aliases as NRQL comments
+    fn collect_queries(&self) -> String {
+        let mut lines = vec![];
+        for (query, data) in self.datasets.iter() {
+            if let Some(alias) = &data.query_alias {
+                lines.push(format!("-- {}", alias));
+            }
+            lines.push(query.to_owned());
+        }
+        if !self.logs.query.is_empty() {
+            lines.push(self.logs.query.to_owned());
+        }
+
+        lines.join("\n")
+    }
+
+    // Writes queries, aliases, filters, theme and the last fetched data to one file
+    fn export_bundle(&self) -> Result<PathBuf> {
+        let mut bundle = Bundle {
+            theme: BundleTheme {
+                focus_fg: self.config.theme.focus_fg.to_string(),
+                chart_fg: self.config.theme.chart_fg.to_string(),
+            },
+            history: self.query_history.iter().cloned().collect(),
+            log_filters: self.log_filters.clone(),
+            ..Bundle::default()
+        };
+
+        for (query, data) in self.datasets.iter() {
+            if let Some(alias) = &data.query_alias {
+                bundle.aliases.insert(query.to_owned(), alias.to_owned());
             }
+            if let Some(buckets) = &data.histogram {
+                bundle.histograms.push(HistogramPayload {
+                    query: query.to_owned(),
+                    buckets: buckets.clone(),
+                });
+            } else if data.has_data {
+                bundle.timeseries.push(Payload {
+                    query: query.to_owned(),
+                    data: data.facets.clone(),
+                    bounds: data.bounds,
+                    selection: data.selection.clone(),
+                    window: data.window,
+                    nrql: data.nrql.clone(),
+                    rate_unit: data.rate_unit.clone(),
+                    raw_facets: data.raw_facets.clone(),
+                });
+            }
+        }
+
+        if !self.logs.is_empty() {
+            bundle.logs = Some(LogPayload {
+                query: self.logs.query.clone(),
+                logs: self
+                    .logs
+                    .iter()
+                    .map(|(timestamp, lines)| (timestamp.to_owned(), lines.join("\n")))
+                    .collect(),
+                chart_data: self.logs.chart_data.clone(),
+                bounds: self.logs.bounds,
+                count: self.logs.count,
+            });
+        }
+
+        let path = self
+            .config
+            .config_dir
+            .join(format!("bundle-{}.json", Utc::now().format("%Y%m%d%H%M%S")));
+        bundle.write(&path)?;
+
+        Ok(path)
+    }
+
+    // Writes the logs that pass the current filters to a flat CSV file
+    fn export_logs_csv(&self) -> Result<PathBuf> {
+        let logs = self
+            .logs
+            .iter()
+            .filter(|(_, lines)| apply_filter(self, lines))
+            .filter_map(|(_, lines)| serde_json::from_str(&lines.join("\n")).ok())
+            .collect::<Vec<serde_json::Value>>();
+        if logs.is_empty() {
+            return Err(anyhow!("No logs to export"));
+        }
+
+        let path = self
+            .config
+            .config_dir
+            .join(format!("logs-{}.csv", Utc::now().format("%Y%m%d%H%M%S")));
+        fs::create_dir_all(&self.config.config_dir)?;
+        fs::write(&path, logs_to_csv(&logs))?;
+
+        Ok(path)
+    }
+
+    // Saves the selected graph's visible facets as `<alias>-<time>.svg` in the config directory
+    fn export_graph_svg(&self) -> Result<PathBuf> {
+        let query = &self.datasets.selected;
+        let dataset = self
+            .datasets
+            .selected()
+            .filter(|dataset| dataset.has_data && dataset.histogram.is_none())
+            .ok_or(anyhow!("No graph to export"))?;
+        let facets = dataset
+            .facets
+            .iter()
+            .filter(|(facet, _)| !self.is_hidden(query, facet))
+            .map(|(facet, points)| (facet.to_owned(), points.clone()))
+            .collect::<BTreeMap<String, Vec<(f64, f64)>>>();
+
+        let title = dataset.query_alias.as_ref().unwrap_or(query);
+        let name = dataset
+            .query_alias
+            .as_deref()
+            .unwrap_or("graph")
+            .chars()
+            .map(|c| match c.is_alphanumeric() || c == '-' || c == '_' {
+                true => c,
+                false => '_',
+            })
+            .collect::<String>();
+        let path = self.config.config_dir.join(format!(
+            "{}-{}.svg",
+            name,
+            Utc::now().format("%Y%m%d%H%M%S")
+        ));
+        fs::create_dir_all(&self.config.config_dir)?;
+        fs::write(
+            &path,
+            render_svg(title, &facets, dataset.bounds, |facet| {
+                facet_colour(self, facet)
+            }),
+        )?;
+
+        Ok(path)
+    }
+
+    // Restores an exported bundle without contacting New Relic
+    pub fn import_bundle(&mut self, bundle: Bundle) {
+        if let (Ok(focus_fg), Ok(chart_fg)) = (
+            bundle.theme.focus_fg.parse::<Color>(),
+            bundle.theme.chart_fg.parse::<Color>(),
+        ) {
+            self.config.theme = Theme { focus_fg, chart_fg };
+        }
+        self.query_history = VecDeque::from(bundle.history);
+        self.log_filters = bundle.log_filters;
+        self.config.session.is_loaded = true;
+
+        for payload in bundle.timeseries {
+            self.handle_payload(PayloadType::Timeseries(payload));
+        }
+        for payload in bundle.histograms {
+            self.handle_payload(PayloadType::Histogram(payload));
+        }
+        if let Some(payload) = bundle.logs {
+            self.handle_payload(PayloadType::Log(payload));
+        }
+        for (query, alias) in bundle.aliases {
+            self.rename_query(query, alias);
         }
     }
 
     // TODO
     fn add_filter(&mut self, filter: String) {
+        self.log_filters
+            .entry(self.logs.query.clone())
+            .or_default()
+            .insert(filter.clone());
         self.logs.filters.insert(filter.clone());
         self.logs.logs.retain(|_key, value| {
             for line in value {
@@ -402,17 +1470,17 @@ impl App {
                 }
             }
             false
-        })
+        });
+        if self.logs.selected().is_none() {
+            self.logs.select_first();
+        }
     }
 
     fn rename_query(&mut self, query: String, alias: String) {
         if let Entry::Vacant(e) = self.datasets.entry(query.to_owned()) {
             e.insert(Dataset {
-                has_data: false,
                 query_alias: Some(alias),
-                facets: BTreeMap::default(),
-                bounds: Bounds::default(),
-                selection: String::new(),
+                ..Default::default()
             });
         } else {
             _ = self.datasets.entry(query.to_owned()).and_modify(|data| {
@@ -421,9 +1489,296 @@ impl App {
         }
     }
 
-    fn add_query(&mut self, query: String) {
-        self.query_history.push_back(query.clone());
-        _ = self.ui_tx.send(UIEvent::AddQuery(query));
+    // Runs the query box, returning the panel to show next, or None to keep a blank box open
+    // as it would only leave a spinner with nothing to wait for
+    fn submit_query(&mut self) -> Option<Focus> {
+        let raw_query = self.inputs.get(Focus::QueryInput).to_owned();
+        if raw_query.trim().is_empty() {
+            return None;
+        }
+        match self.editing.take() {
+            Some(original) => self.replace_query(original, raw_query),
+            None => self.add_query(raw_query),
+        }
+        match self.error {
+            Some(_) => Some(Focus::NoResult),
+            None => {
+                self.set_focus(UIFocus {
+                    loading: true,
+                    ..self.focus
+                });
+                Some(Focus::Default)
+            }
+        }
+    }
+
+    // Dispatches each `;`-separated query in the batch as its own dataset
+    fn add_query(&mut self, query: String) {
+        self.error = None;
+
+        for statement in split_statements(&query) {
+            self.dispatch_query(statement);
+        }
+    }
+
+    fn dispatch_query(&mut self, query: String) {
+        // Collapse identical submissions made in quick succession into one dispatch
+        let now = Instant::now();
+        self.recent_dispatches
+            .retain(|_, dispatched| now.duration_since(*dispatched) < QUERY_DEBOUNCE);
+        if self.recent_dispatches.contains_key(&query) {
+            return;
+        }
+        self.recent_dispatches.insert(query.clone(), now);
+
+        let query_type = QueryType::from(query.as_str());
+        if let QueryType::Log(_) = query_type {
+            if missing_timeseries(&query) {
+                self.error = Some(format!(
+                    "'{}' aggregates but has no TIMESERIES, did you mean to add TIMESERIES?",
+                    query
+                ));
+                // Keep it in the history so it can be fixed rather than retyped
+                self.query_history.push_back(query);
+                return;
+            }
+            self.last_log_query = Some(query.clone());
+        }
+
+        // Still sent, as the account may keep data for longer than configured
+        if let Some(warning) = retention_warning(&query, self.config.retention_days, Utc::now()) {
+            self.error = Some(warning);
+        }
+
+        let key = query_type.key();
+        self.cancelled.remove(&key);
+        self.in_flight.push((key, query.clone()));
+
+        self.query_history.push_back(query.clone());
+        _ = self.ui_tx.send(UIEvent::AddQuery(query));
+    }
+
+    // Keeps the current settings, and says why, if config.yaml can't be applied
+    fn reload_config(&mut self) {
+        if let Err(e) = self.config.reload() {
+            self.error = Some(format!("Config not reloaded: {}", e));
+            self.set_focus(UIFocus {
+                panel: Focus::NoResult,
+                ..self.focus
+            });
+        }
+    }
+
+    // Refreshes every active query now, outside the timer. Log queries are skipped as usual
+    fn refresh_all(&mut self) {
+        if self.datasets.is_empty() {
+            return;
+        }
+        self.refresh_requested = Some(Instant::now());
+        _ = self.ui_tx.send(UIEvent::RefreshData);
+    }
+
+    pub fn is_refreshing(&self) -> bool {
+        self.refresh_requested
+            .is_some_and(|requested| requested.elapsed() < REFRESH_INDICATOR)
+    }
+
+    // Aborts the most recently dispatched query that hasn't returned yet
+    fn cancel_query(&mut self) {
+        if let Some((key, query)) = self.in_flight.pop() {
+            self.cancelled.insert(key);
+            _ = self.ui_tx.send(UIEvent::CancelQuery(query));
+            self.set_focus(UIFocus {
+                loading: !self.in_flight.is_empty(),
+                ..self.focus
+            });
+        }
+    }
+
+    // Submits an edited query, dropping the original's dataset if the edit changed its key
+    fn replace_query(&mut self, original: String, query: String) {
+        let keys = split_statements(&query)
+            .iter()
+            .map(|statement| QueryType::from(statement.as_str()).key())
+            .collect::<Vec<String>>();
+
+        self.add_query(query);
+        if !keys.contains(&original) {
+            let hidden = self.hidden_facets.remove(&original);
+            if let Some(dataset) = self.datasets.remove(&original) {
+                // Keep the alias and hidden facets if the edited query is still charted
+                if let [key] = keys.as_slice() {
+                    if !matches!(QueryType::from(key.as_str()), QueryType::Log(_)) {
+                        if let Some(alias) = dataset.query_alias {
+                            self.rename_query(key.to_owned(), alias);
+                        }
+                        if let Some(hidden) = hidden {
+                            self.hidden_facets.insert(key.to_owned(), hidden);
+                        }
+                    }
+                }
+            }
+            self.list_state.select(None);
+            _ = self.ui_tx.send(UIEvent::DeleteQuery(original));
+        }
+    }
+
+    // Re-runs the selected query over a new window, or rewrites the one being typed
+    fn apply_window(&mut self, since: &str) -> Result<()> {
+        if self.datasets.selected().is_none() {
+            let nrql = self.inputs.get(Focus::QueryInput).to_nrql()?;
+            self.inputs
+                .set(Focus::QueryInput, nrql.with_window(since).to_string()?);
+            self.inputs.move_cursor_end(Focus::QueryInput);
+            return Ok(());
+        }
+
+        let original = self.datasets.selected.clone();
+        match QueryType::from(original.as_str()) {
+            QueryType::Timeseries(nrql) => {
+                let query = nrql.with_window(since).to_string()?;
+                self.replace_query(original, query);
+                Ok(())
+            }
+            _ => Err(anyhow!("Windows can only be applied to timeseries queries")),
+        }
+    }
+
+    // Copies the selected query into the input box so a variant can be submitted
+    fn duplicate_query(&mut self) {
+        if self.datasets.selected().is_none() {
+            return;
+        }
+
+        self.inputs
+            .set(Focus::QueryInput, self.datasets.selected.to_owned());
+        self.inputs.move_cursor_end(Focus::QueryInput);
+        self.set_focus(UIFocus {
+            panel: Focus::QueryInput,
+            input_mode: InputMode::Input,
+            ..self.focus
+        });
+    }
+
+    // Puts the query narrowed to the selected facet in the query box, to run alongside the original
+    fn open_facet_query(&mut self) {
+        let query = self.datasets.selected().and_then(|data| {
+            data.facet_query(data.selected_facet()?)
+                .and_then(|nrql| nrql.to_string().ok())
+        });
+        match query {
+            Some(query) => {
+                self.inputs.set(Focus::QueryInput, query);
+                self.inputs.move_cursor_end(Focus::QueryInput);
+                self.set_focus(UIFocus {
+                    panel: Focus::QueryInput,
+                    input_mode: InputMode::Input,
+                    ..self.focus
+                });
+            }
+            None => {
+                self.error = Some(String::from("This facet can't be queried on its own"));
+                self.set_focus(UIFocus {
+                    panel: Focus::NoResult,
+                    ..self.focus
+                });
+            }
+        }
+    }
+
+    // Queries the logs of the selected facet's trace, for queries faceted by trace id
+    fn open_trace_logs(&mut self) {
+        let trace_id = self.datasets.selected().and_then(|data| {
+            let nrql = data.nrql.as_ref()?;
+            if !is_trace_attribute(&nrql.facet) {
+                return None;
+            }
+            data.raw_facets.get(data.selected_facet()?).cloned()
+        });
+        match trace_id {
+            Some(trace_id) => {
+                self.add_query(trace_logs_query(&trace_id));
+                self.focus.tab = Tab::Logs;
+            }
+            None => {
+                self.error = Some(String::from("The selected facet isn't a trace id"));
+                self.set_focus(UIFocus {
+                    panel: Focus::NoResult,
+                    ..self.focus
+                });
+            }
+        }
+    }
+
+    fn pin_colour(&mut self) {
+        if let Err(e) = self.cycle_pinned_colour() {
+            self.error = Some(e.to_string());
+            self.set_focus(UIFocus {
+                panel: Focus::NoResult,
+                ..self.focus
+            });
+        }
+    }
+
+    // Pins the selected facet to the next colour in PIN_COLOURS, unpinning after the last
+    fn cycle_pinned_colour(&mut self) -> Result<()> {
+        let facet = match self.datasets.selected().and_then(|d| d.selected_facet()) {
+            Some(facet) => facet.to_owned(),
+            None => return Ok(()),
+        };
+
+        let next = match self.pinned_colours.get(&facet) {
+            Some(colour) => PIN_COLOURS
+                .iter()
+                .position(|c| c == colour)
+                .and_then(|i| PIN_COLOURS.get(i + 1)),
+            None => PIN_COLOURS.first(),
+        };
+
+        match next {
+            Some(colour) => _ = self.pinned_colours.insert(facet, *colour),
+            None => _ = self.pinned_colours.remove(&facet),
+        }
+
+        save_pins(
+            &self.config.config_dir.join(PINS_FILE),
+            &self.pinned_colours,
+        )
+    }
+
+    // Asks to save the session first, unless following where there's nothing to save
+    fn begin_quit(&mut self) {
+        self.quit_return = self.focus.panel;
+        let panel = match self.following {
+            Some(_) => Focus::QuitConfirm,
+            None => Focus::SessionSave,
+        };
+        self.set_focus(UIFocus {
+            panel,
+            input_mode: InputMode::Input,
+            ..self.focus
+        });
+    }
+
+    // Confirm before quitting without saving
+    fn decline_save(&mut self) {
+        self.inputs.clear(Focus::SessionSave);
+        self.inputs.reset_cursor(Focus::SessionSave);
+        self.set_focus(UIFocus {
+            panel: Focus::QuitConfirm,
+            ..self.focus
+        });
+    }
+
+    // Back to wherever `q` was pressed
+    fn cancel_quit(&mut self) {
+        self.inputs.clear(Focus::QuitConfirm);
+        self.inputs.reset_cursor(Focus::QuitConfirm);
+        self.set_focus(UIFocus {
+            panel: self.quit_return,
+            input_mode: InputMode::Normal,
+            ..self.focus
+        });
     }
 
     pub fn set_focus(&mut self, focus: UIFocus) {
@@ -431,16 +1786,62 @@ impl App {
     }
 
     pub fn delete_query(&mut self) {
-        let i = self.list_state.selected().unwrap();
+        let (i, removed) = match self
+            .list_state
+            .selected()
+            .and_then(|i| Some((i, self.datasets.remove_entry(i)?)))
+        {
+            Some(deleted) => deleted,
+            None => return,
+        };
+        // The next query takes the deleted one's place, or the previous one if it was last
+        let i = self.datasets.select(i);
+        self.list_state.select(i);
 
-        let removed = self.datasets.remove_entry(i);
+        self.hidden_facets.remove(&removed);
         // TODO: Fix deleted queries reappearing on new data!
         _ = self.ui_tx.send(UIEvent::DeleteQuery(removed));
+        self.prune_facet_colours();
+    }
+
+    // Hides the selected facet of the selected query, or shows it again
+    fn toggle_hidden_facet(&mut self) {
+        let facet = match self.datasets.selected().and_then(|d| d.selected_facet()) {
+            Some(facet) => facet.to_owned(),
+            None => return,
+        };
+        let hidden = self
+            .hidden_facets
+            .entry(self.datasets.selected.clone())
+            .or_default();
+        if !hidden.remove(&facet) {
+            hidden.insert(facet);
+        }
+        if hidden.is_empty() {
+            self.hidden_facets.remove(&self.datasets.selected);
+        }
+    }
+
+    pub fn is_hidden(&self, query: &str, facet: &str) -> bool {
+        self.hidden_facets
+            .get(query)
+            .is_some_and(|hidden| hidden.contains(facet))
+    }
+
+    // Drops colours for facets that no longer appear in any dataset
+    fn prune_facet_colours(&mut self) {
+        let active = self
+            .datasets
+            .iter()
+            .flat_map(|(_, data)| data.facets.keys())
+            .cloned()
+            .collect::<HashSet<String>>();
+        self.facet_colours.retain(|facet, _| active.contains(facet));
     }
 
     pub fn next(&mut self) {
         match self.focus.tab {
-            Tab::Graph => {
+            Tab::Graph | Tab::Summary => {
                 if self.datasets.is_empty() {
                     return;
                 }
@@ -456,18 +1857,20 @@ impl App {
                     None => 0,
                 };
 
-                self.list_state.select(Some(i));
-                self.datasets.select(i);
+                // Clamped, in case the list shrank since the index was read
+                let i = self.datasets.select(i);
+                self.list_state.select(i);
             }
             Tab::Logs => match self.focus.panel {
                 Focus::Log => {
-                    if self.logs.logs.is_empty() {
+                    let len = self.logs.selected_lines().len();
+                    if len == 0 {
                         return;
                     }
 
                     let i = match self.logs.log_item_list_state.selected() {
                         Some(i) => {
-                            if i >= self.logs.selected().unwrap().len() - 1 {
+                            if i >= len - 1 {
                                 0
                             } else {
                                 i + 1
@@ -495,8 +1898,8 @@ impl App {
                         None => 0,
                     };
 
-                    self.logs.log_list_state.select(Some(i));
-                    self.logs.select(i);
+                    let i = self.logs.select(i);
+                    self.logs.log_list_state.select(i);
                 }
             },
         }
@@ -504,7 +1907,7 @@ impl App {
 
     pub fn previous(&mut self) {
         match self.focus.tab {
-            Tab::Graph => {
+            Tab::Graph | Tab::Summary => {
                 if self.datasets.is_empty() {
                     return;
                 }
@@ -519,19 +1922,21 @@ impl App {
                     }
                     None => 0,
                 };
-                self.list_state.select(Some(i));
-                self.datasets.select(i);
+                // Clamped, in case the list shrank since the index was read
+                let i = self.datasets.select(i);
+                self.list_state.select(i);
             }
             Tab::Logs => match self.focus.panel {
                 Focus::Log => {
-                    if self.logs.logs.is_empty() {
+                    let len = self.logs.selected_lines().len();
+                    if len == 0 {
                         return;
                     }
 
                     let i = match self.logs.log_item_list_state.selected() {
                         Some(i) => {
                             if i == 0 {
-                                self.logs.selected().unwrap().len() - 1
+                                len - 1
                             } else {
                                 i - 1
                             }
@@ -556,51 +1961,29 @@ impl App {
                         }
                         None => 0,
                     };
-                    self.logs.log_list_state.select(Some(i));
-                    self.logs.select(i);
+                    let i = self.logs.select(i);
+                    self.logs.log_list_state.select(i);
                 }
             },
         }
     }
 
-    pub fn load_session(&mut self) {
-        let session_path = self.config.session.session_path.clone();
-        let yaml = fs::read_to_string(session_path).expect("ERROR: Could not read session file!");
-        let session_queries: Vec<String> =
-            serde_yaml::from_str(&yaml).expect("ERROR: Could not deserialize session file!");
-
-        self.query_history = VecDeque::from(session_queries);
+    // A corrupt or unreadable session is reported and the app starts with an empty one
+    pub fn load_session(&mut self) -> Result<()> {
         self.config.session.is_loaded = true;
-    }
-    // pub fn load_session(&mut self) {
-    //     let session_path = self.config.session.session_path.clone();
-    //     let yaml = fs::read_to_string(session_path).expect("ERROR: Could not read session file!");
-    //     let session_queries: Option<BTreeMap<String, String>> =
-    //         serde_yaml::from_str(&yaml).expect("ERROR: Could not deserialize session file!");
-
-    //     dbg!(&session_queries);
-
-    //     if let Some(queries) = session_queries {
-    //         let iter = queries.into_iter();
-    //         for (alias, query) in iter {
-    //             // TODO: Avoid this
-    //             let clean_query = query.replace("as value", "");
-    //             if let Ok(parsed_query) = clean_query.trim().to_nrql() {
-    //                 // TODO: Handle Log session
-    //                 self.add_query(query);
-    //                 self.rename_query(parsed_query.to_string().unwrap(), alias);
-    //             }
-    //         }
-    //     }
-
-    //     self.config.session.is_loaded = true;
-    // }
+        let session = SessionFile::read(&self.config.session.session_path)
+            .map_err(|e| anyhow!("Could not load session: {}", e))?;
 
-    pub fn save_session(&self) {
-        let mut out = String::new();
+        self.query_history = VecDeque::from(session.history);
+        self.log_filters = session.log_filters;
+        self.hidden_facets = session.hidden_facets;
 
-        let timeseries_queries = self
-            .datasets
+        Ok(())
+    }
+
+    // Active timeseries queries keyed by their alias (or the query itself)
+    fn active_queries(&self) -> BTreeMap<String, String> {
+        self.datasets
             .iter()
             .map(|(q, data)| {
                 (
@@ -608,19 +1991,135 @@ impl App {
                     q.to_owned(),
                 )
             })
-            .collect::<BTreeMap<String, String>>();
+            .collect::<BTreeMap<String, String>>()
+    }
+
+    pub fn dashboards_dir(&self) -> PathBuf {
+        self.config.config_dir.join("dashboards")
+    }
+
+    // Names of the saved dashboards, without their extension
+    pub fn dashboard_names(&self) -> Vec<String> {
+        let mut names = fs::read_dir(self.dashboards_dir())
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| {
+                        entry
+                            .path()
+                            .file_stem()
+                            .map(|stem| stem.to_string_lossy().into_owned())
+                    })
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default();
+        names.sort();
+
+        names
+    }
+
+    // A dashboard's file, refusing names that would reach outside the dashboards directory
+    fn dashboard_path(&self, name: &str) -> Result<PathBuf> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow!("Dashboard name cannot be empty!"));
+        }
+        if name.contains(['/', '\\']) || name.contains("..") || Path::new(name).is_absolute() {
+            return Err(anyhow!(
+                "Dashboard name '{}' cannot contain '/', '\\' or '..'!",
+                name
+            ));
+        }
+
+        Ok(self.dashboards_dir().join(format!("{}.yaml", name)))
+    }
+
+    pub fn save_dashboard(&self, name: &str) -> Result<()> {
+        let path = self.dashboard_path(name)?;
+        fs::create_dir_all(self.dashboards_dir())?;
+        let yaml = serde_yaml::to_string(&self.active_queries())?;
+        fs::write(path, yaml)?;
+
+        Ok(())
+    }
+
+    // Replaces the active queries with those of a saved dashboard
+    pub fn load_dashboard(&mut self, name: &str) -> Result<()> {
+        let path = self.dashboard_path(name)?;
+        let yaml = fs::read_to_string(path)
+            .map_err(|_| anyhow!("Could not read dashboard '{}'!", name.trim()))?;
+        let queries: BTreeMap<String, String> = serde_yaml::from_str(&yaml)?;
+        self.set_queries(queries);
+
+        Ok(())
+    }
+
+    // Replaces the active queries, keyed by alias
+    fn set_queries(&mut self, queries: BTreeMap<String, String>) {
+        while let Some(removed) = self.datasets.remove_entry(0) {
+            _ = self.ui_tx.send(UIEvent::DeleteQuery(removed));
+        }
+        self.list_state.select(None);
+        self.datasets.selected.clear();
+
+        for (alias, query) in queries {
+            self.add_query(query.to_owned());
+            if alias != query {
+                self.rename_query(query, alias);
+            }
+        }
+    }
 
-        if !timeseries_queries.is_empty() {
-            let yaml: String = serde_yaml::to_string(&timeseries_queries)
-                .expect("ERROR: Could not serialize queries!");
+    // Mirrors the session file at `path` without ever writing to it
+    pub fn follow(&mut self, path: PathBuf) {
+        self.following = Some(path);
+        self.recovery = None;
+        self.config.session.is_loaded = true;
+        self.follow_session();
+    }
+
+    // Re-reads the followed session, keeping what's shown if it can't be read
+    fn follow_session(&mut self) {
+        let path = match &self.following {
+            Some(path) => path,
+            None => return,
+        };
+        let session = match SessionFile::read(path) {
+            Ok(session) => session,
+            Err(e) => {
+                warn!(
+                    "Could not reload followed session {}: {}",
+                    path.display(),
+                    e
+                );
+                return;
+            }
+        };
 
-            out += &yaml;
+        // Unchanged queries keep their data rather than being fetched again
+        if session.queries != self.active_queries() {
+            self.set_queries(session.queries);
         }
+        self.query_history = VecDeque::from(session.history);
+        self.log_filters = session.log_filters;
+        self.hidden_facets = session.hidden_facets;
+    }
 
-        let log_queries = serde_yaml::to_string(&self.query_history)
-            .expect("ERROR: Could not serialize query history!");
+    fn snapshot(&self) -> SessionFile {
+        SessionFile {
+            queries: self.active_queries(),
+            history: self.query_history.iter().cloned().collect(),
+            log_filters: self.log_filters.clone(),
+            hidden_facets: self.hidden_facets.clone(),
+        }
+    }
 
-        out += &log_queries;
+    pub fn save_session(&self) {
+        if self.following.is_some() {
+            return;
+        }
+        let out =
+            serde_yaml::to_string(&self.snapshot()).expect("ERROR: Could not serialize session!");
 
         let session_path = self.config.session.session_path.clone();
         let mut file = OpenOptions::new()
@@ -634,24 +2133,53 @@ impl App {
             .expect("ERROR: Could not write to session file!");
     }
 
-    fn previous_tab(&mut self) {
-        match self.focus.tab {
-            Tab::Graph => self.focus.tab = Tab::Logs,
-            // Tab::Logs => self.focus.tab = Tab::Graph,
-            Tab::Logs => self.focus.tab = Tab::Logs,
+    // Snapshots the queries and filters, separately from the explicit session
+    fn write_recovery(&mut self) {
+        self.last_snapshot = Instant::now();
+        // Don't overwrite a snapshot that hasn't been restored or discarded yet, nor snapshot a follower
+        if self.recovery.is_some() || self.following.is_some() {
+            return;
+        }
+        let snapshot = self.snapshot();
+        if snapshot.is_empty() {
+            return;
+        }
+        if let Err(e) = snapshot.write(&self.config.config_dir.join(RECOVERY_FILE)) {
+            warn!("Could not write recovery snapshot: {}", e);
         }
     }
 
-    fn next_tab(&mut self) {
-        // TODO: Handle n tabs
-        match self.focus.tab {
-            Tab::Graph => self.focus.tab = Tab::Logs,
-            // Tab::Logs => self.focus.tab = Tab::Graph,
-            Tab::Logs => self.focus.tab = Tab::Logs,
+    fn clear_recovery(&self) {
+        _ = fs::remove_file(self.config.config_dir.join(RECOVERY_FILE));
+    }
+
+    // Re-submits the snapshot's queries, restoring their aliases, history and filters
+    fn restore(&mut self, recovery: SessionFile) {
+        for (alias, query) in recovery.queries {
+            self.add_query(query.to_owned());
+            if alias != query {
+                self.rename_query(query, alias);
+            }
         }
+        self.query_history = VecDeque::from(recovery.history);
+        self.log_filters = recovery.log_filters;
+        self.hidden_facets = recovery.hidden_facets;
+        self.config.session.is_loaded = true;
+        self.clear_recovery();
+    }
+
+    fn previous_tab(&mut self) {
+        let i = self.focus.tab as usize;
+        self.focus.tab = TABS[(i + TABS.len() - 1) % TABS.len()];
+    }
+
+    fn next_tab(&mut self) {
+        let i = self.focus.tab as usize;
+        self.focus.tab = TABS[(i + 1) % TABS.len()];
     }
 
     fn clear_filters(&mut self) {
+        self.log_filters.remove(&self.logs.query);
         self.logs.filters.clear();
         self.add_query(
             self.query_history
@@ -661,3 +2189,763 @@ impl App {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{demo::DEMO_QUERIES, tests::test_app};
+    use ratatui::{backend::TestBackend, buffer::Buffer};
+
+    // A log query, whose key is the query as written
+    const LOG_QUERY: &str = "SELECT * FROM Log WHERE level = 'ERROR'";
+
+    // Charts a query as though its results had arrived
+    fn chart(app: &mut App, query: &str) {
+        app.handle_payload(crate::demo::payload(query).unwrap());
+    }
+
+    #[test]
+    fn failed_reload_is_shown() {
+        let (mut app, _, _) = test_app("app-reload-invalid");
+        fs::write(app.config.config_dir.join("config.yaml"), "theme: 99\n").unwrap();
+
+        app.reload_config();
+
+        assert!(app
+            .error
+            .as_deref()
+            .unwrap()
+            .starts_with("Config not reloaded"));
+        assert!(app.focus.panel == Focus::NoResult);
+    }
+
+    #[test]
+    fn dashboard_round_trip() {
+        let (mut app, _, _) = test_app("dashboard-round-trip");
+        chart(&mut app, DEMO_QUERIES[0]);
+        let key = QueryType::from(DEMO_QUERIES[0]).key();
+        app.rename_query(key.clone(), String::from("throughput"));
+
+        app.save_dashboard(" overview ").unwrap();
+        assert_eq!(app.dashboard_names(), vec!["overview"]);
+
+        app.set_queries(BTreeMap::new());
+        assert!(app.datasets.is_empty());
+        app.load_dashboard("overview").unwrap();
+
+        let queries = app.active_queries();
+        assert_eq!(queries.get("throughput"), Some(&key));
+    }
+
+    #[test]
+    fn dashboard_names_cannot_leave_the_dashboards_directory() {
+        let (mut app, _, _) = test_app("dashboard-traversal");
+
+        for name in ["../../foo", "a/b", "a\\b", "..", "/tmp/foo", ""] {
+            assert!(app.save_dashboard(name).is_err(), "saved '{}'", name);
+            assert!(app.load_dashboard(name).is_err(), "loaded '{}'", name);
+        }
+        assert!(!app.config.config_dir.join("foo.yaml").exists());
+    }
+
+    #[test]
+    fn pinned_colour_takes_precedence() {
+        let (mut app, _, _) = test_app("pin-precedence");
+        chart(&mut app, DEMO_QUERIES[0]);
+        let i = app.datasets.select(0);
+        app.list_state.select(i);
+        let facet = app
+            .datasets
+            .selected()
+            .and_then(|data| data.selected_facet())
+            .unwrap()
+            .to_owned();
+        app.facet_colours.insert(facet.clone(), Color::Red);
+
+        app.pin_colour();
+
+        assert_eq!(facet_colour(&app, &facet), PIN_COLOURS[0]);
+        assert!(app.error.is_none());
+    }
+
+    #[test]
+    fn failed_pin_is_shown() {
+        let (mut app, _, _) = test_app("pin-failure");
+        chart(&mut app, DEMO_QUERIES[0]);
+        let i = app.datasets.select(0);
+        app.list_state.select(i);
+        // Pins can't be saved under a file
+        let file = app.config.config_dir.join("not-a-directory");
+        fs::write(&file, "").unwrap();
+        app.config.config_dir = file;
+
+        app.pin_colour();
+
+        assert!(app.error.is_some());
+        assert!(app.focus.panel == Focus::NoResult);
+    }
+
+    fn has_data(app: &App, key: &str) -> bool {
+        matches!(app.datasets.datasets.get(key), Some(data) if data.has_data)
+    }
+
+    #[test]
+    fn late_payload_of_cancelled_query_is_ignored() {
+        let (mut app, _, ui_rx) = test_app("cancel-late-payload");
+        let key = QueryType::from(DEMO_QUERIES[0]).key();
+        app.add_query(DEMO_QUERIES[0].to_owned());
+
+        app.cancel_query();
+        assert!(matches!(
+            ui_rx.try_iter().last(),
+            Some(UIEvent::CancelQuery(query)) if query == DEMO_QUERIES[0]
+        ));
+        app.receive(crate::demo::payload(DEMO_QUERIES[0]).unwrap());
+
+        assert!(!has_data(&app, &key));
+        assert!(app.cancelled.is_empty());
+    }
+
+    #[test]
+    fn failed_and_empty_queries_are_no_longer_in_flight() {
+        let (mut app, _, _) = test_app("cancel-after-failure");
+        let key = QueryType::from(DEMO_QUERIES[0]).key();
+
+        app.add_query(DEMO_QUERIES[0].to_owned());
+        app.receive(PayloadType::Error {
+            query: Some(key.clone()),
+            message: String::from("timed out"),
+        });
+        assert!(app.in_flight.is_empty());
+
+        app.add_query(LOG_QUERY.to_owned());
+        app.receive(PayloadType::None(LOG_QUERY.to_owned()));
+        assert!(app.in_flight.is_empty());
+
+        // Nothing is left to cancel, so the next result is still charted
+        app.cancel_query();
+        assert!(app.cancelled.is_empty());
+        app.receive(crate::demo::payload(DEMO_QUERIES[0]).unwrap());
+        assert!(has_data(&app, &key));
+    }
+
+    // The whole screen and how many events were sent, which any effect of a key press changes
+    fn observe(app: &mut App, ui_rx: &CrossBeamReceiver<UIEvent>) -> (Buffer, usize) {
+        let mut terminal = Terminal::new(TestBackend::new(120, 40)).unwrap();
+        terminal.draw(|frame| ui(app, frame)).unwrap();
+        (
+            terminal.backend().buffer().clone(),
+            ui_rx.try_iter().count(),
+        )
+    }
+
+    #[test]
+    fn every_bound_key_has_help() {
+        let mut documented = HashSet::new();
+        for (_, keys, _) in KEYBINDINGS {
+            for key in keys.split(" / ").map(|key| key.replace("Space", " ")) {
+                match key.chars().collect::<Vec<char>>()[..] {
+                    [c] => _ = documented.insert(c),
+                    [first, '-', last] => documented.extend(first..=last),
+                    // Named keys like Enter or Shift+Tab
+                    _ => {}
+                }
+            }
+        }
+
+        let (mut app, _, ui_rx) = test_app("help-coverage");
+        chart(&mut app, DEMO_QUERIES[0]);
+        app.datasets.select(0);
+        // Pinned so the graph's axis doesn't move with the clock
+        let dataset = app.datasets.selected_mut().unwrap();
+        dataset.window = Some((dataset.bounds.mins.0, dataset.bounds.maxes.0));
+        app.handle_payload(log_payload(&[(
+            "1700000000000",
+            "{\n\"message\": \"ok\"\n}",
+        )]));
+        app.logs.log_item_list_state.select(Some(1));
+
+        // Every undocumented character key leaves each Normal mode view as it was
+        for (tab, panel) in [
+            (Tab::Graph, Focus::Default),
+            (Tab::Logs, Focus::Default),
+            (Tab::Logs, Focus::Log),
+            (Tab::Logs, Focus::LogDetail),
+            (Tab::Summary, Focus::Default),
+        ] {
+            app.set_focus(UIFocus {
+                tab,
+                panel,
+                input_mode: InputMode::Normal,
+                ..app.focus
+            });
+            let before = observe(&mut app, &ui_rx).0;
+            for c in (' '..='~').filter(|c| !documented.contains(c)) {
+                press(&mut app, KeyCode::Char(c));
+                assert!(
+                    observe(&mut app, &ui_rx) == (before.clone(), 0),
+                    "'{}' is bound without help",
+                    c
+                );
+            }
+        }
+
+        // Whereas a documented key is seen to do something
+        let before = observe(&mut app, &ui_rx);
+        press(&mut app, KeyCode::Char('?'));
+        assert!(observe(&mut app, &ui_rx) != before);
+    }
+
+    #[tokio::test]
+    async fn full_channel_holds_up_the_backend_until_drained() {
+        let (mut app, data_tx, _) = test_app("backpressure");
+        let keyset = || PayloadType::Keyset(vec![String::from("appName")]);
+        for _ in 0..app.config.channel_capacity {
+            data_tx.try_send(keyset()).unwrap();
+        }
+        assert!(matches!(
+            data_tx.try_send(keyset()),
+            Err(tokio::sync::mpsc::error::TrySendError::Full(_))
+        ));
+
+        // A send waits for room rather than dropping or growing the queue
+        let sender = tokio::spawn(async move { data_tx.send(keyset()).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!sender.is_finished());
+
+        // Everything ready is handled in one go, freeing room for the waiting send
+        assert_eq!(app.drain_payloads(), app.config.channel_capacity);
+        sender.await.unwrap().unwrap();
+        assert_eq!(app.drain_payloads(), 1);
+    }
+
+    #[test]
+    fn filter_survives_a_refresh_of_the_same_query() {
+        let (mut app, _, _) = test_app("filter-refresh");
+        let query = DEMO_QUERIES[1];
+        app.receive(crate::demo::payload(query).unwrap());
+        app.add_filter(String::from("Error"));
+        let filtered = app.logs.logs.len();
+        assert!(filtered > 0);
+
+        app.receive(crate::demo::payload(query).unwrap());
+
+        assert!(app.logs.filters.contains("Error"));
+        assert_eq!(app.logs.logs.len(), filtered);
+        assert!(app
+            .logs
+            .logs
+            .values()
+            .all(|lines| lines.iter().any(|line| line.contains("Error"))));
+    }
+
+    #[test]
+    fn corrupt_session_starts_empty() {
+        let (mut app, _, _) = test_app("session-corrupt");
+        fs::write(&app.config.session.session_path, "history: [unclosed").unwrap();
+
+        assert!(app.load_session().is_err());
+        assert!(app.config.session.is_loaded);
+        assert!(app.query_history.is_empty());
+        assert!(app.log_filters.is_empty());
+    }
+
+    #[test]
+    fn session_restores_history_and_filters() {
+        let (mut app, _, _) = test_app("session-load");
+        let session = SessionFile {
+            history: vec![String::from(LOG_QUERY)],
+            log_filters: BTreeMap::from([(
+                String::from(LOG_QUERY),
+                BTreeSet::from([String::from("timeout")]),
+            )]),
+            ..SessionFile::default()
+        };
+        session.write(&app.config.session.session_path).unwrap();
+
+        app.load_session().unwrap();
+
+        assert_eq!(app.query_history, vec![LOG_QUERY]);
+        assert!(app.log_filters[LOG_QUERY].contains("timeout"));
+    }
+
+    #[test]
+    fn duplicating_a_query_leaves_the_original() {
+        let (mut app, _, _) = test_app("duplicate");
+        let key = QueryType::from(DEMO_QUERIES[0]).key();
+        chart(&mut app, DEMO_QUERIES[0]);
+        app.datasets.select(0);
+
+        app.duplicate_query();
+
+        assert_eq!(app.inputs.get(Focus::QueryInput), key);
+        assert!(app.focus.panel == Focus::QueryInput);
+        assert!(has_data(&app, &key));
+    }
+
+    // Queries sent to the backend so far, in order
+    fn dispatched(ui_rx: &CrossBeamReceiver<UIEvent>) -> Vec<String> {
+        ui_rx
+            .try_iter()
+            .filter_map(|event| match event {
+                UIEvent::AddQuery(query) => Some(query),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_submissions_are_dispatched_once() {
+        let (mut app, _, ui_rx) = test_app("debounce");
+        for _ in 0..3 {
+            app.add_query(LOG_QUERY.to_owned());
+        }
+        assert_eq!(dispatched(&ui_rx), vec![LOG_QUERY]);
+
+        // A different query isn't held back
+        app.add_query(DEMO_QUERIES[1].to_owned());
+        assert_eq!(dispatched(&ui_rx), vec![DEMO_QUERIES[1]]);
+    }
+
+    #[test]
+    fn next_then_previous_tab_returns_to_the_start() {
+        let (mut app, _, _) = test_app("tabs");
+        for tab in TABS {
+            app.focus.tab = tab;
+            app.next_tab();
+            assert!(app.focus.tab != tab);
+            app.previous_tab();
+            assert!(app.focus.tab == tab);
+        }
+
+        // Wraps around in both directions
+        app.focus.tab = Tab::Graph;
+        app.previous_tab();
+        assert!(app.focus.tab == TABS[TABS.len() - 1]);
+        app.next_tab();
+        assert!(app.focus.tab == Tab::Graph);
+    }
+
+    #[test]
+    fn drill_down_filters_on_the_detail_field() {
+        // Lines of a pretty-printed log, as shown in the detail panel
+        assert_eq!(
+            drill_down_query(r#"  "hostname": "api-1","#).unwrap(),
+            "SELECT * FROM Log WHERE `hostname` = 'api-1'"
+        );
+        assert_eq!(
+            drill_down_query(r#"  "status": 503,"#).unwrap(),
+            "SELECT * FROM Log WHERE `status` = '503'"
+        );
+        assert_eq!(
+            drill_down_query(r#"  "message": "it's down""#).unwrap(),
+            "SELECT * FROM Log WHERE `message` = 'it\\'s down'"
+        );
+        // Nested values and braces have no single value to filter on
+        assert!(drill_down_query(r#"  "context": {"#).is_none());
+        assert!(drill_down_query("}").is_none());
+    }
+
+    #[test]
+    fn batch_of_two_queries_is_two_dispatches() {
+        let (mut app, _, ui_rx) = test_app("batch");
+        app.add_query(format!(
+            "{}; SELECT * FROM Log WHERE message = 'a;b';",
+            DEMO_QUERIES[1]
+        ));
+
+        assert_eq!(
+            dispatched(&ui_rx),
+            vec![DEMO_QUERIES[1], "SELECT * FROM Log WHERE message = 'a;b'"]
+        );
+    }
+
+    #[test]
+    fn facet_gone_from_every_dataset_loses_its_colour() {
+        let (mut app, _, _) = test_app("prune-colours");
+        chart(&mut app, DEMO_QUERIES[0]);
+        assert!(app.facet_colours.contains_key("search"));
+
+        let mut refresh = match crate::demo::payload(DEMO_QUERIES[0]).unwrap() {
+            PayloadType::Timeseries(payload) => payload,
+            _ => unreachable!(),
+        };
+        refresh.data.remove("search");
+        app.handle_payload(PayloadType::Timeseries(refresh));
+
+        assert!(!app.facet_colours.contains_key("search"));
+        assert!(app.facet_colours.contains_key("checkout"));
+    }
+
+    #[test]
+    fn bundle_round_trip_reproduces_queries_and_data() {
+        let (mut app, _, _) = test_app("bundle-export");
+        let key = QueryType::from(DEMO_QUERIES[0]).key();
+        for query in DEMO_QUERIES {
+            app.query_history.push_back(query.to_owned());
+            chart(&mut app, query);
+        }
+        app.rename_query(key.clone(), String::from("throughput"));
+
+        let path = app.export_bundle().unwrap();
+        let (mut imported, _, _) = test_app("bundle-import");
+        imported.import_bundle(Bundle::read(&path).unwrap());
+
+        assert_eq!(imported.query_history, app.query_history);
+        assert_eq!(
+            imported.datasets.iter().map(|(k, _)| k).collect::<Vec<_>>(),
+            app.datasets.iter().map(|(k, _)| k).collect::<Vec<_>>()
+        );
+        let (exported, restored) = (
+            &app.datasets.datasets[&key],
+            &imported.datasets.datasets[&key],
+        );
+        // JSON can lose the last bit of a float, so points are compared approximately
+        assert!(restored.facets.keys().eq(exported.facets.keys()));
+        for (facet, points) in &restored.facets {
+            let exported = &exported.facets[facet];
+            assert_eq!(points.len(), exported.len());
+            assert!(points
+                .iter()
+                .zip(exported)
+                .all(|(a, b)| a.0 == b.0 && (a.1 - b.1).abs() < 1e-9));
+        }
+        assert_eq!(restored.query_alias.as_deref(), Some("throughput"));
+        assert_eq!(imported.logs.query, app.logs.query);
+        assert_eq!(imported.logs.logs, app.logs.logs);
+    }
+
+    // A refresh of `query` whose facets end on the given values
+    fn refresh_with(app: &mut App, query: &str, latest: &[(&str, f64)]) {
+        let data = latest
+            .iter()
+            .map(|(facet, value)| (facet.to_string(), vec![(0.0, 0.0), (60.0, *value)]))
+            .collect();
+        app.handle_payload(PayloadType::Timeseries(Payload {
+            query: query.to_owned(),
+            data,
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn delta_compares_against_the_previous_refresh() {
+        let (mut app, _, _) = test_app("summary-delta");
+        let key = QueryType::from(DEMO_QUERIES[0]).key();
+
+        refresh_with(&mut app, &key, &[("checkout", 3.0), ("search", 4.0)]);
+        assert_eq!(app.datasets.datasets[&key].latest_value(), Some(7.0));
+        assert_eq!(app.datasets.datasets[&key].delta(), None);
+
+        refresh_with(&mut app, &key, &[("checkout", 5.0), ("search", 1.0)]);
+        assert_eq!(app.datasets.datasets[&key].delta(), Some(-1.0));
+    }
+
+    #[test]
+    fn editing_a_timeseries_into_a_log_query_removes_its_graph() {
+        let (mut app, _, ui_rx) = test_app("edit-to-log");
+        let key = QueryType::from(DEMO_QUERIES[0]).key();
+        chart(&mut app, DEMO_QUERIES[0]);
+
+        app.replace_query(key.clone(), LOG_QUERY.to_owned());
+
+        assert!(!app.datasets.datasets.contains_key(&key));
+        let events = ui_rx.try_iter().collect::<Vec<UIEvent>>();
+        assert!(
+            matches!(&events[..], [UIEvent::AddQuery(added), UIEvent::DeleteQuery(deleted)]
+            if added == LOG_QUERY && *deleted == key)
+        );
+    }
+
+    #[test]
+    fn collected_queries_include_aliases_and_logs() {
+        let (mut app, _, _) = test_app("collect-queries");
+        chart(&mut app, DEMO_QUERIES[0]);
+        let key = QueryType::from(DEMO_QUERIES[0]).key();
+        app.rename_query(key.clone(), String::from("throughput"));
+        app.logs.query = String::from(LOG_QUERY);
+
+        let lines = [String::from("-- throughput"), key, String::from(LOG_QUERY)];
+        assert_eq!(app.collect_queries(), lines.join("\n"));
+    }
+
+    #[test]
+    fn declined_quit_returns_to_the_prior_focus() {
+        let (mut app, _, _) = test_app("quit-declined");
+        app.set_focus(UIFocus {
+            panel: Focus::Log,
+            ..app.focus
+        });
+
+        app.begin_quit();
+        assert!(app.focus.panel == Focus::SessionSave);
+        app.inputs.enter_char(Focus::SessionSave, 'n');
+
+        app.decline_save();
+        assert!(app.focus.panel == Focus::QuitConfirm);
+        assert!(app.focus.input_mode == InputMode::Input);
+        assert_eq!(app.inputs.get(Focus::SessionSave), "");
+        app.inputs.enter_char(Focus::QuitConfirm, 'n');
+
+        app.cancel_quit();
+        assert!(app.focus.panel == Focus::Log);
+        assert!(app.focus.input_mode == InputMode::Normal);
+        assert_eq!(app.inputs.get(Focus::QuitConfirm), "");
+    }
+
+    #[test]
+    fn following_quits_straight_to_the_confirmation() {
+        let (mut app, _, _) = test_app("quit-following");
+        app.following = Some(app.config.config_dir.join("session.yaml"));
+
+        app.begin_quit();
+
+        assert!(app.focus.panel == Focus::QuitConfirm);
+        assert!(app.focus.input_mode == InputMode::Input);
+    }
+
+    #[test]
+    fn query_detail_shows_the_full_query() {
+        let (mut app, _, _) = test_app("query-detail");
+        chart(&mut app, DEMO_QUERIES[0]);
+        let key = QueryType::from(DEMO_QUERIES[0]).key();
+        app.rename_query(key.clone(), String::from("throughput"));
+        app.datasets.select(0);
+
+        let detail = app.query_detail();
+        assert_eq!(detail[..2], [String::from("Query:"), key]);
+        assert!(detail.contains(&String::from("Alias: throughput")));
+        assert!(detail.contains(&String::from("  checkout")));
+
+        app.logs.query = String::from(LOG_QUERY);
+        app.focus.tab = Tab::Logs;
+        assert_eq!(
+            app.query_detail(),
+            vec![String::from("Query:"), String::from(LOG_QUERY)]
+        );
+    }
+
+    #[test]
+    fn recovery_snapshot_round_trip() {
+        let (mut app, _, _) = test_app("recovery-round-trip");
+        chart(&mut app, DEMO_QUERIES[0]);
+        let key = QueryType::from(DEMO_QUERIES[0]).key();
+        app.rename_query(key.clone(), String::from("throughput"));
+        app.log_filters.insert(
+            String::from(LOG_QUERY),
+            BTreeSet::from([String::from("timeout")]),
+        );
+        app.write_recovery();
+
+        // As if the app had crashed and been started again
+        let config_dir = app.config.config_dir.clone();
+        let (_data_tx, data_rx) = tokio::sync::mpsc::channel(1);
+        let (ui_tx, ui_rx) = crossbeam_channel::unbounded();
+        let mut app = App::new(
+            crate::Config::load_from(config_dir.clone(), true, None),
+            data_rx,
+            ui_tx,
+        );
+        let recovery = app.recovery.take().unwrap();
+        app.restore(recovery);
+
+        assert_eq!(dispatched(&ui_rx), vec![key.clone()]);
+        assert_eq!(app.active_queries().get("throughput"), Some(&key));
+        assert!(app.log_filters[LOG_QUERY].contains("timeout"));
+        assert!(!config_dir.join(RECOVERY_FILE).exists());
+    }
+
+    #[test]
+    fn manual_refresh_enqueues_a_refresh() {
+        let (mut app, _, ui_rx) = test_app("manual-refresh");
+        app.refresh_all();
+        assert!(ui_rx.try_recv().is_err());
+
+        chart(&mut app, DEMO_QUERIES[0]);
+        app.refresh_all();
+
+        assert!(matches!(ui_rx.try_recv(), Ok(UIEvent::RefreshData)));
+        assert!(app.is_refreshing());
+    }
+
+    #[test]
+    fn blank_input_is_not_dispatched() {
+        let (mut app, _, ui_rx) = test_app("blank-input");
+        app.inputs.set(Focus::QueryInput, String::from("  "));
+
+        assert!(app.submit_query().is_none());
+        assert!(dispatched(&ui_rx).is_empty());
+        assert!(!app.focus.loading);
+
+        app.inputs.set(Focus::QueryInput, String::from(LOG_QUERY));
+        assert!(app.submit_query() == Some(Focus::Default));
+        assert_eq!(dispatched(&ui_rx), vec![LOG_QUERY]);
+        assert!(app.focus.loading);
+    }
+
+    // Draws over a second of 50ms polls, `changed` saying whether a poll brought new input or data
+    fn draws_per_second(changed: impl Fn(usize) -> bool) -> usize {
+        let poll = Duration::from_millis(50);
+        let tick_rate = Duration::from_millis(250);
+        let (mut dirty, mut since_draw, mut draws) = (true, Duration::ZERO, 0);
+        for i in 0..20 {
+            if needs_draw(dirty, since_draw, tick_rate) {
+                draws += 1;
+                since_draw = Duration::ZERO;
+            }
+            since_draw += poll;
+            dirty = changed(i);
+        }
+        draws
+    }
+
+    #[test]
+    fn static_graph_is_redrawn_only_on_the_tick() {
+        assert_eq!(draws_per_second(|_| false), 4);
+        assert_eq!(draws_per_second(|i| i == 2), 5);
+        assert_eq!(draws_per_second(|_| true), 20);
+    }
+
+    #[test]
+    fn hidden_facets_persist_in_the_session() {
+        let (mut app, _, _) = test_app("hidden-facets-session");
+        chart(&mut app, DEMO_QUERIES[0]);
+        let key = QueryType::from(DEMO_QUERIES[0]).key();
+        app.datasets.select(0);
+        app.toggle_hidden_facet();
+        assert!(app.is_hidden(&key, "checkout"));
+        app.save_session();
+
+        app.hidden_facets.clear();
+        app.load_session().unwrap();
+
+        assert!(app.is_hidden(&key, "checkout"));
+        assert!(!app.is_hidden(&key, "payments"));
+    }
+
+    fn log_payload(logs: &[(&str, &str)]) -> PayloadType {
+        PayloadType::Log(LogPayload {
+            query: String::from(LOG_QUERY),
+            logs: logs
+                .iter()
+                .map(|(timestamp, log)| (timestamp.to_string(), log.to_string()))
+                .collect(),
+            chart_data: Default::default(),
+            bounds: Default::default(),
+            count: logs.len(),
+        })
+    }
+
+    #[test]
+    fn empty_log_bodies_are_skipped() {
+        let (mut app, _, _) = test_app("empty-log-body");
+
+        app.handle_payload(log_payload(&[
+            ("1700000000000", ""),
+            ("1700000060000", "  \n"),
+        ]));
+        assert!(app.logs.is_empty());
+        assert!(app.logs.selected().is_none());
+
+        app.handle_payload(log_payload(&[
+            ("1700000000000", ""),
+            ("1700000060000", "{\"message\": \"ok\"}"),
+        ]));
+        assert_eq!(app.logs.len(), 1);
+        assert_eq!(app.logs.selected, "1700000060000");
+        assert_eq!(app.logs.log_list_state.selected(), Some(0));
+        assert_eq!(app.logs.selected_lines(), vec!["{\"message\": \"ok\"}"]);
+    }
+
+    #[test]
+    fn one_hour_preset_rewrites_the_window() {
+        let (mut app, _, ui_rx) = test_app("window-preset");
+        let query = "FROM Transaction SELECT count(*) WHERE duration > 0 FACET appName \
+                     SINCE 3 days ago UNTIL 1 day ago LIMIT MAX TIMESERIES";
+        chart(&mut app, query);
+        app.datasets.select(0);
+
+        let (_, since) = WINDOW_PRESETS
+            .into_iter()
+            .find(|(name, _)| *name == "1h")
+            .unwrap();
+        app.apply_window(since).unwrap();
+
+        let dispatched = dispatched(&ui_rx);
+        let nrql = QueryType::from(dispatched[0].as_str());
+        assert!(matches!(nrql, QueryType::Timeseries(nrql)
+            if nrql.since == "1 hour ago" && nrql.until == "now"
+                && nrql.r#where == "duration > 0" && nrql.facet == "appName"));
+        assert!(dispatched[0].contains("SINCE 1 hour ago UNTIL now"));
+    }
+
+    #[test]
+    fn quoted_facet_displays_unquoted_but_queries_the_raw_value() {
+        let (mut app, _, _) = test_app("quoted-facet");
+        let nrql = "FROM Transaction SELECT count(*) WHERE duration > 0 FACET host \
+                    SINCE 1 hour ago UNTIL now LIMIT MAX TIMESERIES"
+            .to_nrql()
+            .unwrap();
+        let row = crate::query::TimeseriesResult {
+            begin_time_seconds: Some(0.0),
+            end_time_seconds: Some(60.0),
+            facet: Some(String::from(" \"prod\" ")),
+            value: Some(1.0),
+        };
+        app.handle_payload(PayloadType::Timeseries(crate::backend::parse_timeseries(
+            nrql,
+            vec![row],
+        )));
+        app.datasets.select(0);
+
+        let data = app.datasets.selected().unwrap();
+        assert_eq!(data.facets.keys().collect::<Vec<&String>>(), vec!["prod"]);
+        assert_eq!(
+            data.facet_query("prod").unwrap().r#where,
+            r#"(duration > 0) AND `host` = ' "prod" '"#
+        );
+    }
+
+    fn press(app: &mut App, code: KeyCode) {
+        app.handle_key(KeyEvent::from(code));
+    }
+
+    #[test]
+    fn follower_cannot_filter_or_drill_down() {
+        let (mut app, _, ui_rx) = test_app("follower-read-only");
+        app.handle_payload(log_payload(&[(
+            "1700000000000",
+            "{\n\"service\": \"api\"\n}",
+        )]));
+        app.log_filters.insert(
+            String::from(LOG_QUERY),
+            BTreeSet::from([String::from("api")]),
+        );
+        app.set_focus(UIFocus {
+            panel: Focus::LogDetail,
+            ..app.focus
+        });
+        app.logs.log_item_list_state.select(Some(1));
+        app.following = Some(app.config.config_dir.join("session.yaml"));
+
+        for code in [KeyCode::Char('F'), KeyCode::Char('C'), KeyCode::Enter] {
+            press(&mut app, code);
+            assert!(app.focus.panel == Focus::NoResult);
+            assert!(app.focus.input_mode == InputMode::Normal);
+            app.set_focus(UIFocus {
+                panel: Focus::LogDetail,
+                ..app.focus
+            });
+        }
+        assert!(dispatched(&ui_rx).is_empty());
+        assert_eq!(app.log_filters[LOG_QUERY].len(), 1);
+
+        // The same line drills down once the session is no longer followed
+        app.following = None;
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(
+            dispatched(&ui_rx),
+            vec![String::from("SELECT * FROM Log WHERE `service` = 'api'")]
+        );
+    }
+}
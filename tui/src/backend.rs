@@ -1,8 +1,14 @@
-use crate::query::{Timeseries, TimeseriesResult};
+use crate::parser::subquery_select;
+use crate::query::{
+    histogram_args, like_clause, rate_unit, QueryType, Timeseries, TimeseriesResult,
+};
 use anyhow::{Error, Result};
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
 
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     sync::mpsc::{channel, Receiver, Sender},
 };
 use tokio::runtime::{self, Runtime};
@@ -12,7 +18,7 @@ use crossbeam_channel::{unbounded, Receiver as MReceiver, Sender as MSender};
 use crate::client::NewRelicClient;
 use crate::query::NRQLQuery;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Bounds {
     pub mins: (f64, f64),
     pub maxes: (f64, f64),
@@ -31,26 +37,111 @@ impl Default for Bounds {
 pub enum PayloadType {
     Timeseries(Payload),
     Log(LogPayload),
-    None, // No data
+    Histogram(HistogramPayload),
+    Keyset(Vec<String>),
+    Applications(Vec<Application>),
+    Error {
+        // The query that failed, None for errors outside a query such as the schema check
+        query: Option<String>,
+        message: String,
+    },
+    SessionChanged, // The followed session file was saved
+    None(String),   // No data for the query
 }
 
-#[derive(Debug)]
+impl PayloadType {
+    // The dataset key the payload answers, if it carries one
+    pub fn query(&self) -> Option<&str> {
+        match self {
+            PayloadType::Timeseries(payload) => Some(&payload.query),
+            PayloadType::Log(payload) => Some(&payload.query),
+            PayloadType::Histogram(payload) => Some(&payload.query),
+            PayloadType::Error { query, .. } => query.as_deref(),
+            PayloadType::None(query) => Some(query),
+            PayloadType::Keyset(_) | PayloadType::Applications(_) | PayloadType::SessionChanged => {
+                None
+            }
+        }
+    }
+
+    // Files a payload fetched with a frozen window under the query as it was written
+    pub fn refiled(self, query: &str) -> Self {
+        match (self, QueryType::from(query)) {
+            (PayloadType::Timeseries(mut payload), QueryType::Timeseries(nrql)) => {
+                payload.query = nrql.to_string().unwrap();
+                payload.nrql = Some(nrql);
+                PayloadType::Timeseries(payload)
+            }
+            (payload, _) => payload,
+        }
+    }
+}
+
+// The query a refresh sends: pinned to the window it had when dispatched `at`, or as written
+pub fn frozen_query(query: &str, at: DateTime<Utc>) -> Option<String> {
+    match QueryType::from(query) {
+        QueryType::Timeseries(nrql) => nrql.frozen_at(at)?.to_string().ok(),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Application {
+    pub name: String,
+    pub guid: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistogramPayload {
+    pub query: String,
+    pub buckets: Vec<(String, u64)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct LogPayload {
+    pub query: String,
     pub logs: BTreeMap<String, String>,
     pub chart_data: ChartData,
     pub bounds: Bounds,
+    // Rows returned, before logs sharing a timestamp are merged
+    #[serde(default)]
+    pub count: usize,
 }
 
 #[derive(Debug)]
 pub struct Bins {}
 
-#[derive(Default, Debug)]
+// Series name for results without a FACET, which no facet New Relic returns can collide with
+pub const NO_FACET: &str = "\0value";
+
+// Facet values can arrive quoted or padded, e.g. `"prod"`, which would show in the legend
+pub fn normalize_facet(facet: &str) -> String {
+    match facet.trim().trim_matches('"').trim() {
+        "" => facet.to_owned(),
+        normalized => normalized.to_owned(),
+    }
+}
+
+// Name a facet is shown under
+pub fn facet_label(facet: &str) -> &str {
+    match facet {
+        NO_FACET => "value",
+        facet => facet,
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
 pub struct Payload {
     pub query: String,
-    pub facets: Vec<String>,
     pub data: BTreeMap<String, Vec<(f64, f64)>>,
     pub bounds: Bounds,
     pub selection: String,
+    pub window: Option<(f64, f64)>,
+    pub nrql: Option<NRQLQuery>,
+    pub rate_unit: Option<String>,
+    // Facet values as returned, by their normalized name
+    #[serde(default)]
+    pub raw_facets: BTreeMap<String, String>,
 }
 
 pub struct Backend {
@@ -66,6 +157,11 @@ pub enum UIEvent {
     RefreshData,
     AddQuery(String),
     DeleteQuery(String),
+    CancelQuery(String),
+    Keyset(String),
+    Applications(String),
+    // Whether refreshes keep each query's window from its first dispatch
+    FreezeWindows(bool),
 }
 
 impl Backend {
@@ -90,7 +186,7 @@ impl Backend {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChartData {
     pub info: Vec<(f64, f64)>,
     pub error: Vec<(f64, f64)>,
@@ -107,6 +203,63 @@ impl ChartData {
     }
 }
 
+impl ChartData {
+    // Counts each level's logs into `n` equal-width buckets, optionally on a log scale
+    pub fn bucketed(&self, min_x: f64, max_x: f64, n: usize, log_scale: bool) -> ChartData {
+        let scale = |points: &[(f64, f64)]| {
+            bucket_counts(points, min_x, max_x, n)
+                .into_iter()
+                .map(|(x, count)| {
+                    if log_scale {
+                        (x, f64::log10(1_f64 + count))
+                    } else {
+                        (x, count)
+                    }
+                })
+                .collect::<Vec<(f64, f64)>>()
+        };
+
+        ChartData {
+            info: scale(&self.info),
+            error: scale(&self.error),
+            debug: scale(&self.debug),
+        }
+    }
+
+    // Height of the tallest bar across all levels
+    pub fn y_bound(&self) -> f64 {
+        self.info
+            .iter()
+            .chain(&self.error)
+            .chain(&self.debug)
+            .map(|(_, y)| *y)
+            .fold(0_f64, f64::max)
+    }
+}
+
+// Counts points into `n` buckets between `min` and `max`, keyed by each bucket's start
+pub fn bucket_counts(points: &[(f64, f64)], min: f64, max: f64, n: usize) -> Vec<(f64, f64)> {
+    let n = n.max(1);
+    let width = (max - min) / n as f64;
+    let mut counts = vec![0_u64; n];
+
+    for (x, _) in points {
+        let i = if width > 0_f64 {
+            (((x - min) / width) as usize).min(n - 1)
+        } else {
+            0
+        };
+        counts[i] += 1;
+    }
+
+    counts
+        .into_iter()
+        .enumerate()
+        .filter(|(_, count)| *count > 0)
+        .map(|(i, count)| (min + i as f64 * width, count as f64))
+        .collect()
+}
+
 impl Default for ChartData {
     fn default() -> Self {
         Self::new()
@@ -114,15 +267,17 @@ impl Default for ChartData {
 }
 
 pub async fn query_log(query: String, client: NewRelicClient) -> Result<LogPayload, Error> {
-    let data: Vec<serde_json::Value> = client
-        .query::<serde_json::Value>(query)
-        .await
-        .unwrap_or_default();
+    let data: Vec<serde_json::Value> = client.query::<serde_json::Value>(&query).await?;
+
+    parse_logs(query, data)
+}
 
+pub fn parse_logs(query: String, data: Vec<serde_json::Value>) -> Result<LogPayload, Error> {
     let mut logs: BTreeMap<String, String> = BTreeMap::new();
     let mut chart_data = ChartData::default();
     let mut min_bounds: (f64, f64) = (f64::MAX, f64::MAX);
     let mut max_bounds: (f64, f64) = (0 as f64, 0 as f64);
+    let count = data.len();
 
     for log in data {
         let timestamp = log
@@ -156,7 +311,9 @@ pub async fn query_log(query: String, client: NewRelicClient) -> Result<LogPaylo
     }
 
     Ok(LogPayload {
+        query,
         logs,
+        count,
         chart_data,
         bounds: Bounds {
             mins: min_bounds,
@@ -165,11 +322,131 @@ pub async fn query_log(query: String, client: NewRelicClient) -> Result<LogPaylo
     })
 }
 
+// Builds a `col | col | col` row from the selected fields of a log
+pub fn project_columns(log: &serde_json::Value, columns: &[String]) -> String {
+    columns
+        .iter()
+        .map(|column| match log.get(column) {
+            Some(serde_json::Value::String(value)) => value.to_owned(),
+            Some(value) => value.to_string(),
+            None => String::from("-"),
+        })
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+// Flattens nested objects and arrays into dotted keys, e.g. `context.requestId` or `tags.0`
+pub fn flatten_json(value: &serde_json::Value, prefix: &str, out: &mut BTreeMap<String, String>) {
+    let key = |child: &str| match prefix {
+        "" => child.to_owned(),
+        _ => format!("{}.{}", prefix, child),
+    };
+    match value {
+        serde_json::Value::Object(map) => map
+            .iter()
+            .for_each(|(child, value)| flatten_json(value, &key(child), out)),
+        serde_json::Value::Array(values) => values
+            .iter()
+            .enumerate()
+            .for_each(|(i, value)| flatten_json(value, &key(&i.to_string()), out)),
+        serde_json::Value::String(s) => _ = out.insert(prefix.to_owned(), s.to_owned()),
+        serde_json::Value::Null => _ = out.insert(prefix.to_owned(), String::new()),
+        value => _ = out.insert(prefix.to_owned(), value.to_string()),
+    }
+}
+
+// One flattened row per log under a header of every key seen, missing keys left empty
+pub fn logs_to_csv(logs: &[serde_json::Value]) -> String {
+    let rows = logs
+        .iter()
+        .map(|log| {
+            let mut row = BTreeMap::new();
+            flatten_json(log, "", &mut row);
+            row
+        })
+        .collect::<Vec<BTreeMap<String, String>>>();
+    let header = rows
+        .iter()
+        .flat_map(|row| row.keys().cloned())
+        .collect::<BTreeSet<String>>();
+
+    let mut csv = csv_line(header.iter().map(String::as_str));
+    for row in &rows {
+        csv += &csv_line(
+            header
+                .iter()
+                .map(|key| row.get(key).map_or("", String::as_str)),
+        );
+    }
+    csv
+}
+
+fn csv_line<'a>(fields: impl Iterator<Item = &'a str>) -> String {
+    let fields = fields
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_owned()
+            }
+        })
+        .collect::<Vec<String>>();
+    format!("{}\n", fields.join(","))
+}
+
 pub async fn query_timeseries(query: NRQLQuery, client: NewRelicClient) -> Result<Payload, Error> {
     let data = client
         .query::<TimeseriesResult>(query.to_string().unwrap())
-        .await
-        .unwrap_or_default();
+        .await?;
+
+    Ok(parse_timeseries(query, data))
+}
+
+pub fn parse_timeseries(query: NRQLQuery, data: Vec<TimeseriesResult>) -> Payload {
+    // Pin the x-axis to an explicit window rather than the data's extent
+    let mut payload = timeseries_payload(query.to_string().unwrap(), data, query.epoch_window());
+    payload.selection = query.select.to_owned();
+    payload.rate_unit = rate_unit(&query.select);
+    payload.nrql = Some(query);
+
+    payload
+}
+
+pub async fn query_raw_timeseries(query: String, client: NewRelicClient) -> Result<Payload, Error> {
+    let data = client.query::<TimeseriesResult>(&query).await?;
+
+    Ok(parse_raw_timeseries(query, data))
+}
+
+// Subqueries are charted as returned, labelled with their outer selection
+pub fn parse_raw_timeseries(query: String, data: Vec<TimeseriesResult>) -> Payload {
+    let selection = subquery_select(&query).unwrap_or_default();
+    let mut payload = timeseries_payload(query, data, None);
+    payload.rate_unit = rate_unit(&selection);
+    payload.selection = selection;
+
+    payload
+}
+
+fn timeseries_payload(
+    query: String,
+    data: Vec<TimeseriesResult>,
+    window: Option<(f64, f64)>,
+) -> Payload {
+    // Rows without a time can't be placed on the chart
+    let total = data.len();
+    let data = data
+        .into_iter()
+        .filter_map(|row| Timeseries::try_from(row).ok())
+        .collect::<Vec<Timeseries>>();
+    if data.len() < total {
+        warn!(
+            "Skipped {} of {} results with no time for {}",
+            total - data.len(),
+            total,
+            query
+        );
+    }
 
     let mut min_bounds: (f64, f64) = (f64::MAX, f64::MAX);
     let mut max_bounds: (f64, f64) = (0 as f64, 0 as f64);
@@ -182,33 +459,479 @@ pub async fn query_timeseries(query: NRQLQuery, client: NewRelicClient) -> Resul
         max_bounds.1 = f64::max(max_bounds.1, point.value);
     }
 
+    if let Some((since, until)) = window {
+        min_bounds.0 = since;
+        max_bounds.0 = until;
+    }
+
     let mut facets: BTreeMap<String, Vec<(f64, f64)>> = BTreeMap::default();
-    let mut facet_keys: Vec<String> = vec![];
-
-    for data in data.into_iter().map(Timeseries::from) {
-        let facet = &data.facet.unwrap_or(String::from("value"));
-        facet_keys.push(facet.to_owned());
-        if facets.contains_key(facet) {
-            facets
-                .get_mut(facet)
-                .unwrap()
-                .extend_from_slice(&[(data.end_time_seconds, data.value)]);
-        } else {
-            facets.insert(
-                facet.to_owned(),
-                vec![(data.begin_time_seconds, data.value)],
-            );
-        }
+    let mut raw_facets = BTreeMap::default();
+
+    // Bucket widths vary (e.g. TIMESERIES AUTO), so every point sits at its bucket's end
+    for data in data {
+        let facet = match data.facet {
+            Some(raw) => {
+                let facet = normalize_facet(&raw);
+                raw_facets.insert(facet.clone(), raw);
+                facet
+            }
+            None => String::from(NO_FACET),
+        };
+        facets
+            .entry(facet)
+            .or_default()
+            .push((data.end_time_seconds, data.value));
     }
 
-    Ok(Payload {
-        query: query.to_string().unwrap(),
-        facets: facet_keys,
+    Payload {
+        query,
         data: facets,
+        raw_facets,
         bounds: Bounds {
             mins: min_bounds,
             maxes: max_bounds,
         },
-        selection: query.select.to_owned(),
+        window,
+        ..Default::default()
+    }
+}
+
+// Keeps the `max` - 1 facets with the highest latest value and sums the rest into "other"
+pub fn cap_facets(
+    facets: BTreeMap<String, Vec<(f64, f64)>>,
+    max: usize,
+) -> BTreeMap<String, Vec<(f64, f64)>> {
+    if max == 0 || facets.len() <= max {
+        return facets;
+    }
+
+    let latest = |points: &Vec<(f64, f64)>| {
+        points
+            .iter()
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, y)| *y)
+            .unwrap_or_default()
+    };
+
+    let mut ranked = facets.into_iter().collect::<Vec<_>>();
+    ranked.sort_by(|(_, a), (_, b)| latest(b).total_cmp(&latest(a)));
+    let rest = ranked.split_off(max - 1);
+
+    let mut other = rest
+        .into_iter()
+        .flat_map(|(_, points)| points)
+        .collect::<Vec<(f64, f64)>>();
+    other.sort_by(|a, b| a.0.total_cmp(&b.0));
+    other.dedup_by(|next, acc| {
+        if next.0 == acc.0 {
+            acc.1 += next.1;
+            true
+        } else {
+            false
+        }
+    });
+
+    let mut capped = ranked.into_iter().collect::<BTreeMap<_, _>>();
+    capped.insert(String::from("other"), other);
+    capped
+}
+
+pub async fn query_histogram(
+    query: String,
+    client: NewRelicClient,
+) -> Result<HistogramPayload, Error> {
+    let data: Vec<serde_json::Value> = client.query::<serde_json::Value>(&query).await?;
+
+    Ok(HistogramPayload {
+        buckets: parse_histogram(&query, &data),
+        query,
     })
 }
+
+pub async fn query_keyset(source: String, client: NewRelicClient) -> Result<Vec<String>, Error> {
+    let query = format!("SELECT keyset() FROM {}", source);
+    let data: Vec<serde_json::Value> = client.query::<serde_json::Value>(&query).await?;
+
+    Ok(parse_keyset(&data))
+}
+
+// Attribute names from keyset() rows, either `{key, type}` per attribute or `allKeys` arrays
+pub fn parse_keyset(data: &[serde_json::Value]) -> Vec<String> {
+    let mut keys = data
+        .iter()
+        .flat_map(|row| match (row.get("key"), row.get("allKeys")) {
+            (Some(serde_json::Value::String(key)), _) => vec![key.to_owned()],
+            (_, Some(serde_json::Value::Array(keys))) => keys
+                .iter()
+                .filter_map(|key| key.as_str().map(String::from))
+                .collect(),
+            _ => vec![],
+        })
+        .collect::<Vec<String>>();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+pub async fn query_applications(
+    name: String,
+    client: NewRelicClient,
+) -> Result<Vec<Application>, Error> {
+    let query = format!(
+        "SELECT appName, entityGuid FROM Transaction WHERE {} SINCE 1 day ago LIMIT MAX",
+        like_clause("appName", &format!("%{}%", name))
+    );
+    let data: Vec<serde_json::Value> = client.query::<serde_json::Value>(&query).await?;
+
+    Ok(parse_applications(&data))
+}
+
+// One entry per application, as every transaction row repeats its name and guid
+pub fn parse_applications(data: &[serde_json::Value]) -> Vec<Application> {
+    let mut applications = data
+        .iter()
+        .filter_map(|row| {
+            Some(Application {
+                name: row.get("appName")?.as_str()?.to_owned(),
+                guid: row.get("entityGuid")?.as_str()?.to_owned(),
+            })
+        })
+        .collect::<Vec<Application>>();
+    applications.sort();
+    applications.dedup();
+    applications
+}
+
+// Extracts (bucket label, count) pairs in ascending bucket order. Buckets are labelled with the
+// lower bound New Relic returns for them, e.g. `{ "minValue": 0.5, "maxValue": 1, "count": 12 }`,
+// as it may adjust the widths asked for. Bare counts are labelled from the query's arguments
+pub fn parse_histogram(query: &str, data: &[serde_json::Value]) -> Vec<(String, u64)> {
+    let histogram = data
+        .iter()
+        .filter_map(|row| row.as_object())
+        .flat_map(|row| row.iter())
+        .find(|(key, _)| key.starts_with("histogram"))
+        .map(|(_, value)| value);
+    // Listed directly, or under `buckets` alongside the overall bounds
+    let buckets = match histogram
+        .map(|value| value.get("buckets").unwrap_or(value))
+        .and_then(|value| value.as_array())
+    {
+        Some(buckets) => buckets,
+        None => return vec![],
+    };
+    let count = |value: &serde_json::Value| value.as_f64().unwrap_or_default() as u64;
+
+    let mut bounded = buckets
+        .iter()
+        .filter_map(|bucket| {
+            let min = bucket.get("minValue")?.as_f64()?;
+            Some((min, bucket.get("count").map_or(0, count)))
+        })
+        .collect::<Vec<(f64, u64)>>();
+    if !bounded.is_empty() {
+        bounded.sort_by(|a, b| a.0.total_cmp(&b.0));
+        return bounded
+            .into_iter()
+            .map(|(min, count)| (bucket_label(min), count))
+            .collect();
+    }
+
+    // histogram(attribute, ceiling, number of buckets)
+    let args = histogram_args(query);
+    let ceiling = args.get(1).and_then(|v| v.parse::<f64>().ok());
+    let n_buckets = args
+        .get(2)
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(buckets.len());
+
+    buckets
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let label = match ceiling {
+                Some(ceiling) if n_buckets > 0 => {
+                    bucket_label(i as f64 * ceiling / n_buckets as f64)
+                }
+                _ => i.to_string(),
+            };
+            (label, count(value))
+        })
+        .collect()
+}
+
+fn bucket_label(bound: f64) -> String {
+    format!("{}", (bound * 100.0).round() / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::NRQL;
+    use serde_json::json;
+
+    #[test]
+    fn histogram_labels_come_from_returned_bounds() {
+        // Asked for 10 buckets up to 1, but New Relic widened them
+        let data = vec![json!({ "histogram": [
+            { "minValue": 0.0, "maxValue": 0.25, "count": 3 },
+            { "minValue": 0.25, "maxValue": 0.5, "count": 7 },
+        ] })];
+
+        let buckets = parse_histogram("SELECT histogram(duration, 1, 10) FROM Transaction", &data);
+
+        assert_eq!(
+            buckets,
+            vec![(String::from("0"), 3), (String::from("0.25"), 7)]
+        );
+    }
+
+    #[test]
+    fn histogram_buckets_are_ascending() {
+        let data = vec![json!({ "histogram.duration": { "buckets": [
+            { "minValue": 2.0, "maxValue": 3.0, "count": 1 },
+            { "minValue": 0.0, "maxValue": 1.0, "count": 5 },
+            { "minValue": 1.0, "maxValue": 2.0, "count": 9 },
+        ] } })];
+
+        let labels = parse_histogram("SELECT histogram(duration) FROM Transaction", &data)
+            .into_iter()
+            .map(|(label, _)| label)
+            .collect::<Vec<String>>();
+
+        assert_eq!(labels, vec!["0", "1", "2"]);
+    }
+
+    #[test]
+    fn histogram_counts_without_bounds_use_query_arguments() {
+        let data = vec![json!({ "histogram": [4, 6] })];
+
+        let buckets = parse_histogram("SELECT histogram(duration, 1, 2) FROM Transaction", &data);
+
+        assert_eq!(
+            buckets,
+            vec![(String::from("0"), 4), (String::from("0.5"), 6)]
+        );
+    }
+
+    #[test]
+    fn facets_over_the_cap_are_summed_into_other() {
+        // Facet i ends on i, so 10 to 19 are the top ten
+        let facets = (0..20)
+            .map(|i| (format!("host-{:02}", i), vec![(0.0, 1.0), (60.0, i as f64)]))
+            .collect::<BTreeMap<String, Vec<(f64, f64)>>>();
+
+        let capped = cap_facets(facets, 11);
+
+        assert_eq!(capped.len(), 11);
+        assert!((10..20).all(|i| capped.contains_key(&format!("host-{:02}", i))));
+        assert_eq!(capped["other"], vec![(0.0, 10.0), (60.0, 45.0)]);
+    }
+
+    #[test]
+    fn epoch_millisecond_range_bounds_the_x_axis_in_seconds() {
+        let query = "FROM Transaction SELECT count(*) WHERE appName = 'api' SINCE 1700000000000 UNTIL 1700003600000 LIMIT MAX TIMESERIES"
+            .to_nrql()
+            .unwrap();
+        let data = vec![TimeseriesResult {
+            begin_time_seconds: Some(1700001000.0),
+            end_time_seconds: Some(1700001060.0),
+            facet: None,
+            value: Some(5.0),
+        }];
+
+        let payload = parse_timeseries(query, data);
+
+        assert_eq!(payload.window, Some((1700000000.0, 1700003600.0)));
+        assert_eq!(payload.bounds.mins.0, 1700000000.0);
+        assert_eq!(payload.bounds.maxes.0, 1700003600.0);
+    }
+
+    #[test]
+    fn selected_columns_are_projected_in_order() {
+        let log = json!({
+            "timestamp": 1700000000000_u64,
+            "level": "ERROR",
+            "message": "Connection refused",
+            "hostname": "api-1",
+        });
+        let columns = ["level", "message", "timestamp", "trace.id"].map(String::from);
+
+        assert_eq!(
+            project_columns(&log, &columns),
+            "ERROR | Connection refused | 1700000000000 | -"
+        );
+    }
+
+    #[test]
+    fn y_bound_is_the_tallest_bucket() {
+        let chart_data = ChartData {
+            // Three info logs in the first half, one in the second
+            info: vec![(0.0, 1.0), (1.0, 1.0), (4.0, 1.0), (6.0, 1.0)],
+            error: vec![(9.0, 1.0), (10.0, 1.0)],
+            debug: vec![],
+        };
+
+        let linear = chart_data.bucketed(0.0, 10.0, 2, false);
+        assert_eq!(linear.info, vec![(0.0, 3.0), (5.0, 1.0)]);
+        assert_eq!(linear.y_bound(), 3.0);
+
+        let log = chart_data.bucketed(0.0, 10.0, 2, true);
+        assert_eq!(log.y_bound(), f64::log10(4.0));
+    }
+
+    #[test]
+    fn embedded_query_serializes_back_to_the_dispatched_query() {
+        let dispatched = "FROM Transaction SELECT count(*) WHERE appName = 'api' FACET host SINCE 1 hour ago UNTIL now LIMIT MAX TIMESERIES 5 minutes";
+        let payload = parse_timeseries(dispatched.to_nrql().unwrap(), vec![]);
+
+        let nrql = payload.nrql.unwrap();
+        assert_eq!(nrql.to_string().unwrap(), payload.query);
+        assert_eq!(payload.query, QueryType::from(dispatched).key());
+
+        // Kept as structured clauses through a save and load
+        let saved = serde_json::to_string(&nrql).unwrap();
+        let loaded = serde_json::from_str::<NRQLQuery>(&saved).unwrap();
+        assert_eq!(loaded.facet, "host");
+        assert_eq!(loaded.to_string().unwrap(), payload.query);
+    }
+
+    #[test]
+    fn keyset_rows_are_sorted_and_deduplicated() {
+        let data = vec![
+            json!({ "key": "duration", "type": "numeric" }),
+            json!({ "key": "appName", "type": "string" }),
+            json!({ "allKeys": ["host", "duration", 7] }),
+            json!({ "type": "string" }),
+        ];
+
+        assert_eq!(
+            parse_keyset(&data),
+            vec!["appName", "duration", "host"]
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<String>>()
+        );
+    }
+
+    #[test]
+    fn y_bound_fits_whichever_level_is_tallest() {
+        let chart_data = ChartData {
+            info: vec![(0.0, 2.0), (5.0, 1.0)],
+            error: vec![(0.0, 1.0), (5.0, 7.0)],
+            debug: vec![(0.0, 4.0)],
+        };
+        assert_eq!(chart_data.y_bound(), 7.0);
+
+        assert_eq!(ChartData::default().y_bound(), 0.0);
+    }
+
+    #[test]
+    fn application_rows_give_one_entry_per_app() {
+        let data = vec![
+            json!({ "appName": "payments", "entityGuid": "MXxBUE18QVBQ" }),
+            json!({ "appName": "checkout", "entityGuid": "MXxBUE18Q0hL" }),
+            json!({ "appName": "payments", "entityGuid": "MXxBUE18QVBQ" }),
+            json!({ "appName": "orphan" }),
+        ];
+
+        let application = |name: &str, guid: &str| Application {
+            name: name.to_owned(),
+            guid: guid.to_owned(),
+        };
+        assert_eq!(
+            parse_applications(&data),
+            vec![
+                application("checkout", "MXxBUE18Q0hL"),
+                application("payments", "MXxBUE18QVBQ"),
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_header_is_the_union_of_every_logs_keys() {
+        let logs = vec![
+            json!({ "level": "ERROR", "message": "Connection refused, retrying" }),
+            json!({ "level": "INFO", "context": { "requestId": "abc" }, "tags": ["a"] }),
+        ];
+
+        assert_eq!(
+            logs_to_csv(&logs),
+            "context.requestId,level,message,tags.0\n\
+             ,ERROR,\"Connection refused, retrying\",\n\
+             abc,INFO,,a\n"
+        );
+    }
+
+    #[test]
+    fn facet_named_value_charts_separately_from_no_facet() {
+        let row = |facet: Option<&str>, value: f64| TimeseriesResult {
+            begin_time_seconds: Some(0.0),
+            end_time_seconds: Some(60.0),
+            facet: facet.map(String::from),
+            value: Some(value),
+        };
+
+        let payload = parse_raw_timeseries(
+            String::from("FROM (SELECT count(*) FROM Log) SELECT max(count) TIMESERIES"),
+            vec![row(None, 1.0), row(Some("value"), 2.0)],
+        );
+
+        assert_eq!(payload.data[NO_FACET], vec![(60.0, 1.0)]);
+        assert_eq!(payload.data["value"], vec![(60.0, 2.0)]);
+        assert_eq!(facet_label(NO_FACET), "value");
+    }
+
+    #[test]
+    fn rows_without_a_time_are_skipped() {
+        let rows = serde_json::from_value::<Vec<TimeseriesResult>>(json!([
+            { "beginTimeSeconds": 0, "endTimeSeconds": 60, "value": 1, "extra": "ignored" },
+            { "beginTimeSeconds": 60, "value": 2 },
+            { "beginTimeSeconds": 120, "endTimeSeconds": null, "value": 3 },
+            { "beginTimeSeconds": 120, "endTimeSeconds": 180, "value": 4 },
+        ]))
+        .unwrap();
+
+        let payload = parse_raw_timeseries(
+            String::from("FROM (SELECT count(*) FROM Log) SELECT max(count) TIMESERIES"),
+            rows,
+        );
+
+        assert_eq!(payload.data[NO_FACET], vec![(60.0, 1.0), (180.0, 4.0)]);
+        assert_eq!(payload.bounds.mins.0, 60.0);
+    }
+
+    #[test]
+    fn frozen_refresh_keeps_the_original_window() {
+        let query = "FROM Transaction SELECT count(*) WHERE duration > 0 FACET appName \
+                     SINCE 1 hour ago UNTIL now LIMIT MAX TIMESERIES";
+        let key = QueryType::from(query).key();
+        let dispatched_at = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        let frozen = frozen_query(&key, dispatched_at).unwrap();
+        assert!(frozen.contains("SINCE 1699996400000 UNTIL 1700000000000"));
+        // Every later refresh resolves against the dispatch time, so sends the same window
+        assert_eq!(frozen_query(&key, dispatched_at), Some(frozen.clone()));
+        assert_eq!(frozen_query("SELECT * FROM Log", dispatched_at), None);
+
+        let refreshed = PayloadType::Timeseries(Payload {
+            query: frozen,
+            ..Default::default()
+        })
+        .refiled(&key);
+        assert_eq!(refreshed.query(), Some(key.as_str()));
+    }
+
+    #[test]
+    fn out_of_range_window_is_not_frozen() {
+        let at = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        for query in [
+            "FROM Transaction SELECT count(*) WHERE duration > 0 \
+             SINCE 99999999999999 days ago UNTIL now LIMIT MAX TIMESERIES",
+            "FROM Transaction SELECT count(*) WHERE duration > 0 \
+             SINCE 1 hour ago UNTIL 100000000 weeks ago LIMIT MAX TIMESERIES",
+        ] {
+            assert_eq!(frozen_query(query, at), None);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use crate::backend::{HistogramPayload, LogPayload, Payload};
+
+// Everything needed to reproduce a session offline, including the last fetched data
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bundle {
+    pub theme: BundleTheme,
+    pub history: Vec<String>,
+    pub aliases: BTreeMap<String, String>,
+    pub log_filters: BTreeMap<String, BTreeSet<String>>,
+    pub timeseries: Vec<Payload>,
+    pub histograms: Vec<HistogramPayload>,
+    pub logs: Option<LogPayload>,
+}
+
+// Colours in their ratatui string form, e.g. `#60a5fa` or `red`
+#[derive(Default, Serialize, Deserialize)]
+pub struct BundleTheme {
+    pub focus_fg: String,
+    pub chart_fg: String,
+}
+
+impl Bundle {
+    pub fn read(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+}
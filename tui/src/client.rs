@@ -1,19 +1,41 @@
-use crate::query::QueryResponse;
+use crate::{query::QueryResponse, secret::Secret};
 use anyhow::{anyhow, Error};
+use log::debug;
 use reqwest::{
-    header::{HeaderMap, HeaderValue},
-    Client, ClientBuilder, Method,
+    header::{HeaderMap, HeaderValue, CONTENT_TYPE},
+    Client, ClientBuilder, Method, Response,
 };
 use serde::de::DeserializeOwned;
+use serde_json::Value;
 
+const ERROR_SNIPPET_LEN: usize = 200;
+const REQUEST_ID_HEADER: &str = "X-Request-ID";
+const SCHEMA_PROBE: &str = "SELECT count(*) FROM Transaction SINCE 1 minute ago";
+pub const SCHEMA_WARNING: &str = "New Relic response schema unexpected";
 const QUERY_BASE: &str = r#"{ "query":  "{ actor { account(id: $account) { nrql(query: \"$query\") { results } } } }" }"#;
 
 #[derive(Clone)]
 pub struct NewRelicClient {
     url: Option<&'static str>,
     account: Option<String>,
-    api_key: Option<String>,
+    api_key: Option<Secret>,
     client: Option<Client>,
+    request_id: Option<String>,
+}
+
+// A random (version 4) UUID, identifying one query's requests in the log
+pub fn new_request_id() -> String {
+    let bits =
+        (rand::random::<u128>() & !(0xf000 << 64) & !(0xc << 60)) | (0x4000 << 64) | (0x8 << 60);
+    let hex = format!("{:032x}", bits);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
 }
 
 impl NewRelicClient {
@@ -23,6 +45,15 @@ impl NewRelicClient {
             account: None,
             api_key: None,
             client: None,
+            request_id: None,
+        }
+    }
+
+    // A copy whose requests carry `id` in the log and an X-Request-ID header
+    pub fn with_request_id(&self, id: &str) -> Self {
+        NewRelicClient {
+            request_id: Some(id.to_owned()),
+            ..self.clone()
         }
     }
 
@@ -36,8 +67,8 @@ impl NewRelicClient {
         self
     }
 
-    pub fn api_key(&mut self, key: &str) -> &mut Self {
-        self.api_key = Some(key.to_owned());
+    pub fn api_key(&mut self, key: &Secret) -> &mut Self {
+        self.api_key = Some(key.clone());
         self
     }
 
@@ -47,11 +78,15 @@ impl NewRelicClient {
             "Content-Type",
             HeaderValue::from_str("application/json").unwrap(),
         );
-        headers.append(
-            "API-Key",
-            HeaderValue::from_str(self.api_key.as_ref().expect("ERROR: No API Key provided!"))
-                .unwrap(),
-        );
+        let mut api_key = HeaderValue::from_str(
+            self.api_key
+                .as_ref()
+                .expect("ERROR: No API Key provided!")
+                .expose(),
+        )
+        .expect("ERROR: API Key is not a valid header value!");
+        api_key.set_sensitive(true);
+        headers.append("API-Key", api_key);
 
         self.client = Some(client.default_headers(headers).build().unwrap());
 
@@ -62,27 +97,179 @@ impl NewRelicClient {
         &self,
         query_str: impl AsRef<str>,
     ) -> Result<Vec<T>, Error> {
+        let json = self
+            .post(query_str)
+            .await?
+            .json::<QueryResponse<T>>()
+            .await
+            .map_err(|e| anyhow!(e))?;
+
+        Ok(json.data.actor.account.nrql.results)
+    }
+
+    // Runs a trivial query to check responses still have the shape QueryResponse expects
+    pub async fn check_schema(&self) -> Result<bool, Error> {
+        let json = self
+            .post(SCHEMA_PROBE)
+            .await?
+            .json::<Value>()
+            .await
+            .map_err(|e| anyhow!(e))?;
+
+        Ok(has_results(&json))
+    }
+
+    async fn post(&self, query_str: impl AsRef<str>) -> Result<Response, Error> {
+        let client = self
+            .client
+            .as_ref()
+            .ok_or(anyhow!("client not initialized — call http_client() first"))?;
+        let url = self
+            .url
+            .ok_or(anyhow!("client has no endpoint — call url() first"))?;
+        let account = self
+            .account
+            .as_ref()
+            .ok_or(anyhow!("client has no account — call account() first"))?;
+
         let request_body = QUERY_BASE
-            .replace("$account", self.account.as_ref().unwrap())
+            .replace("$account", account)
             .replace("$query", query_str.as_ref());
-        let response = self
-            .client
-            .clone()
-            .unwrap()
-            .request(Method::POST, self.url.unwrap())
-            .body(request_body)
-            .send()
-            .await;
+        let mut request = client.request(Method::POST, url).body(request_body);
+        if let Some(id) = &self.request_id {
+            debug!("[{}] Sending query to {}", id, url);
+            request = request.header(REQUEST_ID_HEADER, id);
+        }
+        let response = request.send().await;
 
         if let Ok(data) = response {
-            let json = data
-                .json::<QueryResponse<T>>()
-                .await
-                .map_err(|e| anyhow!(e))?;
+            // Gateway errors and maintenance pages come back as HTML
+            let status = data.status();
+            let is_json = data
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.contains("json"));
 
-            return Ok(json.data.actor.account.nrql.results);
+            if !status.is_success() || !is_json {
+                let body = data.text().await.unwrap_or_default();
+                let snippet = body.chars().take(ERROR_SNIPPET_LEN).collect::<String>();
+                return Err(anyhow!("{}: {}", status, snippet.trim()));
+            }
+
+            return Ok(data);
         }
 
         Err(anyhow!("Query returned no results!"))
     }
 }
+
+// Whether a response has results at `data.actor.account.nrql.results`
+fn has_results(json: &Value) -> bool {
+    json.pointer("/data/actor/account/nrql/results")
+        .is_some_and(Value::is_array)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    // Serves one canned response, returning the URL to query
+    async fn mock(status: &str, content_type: &str, body: &str) -> &'static str {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/graphql", listener.local_addr().unwrap());
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        );
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            _ = stream.read(&mut request).await;
+            _ = stream.write_all(response.as_bytes()).await;
+        });
+        Box::leak(url.into_boxed_str())
+    }
+
+    fn client(url: &'static str) -> NewRelicClient {
+        let mut client = NewRelicClient::builder();
+        client
+            .url(url)
+            .account("1")
+            .api_key(&Secret::new(String::from("NRAK-TEST")))
+            .http_client(Client::builder());
+        client
+    }
+
+    #[tokio::test]
+    async fn html_response_is_reported_with_its_status() {
+        let url = mock(
+            "502 Bad Gateway",
+            "text/html",
+            "<html><body>Bad Gateway</body></html>",
+        )
+        .await;
+
+        let error = client(url)
+            .query::<Value>("SELECT * FROM Log")
+            .await
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.starts_with("502 Bad Gateway"), "{}", error);
+        assert!(error.contains("<html><body>Bad Gateway"));
+
+        // A maintenance page can come back as a success
+        let url = mock("200 OK", "text/html", "<html>Down for maintenance</html>").await;
+        let error = client(url)
+            .query::<Value>("SELECT * FROM Log")
+            .await
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "200 OK: <html>Down for maintenance</html>");
+    }
+
+    #[tokio::test]
+    async fn altered_response_shape_fails_the_schema_check() {
+        let url = mock(
+            "200 OK",
+            "application/json",
+            r#"{"data":{"actor":{"account":{"nrql":{"results":[{"count":1}]}}}}}"#,
+        )
+        .await;
+        assert!(client(url).check_schema().await.unwrap());
+
+        // e.g. results moved under a new field
+        let url = mock(
+            "200 OK",
+            "application/json",
+            r#"{"data":{"actor":{"account":{"nrql":{"rows":{"results":[]}}}}}}"#,
+        )
+        .await;
+        assert!(!client(url).check_schema().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn unbuilt_client_is_a_descriptive_error() {
+        let error = NewRelicClient::builder()
+            .query::<Value>("SELECT * FROM Log")
+            .await
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "client not initialized — call http_client() first");
+
+        let mut client = NewRelicClient::builder();
+        client
+            .api_key(&Secret::new(String::from("NRAK-TEST")))
+            .http_client(Client::builder());
+        let error = client.check_schema().await.unwrap_err().to_string();
+        assert_eq!(error, "client has no endpoint — call url() first");
+    }
+}
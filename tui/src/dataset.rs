@@ -1,19 +1,422 @@
 use std::{
-    collections::{self, btree_map::Entry, BTreeMap, HashSet},
+    collections::{self, btree_map::Entry, BTreeMap, BTreeSet, HashSet},
+    str::FromStr,
     string::String,
     vec::Vec,
 };
 
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use ratatui::{prelude::Line, widgets::ListState};
+use serde_json::Value;
 
-use crate::backend::{Bounds, ChartData};
+use crate::{
+    backend::{Bounds, ChartData},
+    query::{quote_identifier, quote_value, result_cap, NRQLQuery, DEFAULT_SINCE},
+};
+
+// Spacing this many times the usual interval counts as a gap in the data
+const GAP_FACTOR: f64 = 1.5;
+
+// Median distance between consecutive points, i.e. the usual bucket width
+pub fn median_spacing(points: &[(f64, f64)]) -> Option<f64> {
+    let mut spacings = points
+        .windows(2)
+        .map(|pair| pair[1].0 - pair[0].0)
+        .collect::<Vec<f64>>();
+    if spacings.is_empty() {
+        return None;
+    }
+    spacings.sort_by(f64::total_cmp);
+    Some(spacings[spacings.len() / 2])
+}
+
+// Splits a series wherever a bucket is missing, so gaps aren't drawn as a straight line
+pub fn split_at_gaps(points: &[(f64, f64)]) -> Vec<&[(f64, f64)]> {
+    if points.len() < 3 {
+        return vec![points];
+    }
+    let expected = match median_spacing(points) {
+        Some(expected) => expected,
+        None => return vec![points],
+    };
+
+    let mut segments = vec![];
+    let mut start = 0;
+    for i in 1..points.len() {
+        if points[i].0 - points[i - 1].0 > expected * GAP_FACTOR {
+            segments.push(&points[start..i]);
+            start = i;
+        }
+    }
+    segments.push(&points[start..]);
+
+    segments
+}
+
+// Order facets are listed in the legend and drawn in, later facets drawn over earlier ones
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FacetSort {
+    #[default]
+    Alpha,
+    ValueDesc,
+    ValueAsc,
+}
+
+impl FromStr for FacetSort {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_lowercase().as_str() {
+            "alpha" => Ok(FacetSort::Alpha),
+            "value-desc" => Ok(FacetSort::ValueDesc),
+            "value-asc" => Ok(FacetSort::ValueAsc),
+            _ => Err(()),
+        }
+    }
+}
+
+// Orders facets by their latest value, keeping alphabetical order between equal values
+pub fn sort_facets<'a>(
+    mut facets: Vec<(&'a String, &'a Vec<(f64, f64)>)>,
+    sort: FacetSort,
+) -> Vec<(&'a String, &'a Vec<(f64, f64)>)> {
+    let latest = |points: &Vec<(f64, f64)>| points.last().map_or(f64::NEG_INFINITY, |p| p.1);
+    match sort {
+        FacetSort::Alpha => {}
+        FacetSort::ValueDesc => facets.sort_by(|a, b| latest(b.1).total_cmp(&latest(a.1))),
+        FacetSort::ValueAsc => facets.sort_by(|a, b| latest(a.1).total_cmp(&latest(b.1))),
+    }
+    facets
+}
+
+// Sums the points of facets merged under one name, matching them up by timestamp
+pub fn merge_facets(
+    facets: BTreeMap<String, Vec<(f64, f64)>>,
+    merged: &BTreeMap<String, String>,
+) -> BTreeMap<String, Vec<(f64, f64)>> {
+    if merged.is_empty() {
+        return facets;
+    }
 
+    let mut out: BTreeMap<String, Vec<(f64, f64)>> = BTreeMap::new();
+    for (facet, points) in facets {
+        let name = merged.get(&facet).cloned().unwrap_or(facet);
+        out.entry(name).or_default().extend(points);
+    }
+    for points in out.values_mut() {
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        points.dedup_by(|next, kept| {
+            let same = next.0 == kept.0;
+            if same {
+                kept.1 += next.1;
+            }
+            same
+        });
+    }
+
+    out
+}
+
+#[derive(Default)]
 pub struct Dataset {
     pub has_data: bool,
     pub query_alias: Option<String>,
     pub facets: BTreeMap<String, Vec<(f64, f64)>>,
     pub bounds: Bounds,
     pub selection: String,
+    pub histogram: Option<Vec<(String, u64)>>,
+    pub window: Option<(f64, f64)>,
+    pub selected_facet: usize,
+    pub nrql: Option<NRQLQuery>,
+    pub rate_unit: Option<String>,
+    pub previous_value: Option<f64>,
+    pub previous_facet_values: BTreeMap<String, f64>,
+    pub marked_facets: BTreeSet<String>,
+    // Facets folded into a single series, by their merged name
+    pub merged_facets: BTreeMap<String, String>,
+    // Temporarily the only facet charted, unlike hiding this isn't saved
+    pub solo_facet: Option<String>,
+    // Facet values as New Relic returned them, by their normalized name
+    pub raw_facets: BTreeMap<String, String>,
+}
+
+impl Dataset {
+    // Latest point of every facet, summed
+    pub fn latest_value(&self) -> Option<f64> {
+        let latest = self
+            .facets
+            .values()
+            .filter_map(|points| points.last().map(|(_, value)| *value))
+            .collect::<Vec<f64>>();
+        (!latest.is_empty()).then(|| latest.iter().sum())
+    }
+
+    // Average and maximum of the selected facet within [min_x, max_x]
+    pub fn selected_stats(&self, min_x: f64, max_x: f64) -> Option<(f64, f64)> {
+        let values = self
+            .facets
+            .get(self.selected_facet()?)?
+            .iter()
+            .filter(|(x, _)| *x >= min_x && *x <= max_x)
+            .map(|(_, y)| *y)
+            .collect::<Vec<f64>>();
+        if values.is_empty() {
+            return None;
+        }
+
+        let avg = values.iter().sum::<f64>() / values.len() as f64;
+        let max = values.iter().copied().fold(f64::MIN, f64::max);
+
+        Some((avg, max))
+    }
+
+    // e.g. "SINCE 1 hour ago · 60 points · ~1m", for annotating shared screenshots
+    pub fn subtitle(&self) -> Option<String> {
+        let nrql = self.nrql.as_ref()?;
+        let points = self.facets.values().max_by_key(|points| points.len())?;
+
+        let since = match nrql.since.trim() {
+            "" => DEFAULT_SINCE,
+            since => since,
+        };
+        let mut window = format!("SINCE {}", since);
+        if !matches!(nrql.until.trim(), "" | "now") {
+            window += &format!(" UNTIL {}", nrql.until.trim());
+        }
+        let mut parts = vec![window, format!("{} points", points.len())];
+        if let Some(interval) = median_spacing(points) {
+            parts.push(format!("~{}", compact_seconds(interval)));
+        }
+
+        Some(parts.join(" · "))
+    }
+
+    // Whether the query runs up to now, rather than ending at an UNTIL in the past
+    pub fn is_live(&self) -> bool {
+        self.nrql
+            .as_ref()
+            .map_or(true, |nrql| matches!(nrql.until.trim(), "" | "now"))
+    }
+
+    // Right edge of the x-axis: an explicit window's end, now while live, otherwise the data's end
+    pub fn max_x(&self, now: f64) -> f64 {
+        match self.window {
+            Some((_, until)) => until,
+            None if self.is_live() => now,
+            None => self.bounds.maxes.0,
+        }
+    }
+
+    // Change in the latest value since the previous refresh
+    pub fn delta(&self) -> Option<f64> {
+        Some(self.latest_value()? - self.previous_value?)
+    }
+
+    pub fn latest_facet_value(&self, facet: &str) -> Option<f64> {
+        self.facets.get(facet)?.last().map(|(_, value)| *value)
+    }
+
+    // Latest point of each facet, kept across a refresh to compare against
+    pub fn latest_facet_values(&self) -> BTreeMap<String, f64> {
+        self.facets
+            .keys()
+            .filter_map(|facet| Some((facet.to_owned(), self.latest_facet_value(facet)?)))
+            .collect()
+    }
+
+    // Change in a facet's latest value since the previous refresh
+    pub fn facet_delta(&self, facet: &str) -> Option<f64> {
+        Some(self.latest_facet_value(facet)? - self.previous_facet_values.get(facet)?)
+    }
+
+    pub fn selected_facet(&self) -> Option<&String> {
+        self.facets.keys().nth(self.selected_facet)
+    }
+
+    // Facets to chart in `sort` order, just the soloed one while solo view is on, skipping any without points
+    pub fn charted_facets(&self, sort: FacetSort) -> Vec<(&String, &Vec<(f64, f64)>)> {
+        let facets = self
+            .facets
+            .iter()
+            .filter(|(facet, points)| {
+                !points.is_empty() && self.solo_facet.as_ref().map_or(true, |solo| solo == *facet)
+            })
+            .collect();
+        sort_facets(facets, sort)
+    }
+
+    // The query narrowed to one facet, matching the value as returned rather than as shown
+    pub fn facet_query(&self, facet: &str) -> Option<NRQLQuery> {
+        let nrql = self.nrql.as_ref()?;
+        // Merged facets and "other" have no value of their own
+        let raw = self.raw_facets.get(facet)?;
+        // Values of multi-attribute and function facets can't be matched against one attribute
+        let attribute = nrql.facet.trim();
+        if attribute.is_empty() || attribute.contains([',', '(']) {
+            return None;
+        }
+
+        Some(nrql.clone().and_where(&format!(
+            "{} = {}",
+            quote_identifier(attribute),
+            quote_value(raw)
+        )))
+    }
+
+    // Whether there's anything to draw, as a partial payload can leave every facet empty
+    pub fn has_points(&self) -> bool {
+        self.histogram.is_some() || self.facets.values().any(|points| !points.is_empty())
+    }
+
+    pub fn toggle_solo_facet(&mut self) {
+        let facet = self.selected_facet().cloned();
+        self.solo_facet = match self.solo_facet == facet {
+            true => None,
+            false => facet,
+        };
+    }
+
+    pub fn toggle_marked_facet(&mut self) {
+        if let Some(facet) = self.selected_facet().cloned() {
+            if !self.marked_facets.remove(&facet) {
+                self.marked_facets.insert(facet);
+            }
+        }
+    }
+
+    // Folds the marked facets into one series called `name`, which later refreshes keep
+    pub fn merge_marked_facets(&mut self, name: &str) {
+        let marked = std::mem::take(&mut self.marked_facets);
+        // Facets already merged into a marked one follow it into the new series
+        for target in self.merged_facets.values_mut() {
+            if marked.contains(target) {
+                *target = name.to_owned();
+            }
+        }
+        for facet in marked {
+            self.merged_facets.insert(facet, name.to_owned());
+        }
+
+        self.facets = merge_facets(std::mem::take(&mut self.facets), &self.merged_facets);
+        self.solo_facet = None;
+        self.selected_facet = self.selected_facet.min(self.facets.len().saturating_sub(1));
+    }
+
+    pub fn next_facet(&mut self) {
+        if !self.facets.is_empty() {
+            self.selected_facet = (self.selected_facet + 1) % self.facets.len();
+        }
+    }
+
+    pub fn previous_facet(&mut self) {
+        if !self.facets.is_empty() {
+            self.selected_facet = (self.selected_facet + self.facets.len() - 1) % self.facets.len();
+        }
+    }
+}
+
+// A line of a pretty-printed log, with the JSON pointer of the object or array it opens or sits in
+pub struct FoldLine {
+    pub text: String,
+    pub opens: Option<String>,
+    pub parent: String,
+}
+
+// Pretty-prints like serde_json, collapsing the objects and arrays at the `folded` pointers
+pub fn fold_lines(value: &Value, folded: &HashSet<String>) -> Vec<FoldLine> {
+    let mut lines = vec![];
+    push_fold_lines(&mut lines, value, folded, 0, "", String::new(), "");
+    lines
+}
+
+// One-line stand-in for a folded object or array, e.g. `{...} (5 keys)`
+pub fn fold_summary(value: &Value) -> String {
+    let plural = |n: usize, one: &str, many: &str| match n {
+        1 => format!("1 {}", one),
+        n => format!("{} {}", n, many),
+    };
+    match value {
+        Value::Object(map) => format!("{{...}} ({})", plural(map.len(), "key", "keys")),
+        Value::Array(items) => format!("[...] ({})", plural(items.len(), "item", "items")),
+        value => value.to_string(),
+    }
+}
+
+fn push_fold_lines(
+    lines: &mut Vec<FoldLine>,
+    value: &Value,
+    folded: &HashSet<String>,
+    depth: usize,
+    key: &str,
+    path: String,
+    parent: &str,
+) {
+    let indent = "  ".repeat(depth);
+    let children = match value {
+        Value::Object(map) if !map.is_empty() => map
+            .iter()
+            .map(|(k, v)| (format!("{}: ", Value::from(k.as_str())), k.to_owned(), v))
+            .collect::<Vec<_>>(),
+        Value::Array(items) if !items.is_empty() => items
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (String::new(), i.to_string(), v))
+            .collect::<Vec<_>>(),
+        // Scalars, {} and [] are a single line either way
+        value => {
+            lines.push(FoldLine {
+                text: format!("{}{}{}", indent, key, value),
+                opens: None,
+                parent: parent.to_owned(),
+            });
+            return;
+        }
+    };
+
+    if folded.contains(&path) {
+        lines.push(FoldLine {
+            text: format!("{}{}{}", indent, key, fold_summary(value)),
+            opens: Some(path),
+            parent: parent.to_owned(),
+        });
+        return;
+    }
+
+    let (open, close) = match value {
+        Value::Array(_) => ('[', ']'),
+        _ => ('{', '}'),
+    };
+    lines.push(FoldLine {
+        text: format!("{}{}{}", indent, key, open),
+        opens: Some(path.clone()),
+        parent: parent.to_owned(),
+    });
+    let last = children.len() - 1;
+    for (i, (child_key, segment, child)) in children.into_iter().enumerate() {
+        // JSON pointer escaping, so keys containing `/` can't be confused with nesting
+        let child_path = format!("{}/{}", path, segment.replace('~', "~0").replace('/', "~1"));
+        push_fold_lines(
+            lines,
+            child,
+            folded,
+            depth + 1,
+            &child_key,
+            child_path,
+            &path,
+        );
+        // Every child but the last is followed by a comma, after its closing line if it has one
+        if i < last {
+            if let Some(line) = lines.last_mut() {
+                line.text.push(',');
+            }
+        }
+    }
+    // Folding from the closing line folds what it closes
+    lines.push(FoldLine {
+        text: format!("{}{}", indent, close),
+        opens: None,
+        parent: path,
+    });
 }
 
 pub struct Datasets {
@@ -23,20 +426,75 @@ pub struct Datasets {
 
 #[derive(Default, Clone)]
 pub struct Logs {
+    pub query: String,
     pub logs: BTreeMap<String, Vec<String>>,
+    pub columns: BTreeMap<String, String>,
     pub chart_data: ChartData,
     pub bounds: Bounds,
     pub log_list_state: ListState,
     pub log_item_list_state: ListState,
     pub selected: String,
     pub filters: HashSet<String>,
+    pub count: usize,
+    pub no_wrap: bool,
+    pub detail_offset: usize,
+    pub log_scale: bool,
+    pub compact: bool,
+    // JSON pointers of the objects and arrays folded in the log view, kept across logs
+    pub folds: HashSet<String>,
 }
 
 impl Logs {
+    // A full page of results means New Relic likely dropped the rest
+    pub fn is_truncated(&self) -> bool {
+        self.count > 0 && self.count >= result_cap(&self.query)
+    }
+
     pub fn selected(&self) -> Option<&Vec<String>> {
         self.logs.get(&self.selected)
     }
 
+    // The selected log as JSON lines with their pointers, None if it isn't JSON
+    fn fold_lines(&self) -> Option<Vec<FoldLine>> {
+        let value = serde_json::from_str::<Value>(&self.selected()?.join("\n")).ok()?;
+        Some(fold_lines(&value, &self.folds))
+    }
+
+    // The selected log's lines as shown, with folded objects and arrays collapsed
+    pub fn selected_lines(&self) -> Vec<String> {
+        if self.folds.is_empty() {
+            return self.selected().cloned().unwrap_or_default();
+        }
+        match self.fold_lines() {
+            Some(lines) => lines.into_iter().map(|line| line.text).collect(),
+            None => self.selected().cloned().unwrap_or_default(),
+        }
+    }
+
+    // Folds or unfolds the object or array opened at the cursor, or else the one the cursor is in
+    pub fn toggle_fold(&mut self) {
+        let target = match self.log_item_list_state.selected().and_then(|i| {
+            let line = self.fold_lines()?.into_iter().nth(i)?;
+            Some(line.opens.unwrap_or(line.parent))
+        }) {
+            Some(target) => target,
+            None => return,
+        };
+        if !self.folds.remove(&target) {
+            self.folds.insert(target.clone());
+        }
+
+        // Keep the cursor on the line that now stands for the fold
+        let i = self.fold_lines().and_then(|lines| {
+            lines
+                .iter()
+                .position(|line| line.opens.as_ref() == Some(&target))
+        });
+        if i.is_some() {
+            self.log_item_list_state.select(i);
+        }
+    }
+
     pub fn iter(&self) -> collections::btree_map::Iter<'_, String, Vec<String>> {
         self.logs.iter()
     }
@@ -49,14 +507,100 @@ impl Logs {
         self.logs.len()
     }
 
-    pub fn select(&mut self, i: usize) {
-        self.selected = self
-            .logs
+    // The selected log's key as epoch milliseconds, None if it isn't a timestamp
+    pub fn selected_timestamp(&self) -> Option<f64> {
+        self.selected.parse::<f64>().ok()
+    }
+
+    // Index of the log closest in time to `target`, in epoch milliseconds
+    pub fn nearest(&self, target: f64) -> Option<usize> {
+        self.logs
             .keys()
-            .nth(i)
-            .expect("ERROR: Could not select query!")
-            .to_owned();
+            .enumerate()
+            .filter_map(|(i, timestamp)| Some((i, timestamp.parse::<f64>().ok()?)))
+            .min_by(|(_, a), (_, b)| (a - target).abs().total_cmp(&(b - target).abs()))
+            .map(|(i, _)| i)
+    }
+
+    // Selects the first log in both the map and the list, or nothing when there are none
+    pub fn select_first(&mut self) {
+        self.selected = self.logs.keys().next().cloned().unwrap_or_default();
+        self.log_list_state
+            .select((!self.logs.is_empty()).then_some(0));
+        self.log_item_list_state.select(None);
+    }
+
+    // Selects the `i`th log, or the last if `i` is out of range, returning the index selected
+    pub fn select(&mut self, i: usize) -> Option<usize> {
+        let last = match self.logs.len().checked_sub(1) {
+            Some(last) => last,
+            None => {
+                self.selected.clear();
+                return None;
+            }
+        };
+        let i = i.min(last);
+        self.selected = self.logs.keys().nth(i)?.to_owned();
+        Some(i)
+    }
+}
+
+// Shortens a number of seconds to its largest whole unit, e.g. 300 to "5m"
+fn compact_seconds(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    match seconds {
+        s if s >= 86400 && s % 86400 == 0 => format!("{}d", s / 86400),
+        s if s >= 3600 && s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s >= 60 && s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+// Reads epoch seconds/milliseconds, `YYYY-MM-DD HH:MM[:SS]`, `HH:MM[:SS]` on the day of
+// `latest`, or a relative phrase such as `15 minutes ago`, as epoch milliseconds (UTC)
+pub fn parse_time(input: &str, latest: DateTime<Utc>, now: DateTime<Utc>) -> Option<f64> {
+    let input = input.trim();
+
+    if let Ok(epoch) = input.parse::<f64>() {
+        // Ten digits of seconds reach well past any log we'd be shown
+        return Some(if epoch < 1e11 {
+            epoch * 1000_f64
+        } else {
+            epoch
+        });
+    }
+
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(date_time) = NaiveDateTime::parse_from_str(input, format) {
+            return Some(date_time.and_utc().timestamp_millis() as f64);
+        }
+    }
+
+    for format in ["%H:%M:%S", "%H:%M"] {
+        if let Ok(time) = NaiveTime::parse_from_str(input, format) {
+            let date: NaiveDate = latest.date_naive();
+            return Some(date.and_time(time).and_utc().timestamp_millis() as f64);
+        }
+    }
+
+    let parts = input
+        .trim_end_matches("ago")
+        .split_whitespace()
+        .collect::<Vec<&str>>();
+    if let [count, unit] = parts.as_slice() {
+        let count = count.parse::<i64>().ok()?;
+        // An out-of-range count is no such time rather than a panic
+        let ago = match unit.trim_end_matches('s') {
+            "second" | "sec" | "s" => Duration::try_seconds(count)?,
+            "minute" | "min" | "m" => Duration::try_minutes(count)?,
+            "hour" | "h" => Duration::try_hours(count)?,
+            "day" | "d" => Duration::try_days(count)?,
+            _ => return None,
+        };
+        return Some(now.checked_sub_signed(ago)?.timestamp_millis() as f64);
     }
+
+    None
 }
 
 impl Datasets {
@@ -75,16 +619,19 @@ impl Datasets {
         self.datasets.get(&self.selected)
     }
 
-    pub fn remove_entry(&mut self, i: usize) -> String {
-        let to_delete = self
-            .datasets
-            .keys()
-            .nth(i)
-            .cloned()
-            .expect("ERROR: Could not index query for deletion!");
+    pub fn selected_mut(&mut self) -> Option<&mut Dataset> {
+        self.datasets.get_mut(&self.selected)
+    }
+
+    // Removes the `i`th query, None if there's no such query
+    pub fn remove_entry(&mut self, i: usize) -> Option<String> {
+        let to_delete = self.datasets.keys().nth(i).cloned()?;
+        let (removed, _) = self.datasets.remove_entry(&to_delete)?;
+        Some(removed)
+    }
 
-        let (removed, _) = self.datasets.remove_entry(&to_delete).unwrap();
-        removed
+    pub fn remove(&mut self, query: &str) -> Option<Dataset> {
+        self.datasets.remove(query)
     }
 
     pub fn iter(&self) -> collections::btree_map::Iter<'_, String, Dataset> {
@@ -99,12 +646,304 @@ impl Datasets {
         self.datasets.len()
     }
 
-    pub fn select(&mut self, i: usize) {
-        self.selected = self
-            .datasets
-            .keys()
-            .nth(i)
-            .expect("ERROR: Could not select query!")
-            .to_owned();
+    // Selects the `i`th query, or the last if `i` is out of range, returning the index selected
+    pub fn select(&mut self, i: usize) -> Option<usize> {
+        let last = match self.datasets.len().checked_sub(1) {
+            Some(last) => last,
+            None => {
+                self.selected.clear();
+                return None;
+            }
+        };
+        let i = i.min(last);
+        self.selected = self.datasets.keys().nth(i)?.to_owned();
+        Some(i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dataset(facets: &[(&str, &[(f64, f64)])]) -> Dataset {
+        Dataset {
+            has_data: true,
+            facets: facets
+                .iter()
+                .map(|(facet, points)| (facet.to_string(), points.to_vec()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn selected_stats_cover_the_visible_window() {
+        let data = dataset(&[
+            (
+                "checkout",
+                &[(0.0, 10.0), (1.0, 2.0), (2.0, 6.0), (3.0, 4.0), (4.0, 50.0)],
+            ),
+            ("payments", &[(1.0, 100.0)]),
+        ]);
+
+        assert_eq!(data.selected_stats(1.0, 3.0), Some((4.0, 6.0)));
+        assert_eq!(data.selected_stats(0.0, 4.0), Some((14.4, 50.0)));
+        assert_eq!(data.selected_stats(5.0, 6.0), None);
+    }
+
+    fn logs(query: &str, count: usize) -> Logs {
+        Logs {
+            query: query.to_owned(),
+            count,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn count_at_the_cap_is_truncated() {
+        assert!(logs("SELECT * FROM Log LIMIT MAX", 5000).is_truncated());
+        assert!(!logs("SELECT * FROM Log LIMIT MAX", 4999).is_truncated());
+        assert!(logs("SELECT * FROM Log", 100).is_truncated());
+        assert!(logs("SELECT * FROM Log LIMIT 10", 10).is_truncated());
+        assert!(!logs("SELECT * FROM Log LIMIT 10", 0).is_truncated());
+    }
+
+    #[test]
+    fn series_is_split_at_a_missing_bucket() {
+        let points = [
+            (0.0, 1.0),
+            (60.0, 2.0),
+            (120.0, 3.0),
+            (240.0, 4.0),
+            (300.0, 5.0),
+        ];
+
+        assert_eq!(median_spacing(&points), Some(60.0));
+        assert_eq!(split_at_gaps(&points), vec![&points[..3], &points[3..]]);
+
+        let steady = [(0.0, 1.0), (60.0, 2.0), (120.0, 3.0)];
+        assert_eq!(split_at_gaps(&steady), vec![&steady[..]]);
+    }
+
+    #[test]
+    fn nearest_log_to_a_target_time() {
+        let mut logs = Logs::default();
+        for timestamp in [
+            "1700000000000",
+            "1700000060000",
+            "1700000120000",
+            "not-a-time",
+        ] {
+            logs.logs.insert(timestamp.to_owned(), vec![]);
+        }
+
+        assert_eq!(logs.nearest(1700000050000.0), Some(1));
+        assert_eq!(logs.nearest(1600000000000.0), Some(0));
+        assert_eq!(logs.nearest(1800000000000.0), Some(2));
+        assert_eq!(Logs::default().nearest(1700000000000.0), None);
+    }
+
+    #[test]
+    fn subtitle_describes_the_window_and_interval() {
+        let mut data = dataset(&[
+            ("checkout", &[(0.0, 1.0), (300.0, 2.0), (600.0, 3.0)]),
+            ("search", &[(0.0, 1.0)]),
+        ]);
+        assert_eq!(data.subtitle(), None);
+
+        data.nrql = Some(NRQLQuery {
+            since: String::from("3 hours ago"),
+            until: String::from("1 hour ago"),
+            ..Default::default()
+        });
+        assert_eq!(
+            data.subtitle().as_deref(),
+            Some("SINCE 3 hours ago UNTIL 1 hour ago · 3 points · ~5m")
+        );
+
+        data.nrql = Some(NRQLQuery {
+            until: String::from("now"),
+            ..Default::default()
+        });
+        assert_eq!(
+            data.subtitle(),
+            Some(format!("SINCE {} · 3 points · ~5m", DEFAULT_SINCE))
+        );
+    }
+
+    #[test]
+    fn merged_facets_sum_their_aligned_points() {
+        let facets = BTreeMap::from([
+            (String::from("prod-1"), vec![(0.0, 1.0), (60.0, 2.0)]),
+            (
+                String::from("prod-2"),
+                vec![(0.0, 3.0), (60.0, 4.0), (120.0, 5.0)],
+            ),
+            (String::from("staging"), vec![(0.0, 9.0)]),
+        ]);
+        let merged = BTreeMap::from([
+            (String::from("prod-1"), String::from("prod")),
+            (String::from("prod-2"), String::from("prod")),
+        ]);
+
+        assert_eq!(
+            merge_facets(facets, &merged),
+            BTreeMap::from([
+                (
+                    String::from("prod"),
+                    vec![(0.0, 4.0), (60.0, 6.0), (120.0, 5.0)]
+                ),
+                (String::from("staging"), vec![(0.0, 9.0)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn solo_view_charts_only_the_soloed_facet() {
+        let mut data = dataset(&[
+            ("checkout", &[(0.0, 1.0)]),
+            ("payments", &[(0.0, 2.0)]),
+            ("search", &[(0.0, 3.0)]),
+        ]);
+        data.selected_facet = 1;
+
+        data.toggle_solo_facet();
+        let charted = data.charted_facets(FacetSort::Alpha);
+        assert_eq!(
+            charted
+                .iter()
+                .map(|(facet, _)| facet.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["payments"]
+        );
+
+        data.toggle_solo_facet();
+        assert_eq!(data.charted_facets(FacetSort::Alpha).len(), 3);
+    }
+
+    #[test]
+    fn past_until_bounds_the_x_axis_at_the_data() {
+        let now = 1_700_100_000.0;
+        let day_ago = now - 86_400.0;
+        let mut data = dataset(&[("checkout", &[(day_ago - 3600.0, 1.0), (day_ago, 2.0)])]);
+        data.bounds.maxes.0 = day_ago;
+        data.nrql = Some(NRQLQuery {
+            since: String::from("2 days ago"),
+            until: String::from("1 day ago"),
+            ..Default::default()
+        });
+        assert!(!data.is_live());
+        assert_eq!(data.max_x(now), day_ago);
+
+        data.nrql = Some(NRQLQuery {
+            until: String::from("now"),
+            ..Default::default()
+        });
+        assert_eq!(data.max_x(now), now);
+    }
+
+    #[test]
+    fn empty_facets_are_not_charted() {
+        let data = dataset(&[("checkout", &[]), ("search", &[(0.0, 3.0)])]);
+        let charted = data.charted_facets(FacetSort::Alpha);
+        assert_eq!(
+            charted
+                .iter()
+                .map(|(facet, _)| facet.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["search"]
+        );
+        assert!(data.has_points());
+
+        assert!(!dataset(&[("checkout", &[])]).has_points());
+    }
+
+    #[test]
+    fn value_desc_orders_by_latest_value() {
+        let data = dataset(&[
+            ("checkout", &[(0.0, 9.0), (60.0, 2.0)]),
+            ("payments", &[(0.0, 1.0), (60.0, 7.0)]),
+            ("search", &[(0.0, 5.0), (60.0, 7.0)]),
+            ("shipping", &[(60.0, 4.0)]),
+        ]);
+        let order = |sort| {
+            data.charted_facets(sort)
+                .into_iter()
+                .map(|(facet, _)| facet.as_str())
+                .collect::<Vec<&str>>()
+        };
+
+        // Equal values stay alphabetical
+        assert_eq!(
+            order(FacetSort::ValueDesc),
+            vec!["payments", "search", "shipping", "checkout"]
+        );
+        assert_eq!(
+            order(FacetSort::ValueAsc),
+            vec!["checkout", "shipping", "payments", "search"]
+        );
+        assert_eq!("value-desc".parse::<FacetSort>(), Ok(FacetSort::ValueDesc));
+    }
+
+    #[test]
+    fn folded_object_renders_as_its_summary() {
+        let log = serde_json::json!({
+            "context": { "requestId": "abc", "retries": [1, 2], "user": { "id": 7 } },
+            "message": "timeout"
+        });
+        assert_eq!(fold_summary(&log["context"]), "{...} (3 keys)");
+        assert_eq!(fold_summary(&log["context"]["retries"]), "[...] (2 items)");
+        assert_eq!(fold_summary(&log["context"]["user"]), "{...} (1 key)");
+
+        let folded = HashSet::from([String::from("/context")]);
+        let text = fold_lines(&log, &folded)
+            .into_iter()
+            .map(|line| line.text)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            text,
+            vec![
+                "{",
+                "  \"context\": {...} (3 keys),",
+                "  \"message\": \"timeout\"",
+                "}"
+            ]
+        );
+    }
+
+    #[test]
+    fn out_of_range_selection_clamps_to_the_last_entry() {
+        let mut datasets = Datasets::new();
+        assert_eq!(datasets.select(3), None);
+        for query in ["a", "b", "c"] {
+            datasets
+                .entry(query.to_owned())
+                .or_insert_with(|| dataset(&[]));
+        }
+        assert_eq!(datasets.select(7), Some(2));
+        assert_eq!(datasets.selected, "c");
+        assert_eq!(datasets.remove_entry(9), None);
+
+        let mut logs = Logs::default();
+        logs.logs.insert(String::from("1700000000000"), vec![]);
+        logs.logs.insert(String::from("1700000060000"), vec![]);
+        assert_eq!(logs.select(5), Some(1));
+        assert_eq!(logs.selected, "1700000060000");
+
+        logs.logs.clear();
+        assert_eq!(logs.select(0), None);
+        assert!(logs.selected.is_empty());
+    }
+
+    #[test]
+    fn out_of_range_relative_time_is_not_found() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        assert_eq!(
+            parse_time("5 minutes ago", now, now),
+            Some(1_699_999_700_000_f64)
+        );
+        assert_eq!(parse_time("99999999999999 days ago", now, now), None);
+        assert_eq!(parse_time("999999999 days ago", now, now), None);
     }
 }
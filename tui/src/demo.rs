@@ -0,0 +1,219 @@
+use anyhow::{Error, Result};
+use chrono::Utc;
+use crossbeam_channel::Receiver as CrossBeamReceiver;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_json::json;
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{sync::mpsc::Sender, time};
+
+use crate::{
+    backend::{
+        parse_histogram, parse_logs, parse_raw_timeseries, parse_timeseries, Application,
+        HistogramPayload, PayloadType, UIEvent,
+    },
+    query::{is_timeseries, QueryType, TimeseriesResult, SUBQUERY_ERROR},
+};
+
+// Queries shown on startup so every view has something in it
+pub const DEMO_QUERIES: [&str; 2] = [
+    "FROM Transaction SELECT count(*) WHERE duration > 0 FACET appName SINCE 1 hour ago UNTIL now LIMIT MAX TIMESERIES",
+    "SELECT * FROM Log",
+];
+
+const DEMO_POINTS: usize = 60;
+const DEMO_LOGS: usize = 200;
+const DEMO_FACETS: [&str; 3] = ["checkout", "payments", "search"];
+const DEMO_LEVELS: [&str; 3] = ["Information", "Debug", "Error"];
+const DEMO_KEYSET: [&str; 7] = [
+    "appName",
+    "duration",
+    "level",
+    "message",
+    "requestId",
+    "service",
+    "timestamp",
+];
+
+// Seeds from the query so the same query always produces the same data
+fn rng_for(query: &str) -> StdRng {
+    let mut hasher = DefaultHasher::new();
+    query.hash(&mut hasher);
+    StdRng::seed_from_u64(hasher.finish())
+}
+
+pub fn payload(query: &str) -> Result<PayloadType, Error> {
+    let mut rng = rng_for(query);
+    let now = Utc::now().timestamp() as f64;
+
+    let payload = match QueryType::from(query) {
+        QueryType::Timeseries(nrql) => {
+            let facets = match nrql.facet.is_empty() {
+                true => vec![None],
+                false => DEMO_FACETS.map(|f| Some(f.to_owned())).to_vec(),
+            };
+            let mut data = vec![];
+            for facet in facets {
+                let mut value = rng.gen_range(10.0..100.0);
+                for i in 0..DEMO_POINTS {
+                    let begin = now - ((DEMO_POINTS - i) * 60) as f64;
+                    value = f64::max(0.0, value + rng.gen_range(-10.0..10.0));
+                    data.push(TimeseriesResult {
+                        begin_time_seconds: Some(begin),
+                        end_time_seconds: Some(begin + 60.0),
+                        facet: facet.clone(),
+                        value: Some(value),
+                    });
+                }
+            }
+            PayloadType::Timeseries(parse_timeseries(nrql, data))
+        }
+        QueryType::Raw(query) if is_timeseries(&query) => {
+            let mut value = rng.gen_range(10.0..100.0);
+            let data = (0..DEMO_POINTS)
+                .map(|i| {
+                    let begin = now - ((DEMO_POINTS - i) * 60) as f64;
+                    value = f64::max(0.0, value + rng.gen_range(-10.0..10.0));
+                    TimeseriesResult {
+                        begin_time_seconds: Some(begin),
+                        end_time_seconds: Some(begin + 60.0),
+                        facet: None,
+                        value: Some(value),
+                    }
+                })
+                .collect();
+            PayloadType::Timeseries(parse_raw_timeseries(query, data))
+        }
+        QueryType::Raw(query) => PayloadType::Error {
+            query: Some(query),
+            message: SUBQUERY_ERROR.to_owned(),
+        },
+        QueryType::Histogram(query) => {
+            let buckets = (0..10)
+                .map(|i| {
+                    json!({
+                        "minValue": i as f64 * 0.1,
+                        "maxValue": (i + 1) as f64 * 0.1,
+                        "count": rng.gen_range(0..500),
+                    })
+                })
+                .collect::<Vec<_>>();
+            let data = vec![json!({ "histogram": buckets })];
+            PayloadType::Histogram(HistogramPayload {
+                buckets: parse_histogram(&query, &data),
+                query,
+            })
+        }
+        QueryType::Log(query) => {
+            let now_ms = now as u64 * 1000;
+            let data = (0..DEMO_LOGS)
+                .map(|i| {
+                    let level = DEMO_LEVELS[rng.gen_range(0..DEMO_LEVELS.len())];
+                    json!({
+                        "timestamp": now_ms - (DEMO_LOGS - i) as u64 * 15_000,
+                        "level": level,
+                        "message": format!("{} request {} handled", level, i),
+                        "service": DEMO_FACETS[i % DEMO_FACETS.len()],
+                        "requestId": format!("{:016x}", rng.gen::<u64>()),
+                    })
+                })
+                .collect();
+            PayloadType::Log(parse_logs(query, data)?)
+        }
+    };
+
+    Ok(payload)
+}
+
+// Stands in for the New Relic listener, answering every query with synthetic data
+pub async fn listen(
+    data_tx: Sender<PayloadType>,
+    ui_rx: CrossBeamReceiver<UIEvent>,
+    refreshing: Arc<AtomicBool>,
+) -> Result<(), Error> {
+    let mut queries: HashSet<String> = HashSet::new();
+    loop {
+        while let Some(event) = ui_rx.try_iter().next() {
+            match event {
+                UIEvent::AddQuery(query) => {
+                    queries.insert(query.to_owned());
+                    data_tx.send(payload(&query)?).await?;
+                }
+                UIEvent::DeleteQuery(query) | UIEvent::CancelQuery(query) => {
+                    queries.remove(&query);
+                }
+                UIEvent::Keyset(_) => {
+                    data_tx
+                        .send(PayloadType::Keyset(
+                            DEMO_KEYSET.iter().map(|key| key.to_string()).collect(),
+                        ))
+                        .await?;
+                }
+                UIEvent::Applications(name) => {
+                    let applications = DEMO_FACETS
+                        .iter()
+                        .filter(|app| app.contains(&name.to_lowercase()))
+                        .map(|app| Application {
+                            name: app.to_string(),
+                            guid: format!("DEMO{:016x}", rng_for(app).gen::<u64>()),
+                        })
+                        .collect();
+                    data_tx
+                        .send(PayloadType::Applications(applications))
+                        .await?;
+                }
+                // Demo data is always generated up to now, so there's no window to freeze
+                UIEvent::FreezeWindows(_) => {}
+                UIEvent::RefreshData => {
+                    for query in &queries {
+                        if let QueryType::Log(_) = QueryType::from(query.as_str()) {
+                            continue;
+                        }
+                        data_tx.send(payload(query)?).await?;
+                    }
+                    refreshing.store(false, Ordering::SeqCst);
+                }
+            }
+        }
+        time::sleep(Duration::from_millis(30)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_app;
+
+    #[tokio::test]
+    async fn demo_mode_fills_timeseries_and_logs_without_a_client() {
+        let (mut app, data_tx, ui_rx) = test_app("demo");
+        let listener = tokio::spawn(listen(data_tx, ui_rx, Arc::new(AtomicBool::new(false))));
+        for query in DEMO_QUERIES {
+            app.ui_tx.send(UIEvent::AddQuery(query.to_owned())).unwrap();
+        }
+
+        let answered = time::timeout(Duration::from_secs(2), async {
+            let mut received = 0;
+            while received < DEMO_QUERIES.len() {
+                time::sleep(Duration::from_millis(10)).await;
+                received += app.drain_payloads();
+            }
+        })
+        .await;
+        listener.abort();
+
+        assert!(answered.is_ok());
+        assert!(app
+            .datasets
+            .iter()
+            .any(|(_, data)| data.has_data && !data.facets.is_empty()));
+        assert!(!app.logs.logs.is_empty());
+    }
+}
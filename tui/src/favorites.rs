@@ -0,0 +1,80 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+// A go-to query kept in favorites.yaml, e.g. `{ name: errors, query: "FROM ..." }`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Favorite {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub query: String,
+}
+
+impl Favorite {
+    // Shown in the picker, the name if it has one
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.query)
+    }
+}
+
+// A missing or unreadable file is treated as no favorites
+pub fn load(path: &Path) -> Vec<Favorite> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|yaml| serde_yaml::from_str::<Vec<Favorite>>(&yaml).ok())
+        .unwrap_or_default()
+}
+
+pub fn save(path: &Path, favorites: &[Favorite]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_yaml::to_string(favorites)?)?;
+
+    Ok(())
+}
+
+// Adds a query, or renames it if it's already a favorite, so each query is listed once
+pub fn add(favorites: &mut Vec<Favorite>, query: &str, name: Option<&str>) {
+    let name = name
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from);
+    match favorites.iter_mut().find(|f| f.query == query) {
+        Some(favorite) => favorite.name = name,
+        None => favorites.push(Favorite {
+            name,
+            query: query.to_owned(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_dir;
+
+    #[test]
+    fn favorites_round_trip() {
+        let path = temp_dir("favorites").join("favorites.yaml");
+        assert!(load(&path).is_empty());
+
+        let mut favorites = vec![];
+        add(&mut favorites, "SELECT * FROM Log", Some(" errors "));
+        add(
+            &mut favorites,
+            "FROM Transaction SELECT count(*) TIMESERIES",
+            None,
+        );
+        // Adding again renames rather than duplicating
+        add(&mut favorites, "SELECT * FROM Log", Some("all logs"));
+        save(&path, &favorites).unwrap();
+
+        let loaded = load(&path);
+        assert_eq!(loaded, favorites);
+        assert_eq!(
+            loaded.iter().map(Favorite::label).collect::<Vec<&str>>(),
+            vec!["all logs", "FROM Transaction SELECT count(*) TIMESERIES"]
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    fs,
+    future::Future,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio::time;
+
+// How often a followed session file is checked for changes
+pub const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+// What a file looked like when last checked, None while it's missing or empty
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    match metadata.len() {
+        0 => None,
+        len => Some((metadata.modified().ok()?, len)),
+    }
+}
+
+// Reports a change once the file has stopped changing, so a save caught halfway through isn't read
+pub struct Changes {
+    seen: Stamp,
+    pending: Stamp,
+}
+
+impl Changes {
+    pub fn new(current: Stamp) -> Self {
+        Changes {
+            seen: current,
+            pending: None,
+        }
+    }
+
+    pub fn poll(&mut self, current: Stamp) -> bool {
+        // Missing or truncated, e.g. between the unlink and rename of an atomic write
+        if current.is_none() || current == self.seen {
+            self.pending = None;
+            return false;
+        }
+        if current != self.pending {
+            self.pending = current;
+            return false;
+        }
+        self.seen = current;
+        self.pending = None;
+        true
+    }
+}
+
+// Calls `on_change` whenever the file at `path` is rewritten, in place or by renaming over it
+pub async fn watch<F, Fut>(path: PathBuf, interval: Duration, mut on_change: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    // Checks the path rather than an open handle, so a replaced file is picked up
+    let mut changes = Changes::new(stamp(&path));
+    let mut ticks = time::interval(interval);
+    loop {
+        ticks.tick().await;
+        if changes.poll(stamp(&path)) {
+            on_change().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_dir;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn change_is_reported_once_it_has_settled() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut changes = Changes::new(Some((start, 10)));
+        assert!(!changes.poll(Some((start, 10))));
+
+        let saved = Some((start + Duration::from_secs(1), 20));
+        assert!(!changes.poll(saved));
+        assert!(changes.poll(saved));
+        assert!(!changes.poll(saved));
+    }
+
+    #[test]
+    fn missing_file_mid_rename_is_not_a_change() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut changes = Changes::new(Some((start, 10)));
+        assert!(!changes.poll(None));
+        assert!(!changes.poll(None));
+        assert!(!changes.poll(Some((start, 10))));
+    }
+
+    #[tokio::test]
+    async fn renamed_over_file_triggers_the_callback() {
+        let dir = temp_dir("follow");
+        let path = dir.join("session.yaml");
+        fs::write(&path, "queries: []\n").unwrap();
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let watched = path.clone();
+        let watcher = tokio::spawn(watch(watched, Duration::from_millis(10), move || {
+            let tx = tx.clone();
+            async move {
+                _ = tx.send(()).await;
+            }
+        }));
+
+        // An atomic save, writing a new file and renaming it over the old one
+        time::sleep(Duration::from_millis(50)).await;
+        let saving = dir.join("session.yaml.tmp");
+        fs::write(&saving, "queries:\n- SELECT * FROM Log\n").unwrap();
+        fs::rename(&saving, &path).unwrap();
+
+        let changed = time::timeout(Duration::from_secs(2), rx.recv()).await;
+        watcher.abort();
+        assert_eq!(changed, Ok(Some(())));
+    }
+}
@@ -1,59 +1,23 @@
 use crate::app::Focus;
 
+// Longest accepted input, well past any practical NRQL query
+const MAX_INPUT_LEN: usize = 4096;
+
+#[derive(Default)]
 pub struct Input {
     pub buffer: String,
     pub cursor_position: usize,
 }
 
 pub struct Inputs {
-    _inputs: [Input; 10],
+    // One input buffer per `Focus` variant
+    _inputs: [Input; Focus::COUNT],
 }
 
 impl Inputs {
     pub fn new() -> Self {
         Inputs {
-            _inputs: [
-                Input {
-                    buffer: String::new(),
-                    cursor_position: 0,
-                },
-                Input {
-                    buffer: String::new(),
-                    cursor_position: 0,
-                },
-                Input {
-                    buffer: String::new(),
-                    cursor_position: 0,
-                },
-                Input {
-                    buffer: String::new(),
-                    cursor_position: 0,
-                },
-                Input {
-                    buffer: String::new(),
-                    cursor_position: 0,
-                },
-                Input {
-                    buffer: String::new(),
-                    cursor_position: 0,
-                },
-                Input {
-                    buffer: String::new(),
-                    cursor_position: 0,
-                },
-                Input {
-                    buffer: String::new(),
-                    cursor_position: 0,
-                },
-                Input {
-                    buffer: String::new(),
-                    cursor_position: 0,
-                },
-                Input {
-                    buffer: String::new(),
-                    cursor_position: 0,
-                },
-            ],
+            _inputs: std::array::from_fn(|_| Input::default()),
         }
     }
     pub fn get(&self, focus: Focus) -> &str {
@@ -64,6 +28,12 @@ impl Inputs {
         self._inputs[focus as usize].cursor_position
     }
 
+    // First visible column for a box `width` columns wide, keeping the cursor in view
+    pub fn scroll_offset(&self, focus: Focus, width: usize) -> usize {
+        self.get_cursor_position(focus)
+            .saturating_sub(width.saturating_sub(1))
+    }
+
     pub fn len(&self, focus: Focus) -> usize {
         self._inputs[focus as usize].buffer.len()
     }
@@ -101,6 +71,10 @@ impl Inputs {
     }
 
     pub fn enter_char(&mut self, focus: Focus, new_char: char) {
+        if self.len(focus) >= MAX_INPUT_LEN {
+            return;
+        }
+
         let cursor_position = self.get_cursor_position(focus);
         self._inputs[focus as usize]
             .buffer
@@ -130,6 +104,13 @@ impl Inputs {
         }
     }
 
+    // Inserts pasted text at the cursor as a single line
+    pub fn paste(&mut self, focus: Focus, text: &str) {
+        normalize_paste(text)
+            .chars()
+            .for_each(|c| self.enter_char(focus, c));
+    }
+
     pub fn set(&mut self, focus: Focus, value: String) {
         self._inputs[focus as usize].buffer = value;
     }
@@ -142,3 +123,54 @@ impl Inputs {
         self._inputs[focus as usize].cursor_position = 0;
     }
 }
+
+// Flattens a multi-line paste, e.g. a query copied from New Relic, collapsing runs of
+// whitespace and line breaks into single spaces
+pub fn normalize_paste(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visible_window_follows_a_cursor_near_the_end() {
+        let mut inputs = Inputs::new();
+        inputs.set(Focus::QueryInput, "x".repeat(100));
+        inputs.move_cursor_end(Focus::QueryInput);
+        (0..5).for_each(|_| inputs.move_cursor_left(Focus::QueryInput));
+
+        let offset = inputs.scroll_offset(Focus::QueryInput, 20);
+
+        assert_eq!(offset, 76);
+        // The cursor sits on the last visible column
+        assert_eq!(inputs.get_cursor_position(Focus::QueryInput), offset + 19);
+    }
+
+    #[test]
+    fn short_input_is_not_scrolled() {
+        let mut inputs = Inputs::new();
+        inputs.set(Focus::QueryInput, String::from("SELECT *"));
+        inputs.move_cursor_end(Focus::QueryInput);
+
+        assert_eq!(inputs.scroll_offset(Focus::QueryInput, 20), 0);
+    }
+
+    #[test]
+    fn multi_line_paste_is_flattened() {
+        let pasted =
+            "SELECT count(*)\r\n  FROM Transaction\n\tWHERE appName = 'checkout'\n  TIMESERIES\n";
+        assert_eq!(
+            normalize_paste(pasted),
+            "SELECT count(*) FROM Transaction WHERE appName = 'checkout' TIMESERIES"
+        );
+
+        let mut inputs = Inputs::new();
+        inputs.set(Focus::QueryInput, String::from("-- "));
+        inputs.move_cursor_end(Focus::QueryInput);
+        inputs.paste(Focus::QueryInput, "a\nb");
+        assert_eq!(inputs.get(Focus::QueryInput), "-- a b");
+        assert_eq!(inputs.get_cursor_position(Focus::QueryInput), 6);
+    }
+}
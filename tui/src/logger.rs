@@ -0,0 +1,93 @@
+use anyhow::Result;
+use chrono::Utc;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+};
+
+const LOG_FILE: &str = "xrelic.log";
+
+// The TUI owns the terminal, so log lines go to a file in the config directory
+struct FileLogger {
+    file: Mutex<File>,
+    level: LevelFilter,
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if let Ok(mut file) = self.file.lock() {
+            _ = writeln!(
+                file,
+                "{} {:<5} {}: {}",
+                Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            _ = file.flush();
+        }
+    }
+}
+
+// Standard level names (error, warn, info, debug, trace, off), anything else is Info
+pub fn parse_level(value: Option<&str>) -> LevelFilter {
+    value
+        .and_then(|level| level.trim().parse::<LevelFilter>().ok())
+        .unwrap_or(LevelFilter::Info)
+}
+
+pub fn setup_logging(config_dir: &Path) -> Result<()> {
+    let level = env::var("NR_LOG_LEVEL")
+        .or_else(|_| env::var("RUST_LOG"))
+        .ok();
+    let level = parse_level(level.as_deref());
+
+    fs::create_dir_all(config_dir)?;
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(config_dir.join(LOG_FILE))?;
+
+    log::set_boxed_logger(Box::new(FileLogger {
+        file: Mutex::new(file),
+        level,
+    }))?;
+    log::set_max_level(level);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_names_parse_to_their_filter() {
+        assert_eq!(parse_level(Some("debug")), LevelFilter::Debug);
+        assert_eq!(parse_level(Some(" WARN ")), LevelFilter::Warn);
+        assert_eq!(parse_level(Some("off")), LevelFilter::Off);
+    }
+
+    #[test]
+    fn invalid_or_missing_level_is_info() {
+        assert_eq!(parse_level(Some("verbose")), LevelFilter::Info);
+        assert_eq!(parse_level(Some("")), LevelFilter::Info);
+        assert_eq!(parse_level(None), LevelFilter::Info);
+    }
+}
@@ -1,94 +1,543 @@
 mod app;
 mod backend;
+mod bundle;
 mod client;
 mod dataset;
+mod demo;
+mod favorites;
+mod follow;
 mod input;
+mod logger;
+mod metrics;
 pub mod parser;
 pub mod query;
+mod secret;
 mod session;
+mod svg;
 mod ui;
+mod watch;
 
-use anyhow::Error;
+use anyhow::{anyhow, Error, Result};
 use app::{App, Theme};
-use backend::{query_log, query_timeseries, PayloadType, UIEvent};
-use client::NewRelicClient;
+use backend::{
+    frozen_query, query_applications, query_histogram, query_keyset, query_log,
+    query_raw_timeseries, query_timeseries, PayloadType, UIEvent,
+};
+use bundle::Bundle;
+use chrono::{DateTime, Utc};
+use client::{new_request_id, NewRelicClient, SCHEMA_WARNING};
 use crossbeam_channel::{unbounded, Receiver as CrossBeamReceiver, Sender as CrossBeamSender};
 use crossterm::{
+    event::{DisableBracketedPaste, EnableBracketedPaste},
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
-use query::{QueryType, NRQL};
-use ratatui::{backend::CrosstermBackend, Terminal};
-use reqwest::Client;
+use dataset::FacetSort;
+use log::{debug, info, warn};
+use metrics::Metrics;
+use query::{is_timeseries, QueryType, SUBQUERY_ERROR};
+use ratatui::{backend::CrosstermBackend, style::Color, symbols::Marker, Terminal};
+use reqwest::{Certificate, Client, ClientBuilder};
+use secret::Secret;
+use serde::Deserialize;
 use session::Session;
-use tokio::{runtime, time};
-use tokio_stream::{wrappers::IntervalStream, StreamExt};
-use ui::PALETTES;
+use tokio::{
+    runtime,
+    sync::mpsc::{channel, Sender},
+    task::JoinHandle,
+    time,
+};
+use ui::{default_highlights, parse_marker, Highlight, PALETTES};
 
 use std::{
-    collections::HashSet,
-    env,
+    collections::{BTreeMap, HashMap, HashSet},
+    env, fs,
     io::{self, stdout},
-    path::PathBuf,
-    sync::mpsc::{channel, Sender},
+    net::TcpListener as StdTcpListener,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
-const DEFAULT_THEME: &str = "5";
+const DEFAULT_THEME: usize = 5;
+const DEFAULT_MAX_FACETS: usize = 12;
+const DEFAULT_REFRESH_INTERVAL: u64 = 10;
+const DEFAULT_CHANNEL_CAPACITY: usize = 64;
+const DEFAULT_TICK_RATE: u64 = 250;
+// New Relic keeps most data for 8 days unless the account has extended retention
+const DEFAULT_RETENTION_DAYS: u64 = 8;
+// Idle connections kept open for reuse, outliving the refresh interval so each refresh reuses them
+const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 4;
+const DEFAULT_POOL_IDLE_TIMEOUT: u64 = 90;
+const DEFAULT_LOG_COLUMNS: [&str; 3] = ["timestamp", "level", "message"];
 const NEW_RELIC_ENDPOINT: &str = "https://api.newrelic.com/graphql";
+const NEW_RELIC_EU_ENDPOINT: &str = "https://api.eu.newrelic.com/graphql";
 
 pub struct Config {
     account: String,
-    api_key: String,
+    api_key: Secret,
+    endpoint: &'static str,
+    profile: Option<String>,
     session: Session,
+    config_dir: PathBuf,
     theme: Theme,
+    max_facets: usize,
+    log_columns: Vec<String>,
+    marker: Marker,
+    // Seconds, shared with the refresh task so a reloaded interval applies from its next tick
+    refresh_interval: Arc<AtomicU64>,
+    splash: bool,
+    precision: Option<usize>,
+    graph_min_ratio: Option<f64>,
+    compact_logs: bool,
+    channel_capacity: usize,
+    tick_rate: Duration,
+    highlights: Vec<Highlight>,
+    facet_sort: FacetSort,
+    retention_days: u64,
+    pool_max_idle_per_host: usize,
+    pool_idle_timeout: Duration,
 }
 
-impl Config {
-    fn load() -> Box<Self> {
-        let account = env::var("NR_ACCOUNT").expect("ERROR: No NR_ACCOUNT provided!");
-        let api_key = env::var("NR_API_KEY").expect("ERROR: No NR_API_KEY provided!");
-        let home_dir = env::var("HOME").expect("ERROR: $HOME could not be read");
-        let palette = env::var("THEME")
-            .unwrap_or(DEFAULT_THEME.into())
-            .parse::<usize>()
-            .expect("ERROR: Invalid THEME value provided!");
+// Optional overrides read from config.yaml, environment variables take precedence
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    theme: Option<usize>,
+    max_facets: Option<usize>,
+    log_columns: Option<Vec<String>>,
+    marker: Option<String>,
+    refresh_interval: Option<u64>,
+    splash: Option<bool>,
+    precision: Option<usize>,
+    graph_min_ratio: Option<f64>,
+    compact_logs: Option<bool>,
+    channel_capacity: Option<usize>,
+    tick_rate: Option<u64>,
+    highlights: Option<Vec<HighlightRule>>,
+    facet_sort: Option<String>,
+    retention_days: Option<u64>,
+    pool_max_idle_per_host: Option<usize>,
+    pool_idle_timeout: Option<u64>,
+    profiles: BTreeMap<String, Profile>,
+}
+
+// A log detail highlight, e.g. `{ substring: tenantId, colour: "#60a5fa", bold: true }`
+#[derive(Deserialize)]
+struct HighlightRule {
+    substring: String,
+    #[serde(default)]
+    with: Vec<String>,
+    colour: Option<String>,
+    #[serde(default)]
+    bold: bool,
+}
+
+// A named account, e.g. prod or staging, selected with --profile or NR_PROFILE
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Profile {
+    account: Option<String>,
+    api_key: Option<String>,
+    region: Option<String>,
+    theme: Option<usize>,
+}
+
+impl ConfigFile {
+    fn read(config_path: &Path) -> Result<Self> {
+        match fs::read_to_string(config_path) {
+            Ok(yaml) => serde_yaml::from_str::<ConfigFile>(&yaml)
+                .map_err(|e| anyhow!("Invalid config file: {}", e)),
+            Err(_) => Ok(ConfigFile::default()),
+        }
+    }
+
+    fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles.get(name).ok_or(anyhow!(
+            "No profile named '{}' in config.yaml (found: {})",
+            name,
+            self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+        ))
+    }
+}
+
+// Settings that can be reloaded while the app is running
+struct Settings {
+    theme: Theme,
+    max_facets: usize,
+    log_columns: Vec<String>,
+    marker: Marker,
+    refresh_interval: Duration,
+    splash: bool,
+    precision: Option<usize>,
+    graph_min_ratio: Option<f64>,
+    compact_logs: bool,
+    channel_capacity: usize,
+    tick_rate: Duration,
+    highlights: Vec<Highlight>,
+    facet_sort: FacetSort,
+    retention_days: u64,
+}
+
+impl Settings {
+    fn load(config_path: &Path, profile: Option<&str>) -> Result<Self> {
+        let file = ConfigFile::read(config_path)?;
+        let profile_theme = match profile {
+            Some(name) => file.profile(name)?.theme,
+            None => None,
+        };
+
+        let palette = setting("THEME", profile_theme.or(file.theme), DEFAULT_THEME)?;
+        let palette = PALETTES
+            .get(palette)
+            .ok_or(anyhow!("THEME must be 0-{}", PALETTES.len() - 1))?;
         let theme = Theme {
-            focus_fg: PALETTES[palette].c200,
-            chart_fg: PALETTES[palette].c400,
+            focus_fg: palette.c200,
+            chart_fg: palette.c400,
+        };
+        let max_facets = setting("MAX_FACETS", file.max_facets, DEFAULT_MAX_FACETS)?;
+        let log_columns = match env::var("LOG_COLUMNS") {
+            Ok(columns) => columns.split(',').map(|c| c.trim().to_owned()).collect(),
+            Err(_) => file
+                .log_columns
+                .unwrap_or(DEFAULT_LOG_COLUMNS.map(String::from).to_vec()),
+        };
+
+        let marker = match env::var("MARKER").ok().or(file.marker) {
+            Some(name) => parse_marker(&name).ok_or(anyhow!("Invalid MARKER value provided!"))?,
+            None => Marker::Braille,
+        };
+
+        let refresh_interval = Duration::from_secs(setting(
+            "REFRESH_INTERVAL",
+            file.refresh_interval,
+            DEFAULT_REFRESH_INTERVAL,
+        )?);
+        if refresh_interval.is_zero() {
+            return Err(anyhow!("REFRESH_INTERVAL must be at least 1"));
+        }
+
+        let splash = setting("SPLASH", file.splash, true)?;
+
+        // Decimal places on the y-axis, derived from the data when unset
+        let precision = match env::var("PRECISION") {
+            Ok(value) => Some(
+                value
+                    .parse::<usize>()
+                    .map_err(|_| anyhow!("Invalid PRECISION value provided!"))?,
+            ),
+            Err(_) => file.precision,
+        };
+
+        // Letterboxes graphs wider than this fraction of their height allows, off when unset
+        let graph_min_ratio = match env::var("GRAPH_MIN_RATIO") {
+            Ok(value) => Some(
+                value
+                    .parse::<f64>()
+                    .map_err(|_| anyhow!("Invalid GRAPH_MIN_RATIO value provided!"))?,
+            ),
+            Err(_) => file.graph_min_ratio,
+        };
+        // NaN and infinity would narrow every graph to nothing
+        if graph_min_ratio.is_some_and(|ratio| !ratio.is_finite() || ratio <= 0_f64) {
+            return Err(anyhow!("Invalid GRAPH_MIN_RATIO value provided!"));
+        }
+
+        let compact_logs = setting("COMPACT_LOGS", file.compact_logs, false)?;
+
+        let channel_capacity = setting(
+            "CHANNEL_CAPACITY",
+            file.channel_capacity,
+            DEFAULT_CHANNEL_CAPACITY,
+        )?;
+        if channel_capacity == 0 {
+            return Err(anyhow!("CHANNEL_CAPACITY must be at least 1"));
+        }
+
+        // How often the screen is redrawn when idle, keeping spinners and "now" moving
+        let tick_rate =
+            Duration::from_millis(setting("TICK_RATE", file.tick_rate, DEFAULT_TICK_RATE)?);
+
+        // Replaces the built-in highlights rather than adding to them
+        let highlights = match file.highlights {
+            Some(rules) => rules
+                .into_iter()
+                .map(|rule| {
+                    let colour = match rule.colour {
+                        Some(colour) => Some(
+                            colour
+                                .parse::<Color>()
+                                .map_err(|_| anyhow!("Invalid highlight colour '{}'", colour))?,
+                        ),
+                        None => None,
+                    };
+                    Ok(Highlight {
+                        substring: rule.substring,
+                        with: rule.with,
+                        colour,
+                        bold: rule.bold,
+                    })
+                })
+                .collect::<Result<Vec<Highlight>>>()?,
+            None => default_highlights(),
         };
 
-        // Construct the path to Session directory
-        let mut session_path = PathBuf::from(home_dir);
+        let facet_sort = match env::var("FACET_SORT").ok().or(file.facet_sort) {
+            Some(name) => name
+                .parse::<FacetSort>()
+                .map_err(|_| anyhow!("Invalid FACET_SORT value provided!"))?,
+            None => FacetSort::default(),
+        };
+
+        let retention_days = setting(
+            "RETENTION_DAYS",
+            file.retention_days,
+            DEFAULT_RETENTION_DAYS,
+        )?;
+
+        Ok(Settings {
+            theme,
+            max_facets,
+            log_columns,
+            marker,
+            refresh_interval,
+            splash,
+            precision,
+            graph_min_ratio,
+            compact_logs,
+            channel_capacity,
+            tick_rate,
+            highlights,
+            facet_sort,
+            retention_days,
+        })
+    }
+}
+
+fn setting<T: FromStr>(key: &str, file_value: Option<T>, default: T) -> Result<T> {
+    match env::var(key) {
+        Ok(value) => value
+            .parse::<T>()
+            .map_err(|_| anyhow!("Invalid {} value provided!", key)),
+        Err(_) => Ok(file_value.unwrap_or(default)),
+    }
+}
+
+impl Config {
+    // Demo mode runs without credentials, a profile's credentials take precedence over the environment
+    fn load(demo: bool, profile: Option<String>) -> Box<Self> {
+        let home_dir = env::var("HOME").expect("ERROR: $HOME could not be read");
+
+        // Construct the path to the config directory
+        let mut config_dir = PathBuf::from(home_dir);
         // TODO: Implement for non-MacOS
-        session_path.push("Library/Application Support/xrelic/session.yaml");
+        config_dir.push("Library/Application Support/xrelic");
+
+        Config::load_from(config_dir, demo, profile)
+    }
+
+    // Reads config.yaml and keeps the session in `config_dir`
+    fn load_from(config_dir: PathBuf, demo: bool, profile: Option<String>) -> Box<Self> {
+        let config_path = config_dir.join("config.yaml");
+
+        let file = ConfigFile::read(&config_path).expect("ERROR: Could not load config!");
+        let active = profile
+            .as_deref()
+            .map(|name| file.profile(name).expect("ERROR: Could not load profile!"));
+
+        let credential =
+            |key: &str, profile_value: Option<&String>| match (profile_value, env::var(key)) {
+                (Some(value), _) => value.to_owned(),
+                (None, Ok(value)) => value,
+                (None, Err(_)) if demo => String::new(),
+                (None, Err(_)) => panic!("ERROR: No {} provided!", key),
+            };
+        let account = credential("NR_ACCOUNT", active.and_then(|p| p.account.as_ref()));
+        let api_key = Secret::new(credential(
+            "NR_API_KEY",
+            active.and_then(|p| p.api_key.as_ref()),
+        ));
+        let endpoint = match active.and_then(|p| p.region.as_deref()) {
+            None | Some("us") => NEW_RELIC_ENDPOINT,
+            Some("eu") => NEW_RELIC_EU_ENDPOINT,
+            Some(region) => panic!("ERROR: Unknown region '{}', expected us or eu", region),
+        };
+
+        let settings = Settings::load(&config_path, profile.as_deref())
+            .expect("ERROR: Could not load config!");
+
+        // Applied when the HTTP client is built, so not reloadable
+        let pool_max_idle_per_host = setting(
+            "POOL_MAX_IDLE_PER_HOST",
+            file.pool_max_idle_per_host,
+            DEFAULT_POOL_MAX_IDLE_PER_HOST,
+        )
+        .expect("ERROR: Could not load config!");
+        let pool_idle_timeout = Duration::from_secs(
+            setting(
+                "POOL_IDLE_TIMEOUT",
+                file.pool_idle_timeout,
+                DEFAULT_POOL_IDLE_TIMEOUT,
+            )
+            .expect("ERROR: Could not load config!"),
+        );
 
         let session = Session {
             queries: None,
-            session_path,
+            session_path: config_dir.join("session.yaml"),
             is_loaded: false,
         };
 
         Box::new(Config {
             account,
             api_key,
+            endpoint,
+            profile,
             session,
-            theme,
+            config_dir,
+            theme: settings.theme,
+            max_facets: settings.max_facets,
+            log_columns: settings.log_columns,
+            marker: settings.marker,
+            refresh_interval: Arc::new(AtomicU64::new(settings.refresh_interval.as_secs())),
+            splash: settings.splash,
+            precision: settings.precision,
+            graph_min_ratio: settings.graph_min_ratio,
+            compact_logs: settings.compact_logs,
+            channel_capacity: settings.channel_capacity,
+            tick_rate: settings.tick_rate,
+            highlights: settings.highlights,
+            facet_sort: settings.facet_sort,
+            retention_days: settings.retention_days,
+            pool_max_idle_per_host,
+            pool_idle_timeout,
         })
     }
+
+    // Re-applies the live settings, keeping the current ones if the new ones are invalid
+    pub fn reload(&mut self) -> Result<()> {
+        let settings = Settings::load(
+            &self.config_dir.join("config.yaml"),
+            self.profile.as_deref(),
+        )?;
+        self.theme = settings.theme;
+        self.max_facets = settings.max_facets;
+        self.log_columns = settings.log_columns;
+        self.marker = settings.marker;
+        self.refresh_interval
+            .store(settings.refresh_interval.as_secs(), Ordering::Relaxed);
+        self.splash = settings.splash;
+        self.precision = settings.precision;
+        self.graph_min_ratio = settings.graph_min_ratio;
+        self.tick_rate = settings.tick_rate;
+        self.highlights = settings.highlights;
+        self.facet_sort = settings.facet_sort;
+        self.retention_days = settings.retention_days;
+
+        Ok(())
+    }
 }
 
 fn main() -> io::Result<()> {
+    let args = env::args().collect::<Vec<String>>();
+    let watch_args = watch::parse_args(&args).unwrap_or_else(|e| {
+        eprintln!("ERROR: {}", e);
+        process::exit(2);
+    });
+
+    let demo = args.iter().any(|arg| arg == "--demo");
+    let bundle = args.iter().position(|arg| arg == "--import").map(|i| {
+        let path = args.get(i + 1).map(PathBuf::from).unwrap_or_else(|| {
+            eprintln!("ERROR: --import requires a bundle path");
+            process::exit(2);
+        });
+        Bundle::read(&path).unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not import bundle: {}", e);
+            process::exit(2);
+        })
+    });
+
+    let offline = demo || bundle.is_some();
+
+    let profile = match args.iter().position(|arg| arg == "--profile") {
+        Some(i) => Some(args.get(i + 1).cloned().unwrap_or_else(|| {
+            eprintln!("ERROR: --profile requires a profile name");
+            process::exit(2);
+        })),
+        None => env::var("NR_PROFILE").ok(),
+    };
+
+    // Read-only view of another instance's session, reloaded whenever it's saved
+    let follow = args.iter().position(|arg| arg == "--follow").map(|i| {
+        args.get(i + 1).map(PathBuf::from).unwrap_or_else(|| {
+            eprintln!("ERROR: --follow requires a session file");
+            process::exit(2);
+        })
+    });
+
+    // Health endpoint for unattended dashboards, bound now so a bad address is reported before the TUI starts
+    let metrics_listener = args
+        .iter()
+        .position(|arg| arg == "--metrics-addr")
+        .map(|i| {
+            let addr = args.get(i + 1).unwrap_or_else(|| {
+                eprintln!("ERROR: --metrics-addr requires an address, e.g. 127.0.0.1:9464");
+                process::exit(2);
+            });
+            StdTcpListener::bind(addr).unwrap_or_else(|e| {
+                eprintln!("ERROR: Could not listen on {}: {}", addr, e);
+                process::exit(2);
+            })
+        });
+
+    let mut config = Config::load(offline, profile);
+    if let Some(path) = &follow {
+        config.session.session_path = path.clone();
+    }
+    if let Err(e) = logger::setup_logging(&config.config_dir) {
+        eprintln!("WARNING: Logging disabled: {}", e);
+    }
+
+    // Built before the TUI starts so certificate problems are readable
+    let newrelic_client = match offline {
+        true => None,
+        false => Some(build_client(&config).unwrap_or_else(|e| {
+            eprintln!("ERROR: {}", e);
+            process::exit(2);
+        })),
+    };
+
+    // Non-interactive mode, exits non-zero once the threshold is breached
+    if let Some(watch_args) = watch_args {
+        let client = newrelic_client.unwrap_or_else(|| {
+            eprintln!("ERROR: --watch needs New Relic credentials");
+            process::exit(2);
+        });
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let code = runtime
+            .block_on(watch::watch(
+                watch_args,
+                client,
+                Duration::from_secs(config.refresh_interval.load(Ordering::Relaxed)),
+            ))
+            .unwrap_or_else(|e| {
+                eprintln!("ERROR: {}", e);
+                2
+            });
+        process::exit(code);
+    }
+
     enable_raw_mode()?;
     stdout().execute(EnterAlternateScreen)?;
-
-    let config = Config::load();
-    let mut newrelic_client = NewRelicClient::builder();
-    newrelic_client
-        .url(NEW_RELIC_ENDPOINT)
-        .account(&config.account)
-        .api_key(&config.api_key)
-        .http_client(Client::builder());
+    // So a multi-line paste isn't read as a series of keys, Enter included
+    stdout().execute(EnableBracketedPaste)?;
 
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
     terminal.show_cursor()?;
@@ -98,104 +547,589 @@ fn main() -> io::Result<()> {
         .thread_name("data")
         .enable_all()
         .build()?;
-    let (data_tx, data_rx) = channel::<PayloadType>();
+    // Bounded so a burst of large payloads holds up the backend until the UI catches up
+    let (data_tx, data_rx) = channel::<PayloadType>(config.channel_capacity);
     let (ui_tx, ui_rx) = unbounded::<UIEvent>();
+    let metrics = Arc::new(Metrics::default());
+    if let Some(listener) = metrics_listener {
+        let metrics = metrics.clone();
+        backend.spawn(async move {
+            if let Err(e) = metrics::serve(listener, metrics).await {
+                warn!("Metrics endpoint stopped: {}", e);
+            }
+        });
+    }
+    if let Some(path) = follow.clone() {
+        let data_tx = data_tx.clone();
+        backend.spawn(follow::watch(path, follow::FOLLOW_INTERVAL, move || {
+            let data_tx = data_tx.clone();
+            async move {
+                _ = data_tx.send(PayloadType::SessionChanged).await;
+            }
+        }));
+    }
     {
-        // Query events
-        let newrelic_client = newrelic_client.clone();
+        // Query events, imported sessions stay offline so nothing answers them
         let data_tx = data_tx.clone();
         let ui_tx = ui_tx.clone();
-        backend.spawn(async move {
-            _ = listen(newrelic_client, data_tx, ui_rx).await;
-        });
+        let refreshing = Arc::new(AtomicBool::new(false));
+        let listener_refreshing = refreshing.clone();
+        if let Some(newrelic_client) = newrelic_client {
+            backend.spawn(async move {
+                _ = listen(
+                    newrelic_client,
+                    data_tx,
+                    ui_rx,
+                    listener_refreshing,
+                    metrics,
+                )
+                .await;
+            });
+        } else if demo {
+            backend.spawn(async move {
+                _ = demo::listen(data_tx, ui_rx, listener_refreshing).await;
+            });
+            for query in demo::DEMO_QUERIES {
+                _ = ui_tx.send(UIEvent::AddQuery(query.to_owned()));
+            }
+        }
 
-        // Refresh events
-        // backend.spawn(async move {
-        //     let mut stream = IntervalStream::new(time::interval(Duration::from_secs(5)));
-        //     while let Some(_ts) = stream.next().await {
-        //         _ = ui_tx.send(UIEvent::RefreshData);
-        //     }
-        // });
+        // Refresh events, skipping ticks while the previous refresh is still running
+        let refresh_interval = config.refresh_interval.clone();
+        if bundle.is_none() {
+            backend.spawn(async move {
+                loop {
+                    time::sleep(Duration::from_secs(
+                        refresh_interval.load(Ordering::Relaxed),
+                    ))
+                    .await;
+                    request_refresh(&refreshing, &ui_tx);
+                }
+            });
+        }
     }
 
-    let app = App::new(config, data_rx, ui_tx);
+    let mut app = App::new(config, data_rx, ui_tx);
+    if let Some(bundle) = bundle {
+        app.import_bundle(bundle);
+    }
+    if let Some(path) = follow {
+        app.follow(path);
+    }
     app.run(&mut terminal).unwrap();
 
     disable_raw_mode()?;
+    stdout().execute(DisableBracketedPaste)?;
     stdout().execute(LeaveAlternateScreen)?;
 
     Ok(())
 }
 
+fn build_client(config: &Config) -> Result<NewRelicClient> {
+    let mut newrelic_client = NewRelicClient::builder();
+    newrelic_client
+        .url(config.endpoint)
+        .account(&config.account)
+        .api_key(&config.api_key)
+        .http_client(http_client_builder(config)?);
+
+    Ok(newrelic_client)
+}
+
+// Trusts an extra root certificate (NR_CA_CERT) for TLS-intercepting proxies
+fn http_client_builder(config: &Config) -> Result<ClientBuilder> {
+    let mut builder = Client::builder()
+        .pool_max_idle_per_host(config.pool_max_idle_per_host)
+        .pool_idle_timeout(config.pool_idle_timeout);
+
+    if let Ok(path) = env::var("NR_CA_CERT") {
+        builder = builder.add_root_certificate(root_certificate(&path)?);
+    }
+
+    if env::var("NR_ACCEPT_INVALID_CERTS").is_ok_and(|v| v == "true") {
+        eprintln!("WARNING: NR_ACCEPT_INVALID_CERTS is set, TLS certificates will not be verified");
+        warn!("TLS certificate verification is disabled");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    Ok(builder)
+}
+
+fn root_certificate(path: &str) -> Result<Certificate> {
+    let pem = fs::read(path).map_err(|e| anyhow!("Could not read NR_CA_CERT {}: {}", path, e))?;
+    Certificate::from_pem(&pem)
+        .map_err(|e| anyhow!("Invalid certificate in NR_CA_CERT {}: {}", path, e))
+}
+
+// Asks the listener to refresh unless its last refresh is still running, which clears the flag
+fn request_refresh(refreshing: &AtomicBool, ui_tx: &CrossBeamSender<UIEvent>) -> bool {
+    if refreshing.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    _ = ui_tx.send(UIEvent::RefreshData);
+    true
+}
+
 async fn listen(
     client: NewRelicClient,
     data_tx: Sender<PayloadType>,
     ui_rx: CrossBeamReceiver<UIEvent>,
+    refreshing: Arc<AtomicBool>,
+    metrics: Arc<Metrics>,
 ) -> Result<(), Error> {
+    // Warn up front rather than failing every query if the response shape has changed
+    match client.check_schema().await {
+        Ok(true) => {}
+        Ok(false) => {
+            let warning = format!(
+                "{}, tui {} may need updating",
+                SCHEMA_WARNING,
+                env!("CARGO_PKG_VERSION")
+            );
+            warn!("{}", warning);
+            data_tx
+                .send(PayloadType::Error {
+                    query: None,
+                    message: warning,
+                })
+                .await?;
+        }
+        Err(e) => debug!("Schema check failed: {}", e),
+    }
+
     let mut queries: HashSet<String> = HashSet::new();
+    let mut tasks: HashMap<String, JoinHandle<()>> = HashMap::new();
+    // When each query was first dispatched, which a frozen window is resolved against
+    let mut dispatched: HashMap<String, DateTime<Utc>> = HashMap::new();
+    let mut frozen = false;
     loop {
         while let Some(event) = ui_rx.try_iter().next() {
             match event {
                 UIEvent::AddQuery(query) => {
                     queries.insert(query.to_owned());
-                    let q = query
-                        .to_nrql()
-                        .map_or_else(|_| QueryType::Log(query.to_owned()), QueryType::Timeseries);
-
-                    match q {
-                        QueryType::Timeseries(x) => {
-                            let result = query_timeseries(x, client.clone()).await;
-                            if let Ok(data) = result {
-                                if data.data.is_empty() {
-                                    data_tx.send(PayloadType::None);
-                                } else {
-                                    let payload = PayloadType::Timeseries(data);
-                                    data_tx.send(payload)?;
-                                }
-                            }
+                    dispatched.entry(query.to_owned()).or_insert(Utc::now());
+                    tasks.retain(|_, task| !task.is_finished());
+
+                    // Run each query on its own task so slow ones can be cancelled
+                    let client = client.clone();
+                    let data_tx = data_tx.clone();
+                    let metrics = metrics.clone();
+                    let task_query = query.clone();
+                    let task = tokio::spawn(async move {
+                        let payload = fetch(&task_query, &client).await;
+                        metrics.record(&payload);
+                        _ = data_tx.send(payload).await;
+                    });
+                    tasks.insert(query, task);
+                }
+                UIEvent::DeleteQuery(query) => {
+                    queries.remove(&query);
+                    dispatched.remove(&query);
+                }
+                UIEvent::CancelQuery(query) => {
+                    debug!("Cancelling query: {}", query);
+                    queries.remove(&query);
+                    dispatched.remove(&query);
+                    if let Some(task) = tasks.remove(&query) {
+                        task.abort();
+                    }
+                }
+                UIEvent::Keyset(source) => {
+                    debug!("Fetching keyset for: {}", source);
+                    let client = client.clone();
+                    let data_tx = data_tx.clone();
+                    tokio::spawn(async move {
+                        _ = data_tx
+                            .send(match query_keyset(source, client).await {
+                                Ok(keys) => PayloadType::Keyset(keys),
+                                Err(e) => PayloadType::Error {
+                                    query: None,
+                                    message: e.to_string(),
+                                },
+                            })
+                            .await;
+                    });
+                }
+                UIEvent::Applications(name) => {
+                    debug!("Searching applications for: {}", name);
+                    let client = client.clone();
+                    let data_tx = data_tx.clone();
+                    tokio::spawn(async move {
+                        _ = data_tx
+                            .send(match query_applications(name, client).await {
+                                Ok(applications) => PayloadType::Applications(applications),
+                                Err(e) => PayloadType::Error {
+                                    query: None,
+                                    message: e.to_string(),
+                                },
+                            })
+                            .await;
+                    });
+                }
+                UIEvent::FreezeWindows(freeze) => {
+                    debug!("Freezing query windows: {}", freeze);
+                    frozen = freeze;
+                }
+                UIEvent::RefreshData => {
+                    debug!("Refreshing {} queries", queries.len());
+                    for query in &queries {
+                        if let QueryType::Log(_) = QueryType::from(query.as_str()) {
+                            continue;
                         }
-                        QueryType::Log(x) => {
-                            let result = query_log(x, client.clone()).await;
-                            if let Ok(data) = result {
-                                if data.logs.is_empty() {
-                                    data_tx.send(PayloadType::None)?;
-                                } else {
-                                    let payload = PayloadType::Log(data);
-                                    data_tx.send(payload)?;
-                                }
+                        let payload = match dispatched
+                            .get(query)
+                            .filter(|_| frozen)
+                            .and_then(|at| frozen_query(query, *at))
+                        {
+                            Some(frozen_query) => {
+                                fetch(&frozen_query, &client).await.refiled(query)
                             }
+                            None => fetch(query, &client).await,
+                        };
+                        metrics.record(&payload);
+                        // Empty or failed refreshes keep the last data on screen
+                        match payload {
+                            PayloadType::None(_) | PayloadType::Error { .. } => {}
+                            payload => data_tx.send(payload).await?,
                         }
                     }
+                    refreshing.store(false, Ordering::SeqCst);
                 }
-                UIEvent::DeleteQuery(query) => {
-                    queries.remove(&query);
-                }
-                UIEvent::RefreshData => {} // UIEvent::RefreshData => {
-                                           //     for query in &queries {
-                                           //         let q = query.to_nrql().map_or_else(
-                                           //             |_| QueryType::Log(query.to_owned()),
-                                           //             QueryType::Timeseries,
-                                           //         );
-
-                                           //         let data = match q {
-                                           //             QueryType::Timeseries(x) => {
-                                           //                 PayloadType::Timeseries(query_timeseries(x, client.clone()).await?)
-                                           //             }
-                                           //             QueryType::Log(query) => {
-                                           //                 PayloadType::Log(query_log(query, client.clone()).await?)
-                                           //                 // Mock data
-                                           //                 // PayloadType::Log(LogPayload {
-                                           //                 //     // logs: BTreeMap::from([("Testing".to_string(), "".to_string())]),
-                                           //                 // })
-                                           //                 // PayloadType::Log(query_log(x, client.clone()).await.unwrap())
-                                           //             }
-                                           //         };
-
-                                           //         data_tx.send(data)?
-                                           //     }
-                                           // }
             }
         }
+        metrics.set_active_queries(queries.len());
         time::sleep(Duration::from_millis(30)).await;
     }
 }
+
+// Runs a query and wraps the result for the UI, reporting failures as errors
+async fn fetch(query: &str, client: &NewRelicClient) -> PayloadType {
+    let id = new_request_id();
+    let client = &client.with_request_id(&id);
+    info!("[{}] Dispatching query: {}", id, query);
+
+    // Carried by empty and failed results too, so the UI knows the query finished
+    let query_type = QueryType::from(query);
+    let key = query_type.key();
+    let payload = match query_type {
+        QueryType::Timeseries(x) => query_timeseries(x, client.clone()).await.map(|data| {
+            if data.data.is_empty() {
+                PayloadType::None(key.clone())
+            } else {
+                PayloadType::Timeseries(data)
+            }
+        }),
+        QueryType::Raw(x) if is_timeseries(&x) => {
+            query_raw_timeseries(x, client.clone()).await.map(|data| {
+                if data.data.is_empty() {
+                    PayloadType::None(key.clone())
+                } else {
+                    PayloadType::Timeseries(data)
+                }
+            })
+        }
+        QueryType::Raw(_) => Ok(PayloadType::Error {
+            query: Some(key.clone()),
+            message: SUBQUERY_ERROR.to_owned(),
+        }),
+        QueryType::Histogram(x) => query_histogram(x, client.clone()).await.map(|data| {
+            if data.buckets.is_empty() {
+                PayloadType::None(key.clone())
+            } else {
+                PayloadType::Histogram(data)
+            }
+        }),
+        QueryType::Log(x) => query_log(x, client.clone()).await.map(|data| {
+            if data.logs.is_empty() {
+                PayloadType::None(key.clone())
+            } else {
+                PayloadType::Log(data)
+            }
+        }),
+    };
+
+    match payload {
+        Ok(payload) => {
+            info!("[{}] Query completed: {}", id, query);
+            payload
+        }
+        Err(e) => {
+            warn!("[{}] Query failed: {}: {}", id, query, e);
+            PayloadType::Error {
+                query: Some(key),
+                message: e.to_string(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh directory for a test's files, e.g. its config.yaml and session
+    pub fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("old-relic-{}-{}", name, process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("ERROR: Could not create test directory");
+        dir
+    }
+
+    // A demo mode app keeping its files in a fresh directory, with the other ends of its channels
+    pub fn test_app(name: &str) -> (App, Sender<PayloadType>, CrossBeamReceiver<UIEvent>) {
+        let config = Config::load_from(temp_dir(name), true, None);
+        let (data_tx, data_rx) = channel::<PayloadType>(config.channel_capacity);
+        let (ui_tx, ui_rx) = unbounded::<UIEvent>();
+        (App::new(config, data_rx, ui_tx), data_tx, ui_rx)
+    }
+
+    #[test]
+    fn reload_applies_a_changed_theme() {
+        let dir = temp_dir("reload-theme");
+        let mut config = Config::load_from(dir.clone(), true, None);
+        assert_eq!(config.theme.focus_fg, PALETTES[DEFAULT_THEME].c200);
+
+        fs::write(dir.join("config.yaml"), "theme: 1\n").unwrap();
+        config.reload().unwrap();
+
+        assert_eq!(config.theme.focus_fg, PALETTES[1].c200);
+        assert_eq!(config.theme.chart_fg, PALETTES[1].c400);
+    }
+
+    #[test]
+    fn reload_shares_the_refresh_interval_with_the_refresh_task() {
+        let dir = temp_dir("reload-interval");
+        let mut config = Config::load_from(dir.clone(), true, None);
+        let refresh_interval = config.refresh_interval.clone();
+
+        fs::write(dir.join("config.yaml"), "refresh_interval: 30\n").unwrap();
+        config.reload().unwrap();
+
+        assert_eq!(refresh_interval.load(Ordering::Relaxed), 30);
+    }
+
+    #[test]
+    fn invalid_reload_keeps_the_current_settings() {
+        let dir = temp_dir("reload-invalid");
+        let mut config = Config::load_from(dir.clone(), true, None);
+
+        fs::write(dir.join("config.yaml"), "theme: [not a number\n").unwrap();
+
+        assert!(config.reload().is_err());
+        assert_eq!(config.theme.focus_fg, PALETTES[DEFAULT_THEME].c200);
+    }
+
+    #[test]
+    fn graph_min_ratio_must_be_finite_and_positive() {
+        let dir = temp_dir("graph-min-ratio");
+        let mut config = Config::load_from(dir.clone(), true, None);
+        let config_path = dir.join("config.yaml");
+
+        for ratio in [".nan", ".inf", "-.inf", "0", "-0.5"] {
+            fs::write(&config_path, format!("graph_min_ratio: {}\n", ratio)).unwrap();
+            let error = Settings::load(&config_path, None).err().unwrap();
+            assert_eq!(error.to_string(), "Invalid GRAPH_MIN_RATIO value provided!");
+            assert!(config.reload().is_err());
+        }
+
+        fs::write(&config_path, "graph_min_ratio: 0.1\n").unwrap();
+        config.reload().unwrap();
+        assert_eq!(config.graph_min_ratio, Some(0.1));
+    }
+
+    #[test]
+    fn marker_setting_maps_to_its_marker() {
+        let dir = temp_dir("marker");
+        let config_path = dir.join("config.yaml");
+        for (name, marker) in [
+            ("braille", Marker::Braille),
+            ("Dot", Marker::Dot),
+            (" block ", Marker::Block),
+        ] {
+            fs::write(&config_path, format!("marker: '{}'\n", name)).unwrap();
+            assert_eq!(Settings::load(&config_path, None).unwrap().marker, marker);
+        }
+
+        fs::write(&config_path, "marker: bar\n").unwrap();
+        assert!(Settings::load(&config_path, None).is_err());
+    }
+
+    #[test]
+    fn out_of_range_theme_is_an_error() {
+        let dir = temp_dir("theme-range");
+        let config_path = dir.join("config.yaml");
+        fs::write(&config_path, "theme: 99\n").unwrap();
+
+        let error = Settings::load(&config_path, None).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!("THEME must be 0-{}", PALETTES.len() - 1)
+        );
+    }
+
+    #[test]
+    fn overlapping_ticks_request_one_refresh() {
+        let refreshing = AtomicBool::new(false);
+        let (ui_tx, ui_rx) = unbounded::<UIEvent>();
+
+        assert!(request_refresh(&refreshing, &ui_tx));
+        assert!(!request_refresh(&refreshing, &ui_tx));
+        assert_eq!(ui_rx.try_iter().count(), 1);
+
+        // The listener finishing its refresh lets the next tick through
+        refreshing.store(false, Ordering::SeqCst);
+        assert!(request_refresh(&refreshing, &ui_tx));
+        assert_eq!(ui_rx.try_iter().count(), 1);
+    }
+
+    const TEST_CA: &str = "-----BEGIN CERTIFICATE-----
+MIIBjjCCATWgAwIBAgIUBOBV8rodlzditFr/6W94pyuDotkwCgYIKoZIzj0EAwIw
+HDEaMBgGA1UEAwwRb2xkLXJlbGljIHRlc3QgQ0EwIBcNMjYxMDE2MTA0NjU0WhgP
+MjEyNjA5MjIxMDQ2NTRaMBwxGjAYBgNVBAMMEW9sZC1yZWxpYyB0ZXN0IENBMFkw
+EwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEp5jPdJHZDNTqqtfIon7Jij7lpIiajpkS
+LrQ4kQ9az5+/z1qURKy0q1QuQqzM8GCzVHRMc8xe4Zm+3hpffzZNQaNTMFEwHQYD
+VR0OBBYEFJuALG2nbgIzZEmfpBXxkvSvmtc4MB8GA1UdIwQYMBaAFJuALG2nbgIz
+ZEmfpBXxkvSvmtc4MA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDRwAwRAIg
+EvGOqF3jKPEHSNR39BIT7nQAWztcDDNPQuJJha+JCzkCIEAbH1ipDp1PdAAmhT2t
+v05+V1bG+MuE1WvkyF4HuX0O
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn configured_ca_certificate_is_trusted() {
+        let path = temp_dir("ca-cert").join("ca.pem");
+        fs::write(&path, TEST_CA).unwrap();
+
+        let certificate = root_certificate(path.to_str().unwrap()).unwrap();
+        assert!(Client::builder()
+            .add_root_certificate(certificate)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn missing_ca_certificate_is_a_clear_error() {
+        let path = temp_dir("ca-cert-missing").join("ca.pem");
+        let path = path.to_str().unwrap();
+
+        let error = root_certificate(path).err().unwrap().to_string();
+        assert!(error.starts_with(&format!("Could not read NR_CA_CERT {}", path)));
+    }
+
+    #[test]
+    fn selected_profile_provides_account_and_region() {
+        let dir = temp_dir("profile-selected");
+        fs::write(
+            dir.join("config.yaml"),
+            "profiles:\n  prod:\n    account: \"1234\"\n    api_key: NRAK-PROD\n    region: eu\n  staging:\n    account: \"5678\"\n",
+        )
+        .unwrap();
+
+        let config = Config::load_from(dir.clone(), true, Some(String::from("prod")));
+        assert_eq!(config.account, "1234");
+        assert_eq!(config.endpoint, NEW_RELIC_EU_ENDPOINT);
+
+        let error = Settings::load(&dir.join("config.yaml"), Some("dev"))
+            .err()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            error,
+            "No profile named 'dev' in config.yaml (found: prod, staging)"
+        );
+    }
+
+    #[test]
+    fn custom_highlight_rule_styles_a_matching_line() {
+        use ratatui::style::{Modifier, Style};
+
+        let path = temp_dir("highlight-rule").join("config.yaml");
+        fs::write(
+            &path,
+            "highlights:\n  - substring: tenantId\n    colour: \"#60a5fa\"\n    bold: true\n",
+        )
+        .unwrap();
+
+        let settings = Settings::load(&path, None).ok().unwrap();
+        let style = |line| crate::ui::highlight_style(&settings.highlights, line, Color::White);
+
+        assert_eq!(
+            style(r#"  "tenantId": "acme","#),
+            Some(
+                Style::default()
+                    .fg(Color::Rgb(0x60, 0xa5, 0xfa))
+                    .add_modifier(Modifier::BOLD)
+            )
+        );
+        // Configured rules replace the built-in ones
+        assert_eq!(style(r#"  "requestId": "abc","#), None);
+    }
+
+    #[tokio::test]
+    async fn request_id_is_logged_at_dispatch_and_completion() {
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpListener,
+        };
+
+        let dir = temp_dir("request-id");
+        logger::setup_logging(&dir).unwrap();
+
+        // Answers one query with no results, handing back the request it was sent
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/graphql", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            let n = stream.read(&mut request).await.unwrap();
+            let body = r#"{"data":{"actor":{"account":{"nrql":{"results":[]}}}}}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request[..n]).to_lowercase()
+        });
+        let mut client = NewRelicClient::builder();
+        client
+            .url(Box::leak(url.into_boxed_str()))
+            .account("1")
+            .api_key(&Secret::new(String::from("NRAK-TEST")))
+            .http_client(Client::builder());
+
+        let query = "SELECT * FROM Log WHERE test = 'request-id'";
+        assert!(matches!(fetch(query, &client).await, PayloadType::None(_)));
+
+        let request = server.await.unwrap();
+        let id = request
+            .lines()
+            .find_map(|line| line.strip_prefix("x-request-id: "))
+            .unwrap()
+            .trim();
+        log::logger().flush();
+        let log = fs::read_to_string(dir.join("xrelic.log")).unwrap();
+        let lines = log
+            .lines()
+            .filter(|line| line.ends_with(query))
+            .collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 2, "{}", log);
+        assert!(lines[0].contains(&format!("[{}] Dispatching query", id)));
+        assert!(lines[1].contains(&format!("[{}] Query completed", id)));
+    }
+
+    #[test]
+    fn configured_pool_is_accepted_and_the_client_builds() {
+        let dir = temp_dir("pool-config");
+        fs::write(
+            dir.join("config.yaml"),
+            "pool_max_idle_per_host: 2\npool_idle_timeout: 15\n",
+        )
+        .unwrap();
+
+        let config = Config::load_from(dir, true, None);
+        assert_eq!(config.pool_max_idle_per_host, 2);
+        assert_eq!(config.pool_idle_timeout, Duration::from_secs(15));
+        assert!(http_client_builder(&config).unwrap().build().is_ok());
+    }
+}
@@ -0,0 +1,153 @@
+use anyhow::Result;
+use chrono::Utc;
+use log::{debug, warn};
+use std::{
+    fmt::Write,
+    net::TcpListener as StdTcpListener,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    time,
+};
+
+use crate::backend::PayloadType;
+
+// Backs off after a failed accept, which would otherwise likely fail again straight away
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// Health of the backend, served in the Prometheus text format with --metrics-addr
+#[derive(Default)]
+pub struct Metrics {
+    // Unix time of the last query that didn't fail, 0 until one succeeds
+    last_success: AtomicI64,
+    errors: AtomicU64,
+    active_queries: AtomicUsize,
+}
+
+impl Metrics {
+    // Empty results count as a success, only failed requests are errors
+    pub fn record(&self, payload: &PayloadType) {
+        match payload {
+            PayloadType::Error { .. } => _ = self.errors.fetch_add(1, Ordering::Relaxed),
+            _ => self
+                .last_success
+                .store(Utc::now().timestamp(), Ordering::Relaxed),
+        }
+    }
+
+    pub fn set_active_queries(&self, n: usize) {
+        self.active_queries.store(n, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, kind, help, value) in [
+            (
+                "old_relic_last_success_timestamp_seconds",
+                "gauge",
+                "Unix time of the last successful query",
+                self.last_success.load(Ordering::Relaxed) as u64,
+            ),
+            (
+                "old_relic_query_errors_total",
+                "counter",
+                "Queries that failed",
+                self.errors.load(Ordering::Relaxed),
+            ),
+            (
+                "old_relic_active_queries",
+                "gauge",
+                "Queries currently being refreshed",
+                self.active_queries.load(Ordering::Relaxed) as u64,
+            ),
+        ] {
+            _ = writeln!(out, "# HELP {} {}", name, help);
+            _ = writeln!(out, "# TYPE {} {}", name, kind);
+            _ = writeln!(out, "{} {}", name, value);
+        }
+        out
+    }
+}
+
+// Answers every request with the current metrics, whatever its path
+pub async fn serve(listener: StdTcpListener, metrics: Arc<Metrics>) -> Result<()> {
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    loop {
+        // A failed accept, e.g. running out of file descriptors, only loses that connection
+        let (mut stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Could not accept a metrics connection: {}", e);
+                time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        debug!("Metrics requested by {}", peer);
+        let body = metrics.render();
+        tokio::spawn(async move {
+            // The request itself doesn't matter, but is read so the client sees a clean close
+            let mut request = [0; 1024];
+            _ = stream.read(&mut request).await;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            if let Err(e) = stream.write_all(response.as_bytes()).await {
+                warn!("Could not send metrics to {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpStream;
+
+    #[test]
+    fn counters_render_in_the_prometheus_format() {
+        let metrics = Metrics::default();
+        metrics.record(&PayloadType::Error {
+            query: None,
+            message: String::from("timed out"),
+        });
+        metrics.record(&PayloadType::None(String::from("SELECT * FROM Log")));
+        metrics.set_active_queries(3);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE old_relic_query_errors_total counter\n"));
+        assert!(rendered.contains("\nold_relic_query_errors_total 1\n"));
+        assert!(rendered.contains("\nold_relic_active_queries 3\n"));
+        assert!(!rendered.contains("\nold_relic_last_success_timestamp_seconds 0\n"));
+    }
+
+    #[tokio::test]
+    async fn endpoint_serves_the_current_counters() {
+        let listener = StdTcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let metrics = Arc::new(Metrics::default());
+        metrics.set_active_queries(2);
+        let server = tokio::spawn(serve(listener, metrics.clone()));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        server.abort();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\nold_relic_active_queries 2\n"));
+        assert!(response.contains("\nold_relic_query_errors_total 0\n"));
+    }
+}
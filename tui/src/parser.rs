@@ -1,79 +1,555 @@
 use anyhow::{anyhow, Result};
 use std::collections::HashMap;
 
-use nom::{
-    branch::alt,
-    bytes::complete::{tag, take_until},
-    IResult,
-};
-
-fn parse_timeseries(input: &str) -> IResult<&str, &str> {
-    alt((tag("TIMESERIES"), tag("TABLE")))(input)
+use nom::IResult;
+
+// Clauses that can follow FROM and SELECT, which New Relic accepts in any order
+const CLAUSES: [&str; 8] = [
+    "WHERE",
+    "FACET",
+    "SINCE",
+    "UNTIL",
+    "LIMIT",
+    "WITH TIMEZONE",
+    "TIMESERIES",
+    "TABLE",
+];
+
+// The clause keyword `input` starts with and its length, allowing any whitespace inside
+// `WITH TIMEZONE`. Only whole words count, so an attribute such as `SINCE_DATE` isn't split
+fn clause_keyword(input: &str) -> Option<(&'static str, usize)> {
+    ["FROM", "SELECT"]
+        .iter()
+        .chain(CLAUSES.iter())
+        .find_map(|keyword| {
+            let mut len = 0;
+            for (i, word) in keyword.split(' ').enumerate() {
+                let rest = &input[len..];
+                let space = rest.len() - rest.trim_start().len();
+                if i > 0 && space == 0 {
+                    return None;
+                }
+                len += space;
+                if !input[len..].starts_with(word) {
+                    return None;
+                }
+                len += word.len();
+            }
+            match input[len..].chars().next() {
+                Some(c) if c.is_alphanumeric() || c == '_' => None,
+                _ => Some((*keyword, len)),
+            }
+        })
 }
 
-fn parse_limit(input: &str) -> IResult<&str, &str> {
-    let (remainder, _) = tag("LIMIT")(input)?;
-    alt((take_until("TIMESERIES"), take_until("TABLE")))(remainder)
+// Splits a query into its top-level clauses as written, e.g. ("SINCE", "1 hour ago").
+// Text before the first keyword is kept under "", and keywords inside parentheses or quotes
+// are left to the clause they're in
+fn split_clauses(input: &str) -> Vec<(&'static str, &str)> {
+    let mut clauses = vec![];
+    let mut current = ("", 0);
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut skip_to = 0;
+
+    for (i, c) in input.char_indices() {
+        if i < skip_to {
+            continue;
+        }
+        match (c, quote) {
+            (_, Some(_)) if escaped => escaped = false,
+            ('\\', Some(_)) => escaped = true,
+            (c, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => {}
+            ('\'' | '"' | '`', None) => quote = Some(c),
+            ('(', None) => depth += 1,
+            (')', None) => depth -= 1,
+            _ if depth == 0 && (i == 0 || input[..i].ends_with(char::is_whitespace)) => {
+                if let Some((keyword, len)) = clause_keyword(&input[i..]) {
+                    clauses.push((current.0, input[current.1..i].trim()));
+                    current = (keyword, i + len);
+                    skip_to = i + len;
+                }
+            }
+            _ => {}
+        }
+    }
+    clauses.push((current.0, input[current.1..].trim()));
+
+    clauses
+        .into_iter()
+        .filter(|(keyword, body)| !(keyword.is_empty() && body.is_empty()))
+        .collect()
 }
 
-fn parse_until(input: &str) -> IResult<&str, &str> {
-    let (remainder, _) = tag("UNTIL")(input)?;
-    take_until("LIMIT")(remainder)
+// Like take_until, but skips matches inside parentheses or quoted strings
+fn take_until_top_level(keyword: &'static str) -> impl Fn(&str) -> IResult<&str, &str> {
+    move |input: &str| {
+        let mut depth = 0;
+        let mut quote: Option<char> = None;
+        for (i, c) in input.char_indices() {
+            match (c, quote) {
+                (c, Some(q)) if c == q => quote = None,
+                (_, Some(_)) => {}
+                ('\'' | '"', None) => quote = Some(c),
+                ('(', None) => depth += 1,
+                (')', None) => depth -= 1,
+                _ if depth == 0 && input[i..].starts_with(keyword) => {
+                    return Ok((&input[i..], &input[..i]));
+                }
+                _ => {}
+            }
+        }
+
+        Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::TakeUntil,
+        )))
+    }
 }
 
-fn parse_since(input: &str) -> IResult<&str, &str> {
-    let (remainder, _) = tag("SINCE")(input)?;
-    take_until("UNTIL")(remainder)
+// Removes `-- line` and `/* block */` comments, leaving quoted strings untouched
+pub fn strip_comments(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    let mut quote: Option<char> = None;
+
+    while let Some(c) = chars.next() {
+        if let Some(q) = quote {
+            out.push(c);
+            if c == '\\' {
+                if let Some(escaped) = chars.next() {
+                    out.push(escaped);
+                }
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+
+        match (c, chars.peek()) {
+            ('\'' | '"', _) => {
+                quote = Some(c);
+                out.push(c);
+            }
+            ('-', Some('-')) => {
+                // Line comment runs until the end of the line
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        out.push(' ');
+                        break;
+                    }
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut prev = '\0';
+                for c in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+                out.push(' ');
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
 }
 
-fn parse_facet(input: &str) -> IResult<&str, &str> {
-    let (remainder, _) = tag("FACET")(input)?;
-    take_until("SINCE")(remainder)
+// Splits `FROM (inner) outer` into the subquery and the rest, None if the FROM isn't parenthesized
+pub fn split_subquery(input: &str) -> Option<(&str, &str)> {
+    let input = input.trim_start();
+    if !input.get(..4)?.eq_ignore_ascii_case("FROM") {
+        return None;
+    }
+    let body = input[4..].trim_start();
+    if !body.starts_with('(') {
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    for (i, c) in body.char_indices() {
+        match (c, quote) {
+            (c, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => {}
+            ('\'' | '"', None) => quote = Some(c),
+            ('(', None) => depth += 1,
+            (')', None) => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&body[1..i], &body[i + 1..]));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
 }
 
-fn parse_where(input: &str) -> IResult<&str, &str> {
-    let (remainder, _) = tag("WHERE")(input)?;
-    alt((take_until("FACET"), take_until("SINCE")))(remainder)
+// Subqueries can't be modelled by NRQLQuery, so they're sent to New Relic as written
+pub fn is_subquery(input: &str) -> bool {
+    split_subquery(&strip_comments(input)).is_some()
 }
 
-fn parse_select(input: &str) -> IResult<&str, &str> {
-    let (remainder, _) = tag("SELECT")(input)?;
-    take_until("WHERE")(remainder)
+// The outer selection of a subquery, e.g. `max(count)` in `FROM (...) SELECT max(count) TIMESERIES`
+pub fn subquery_select(input: &str) -> Option<String> {
+    let input = strip_comments(input);
+    let (_, outer) = split_subquery(&input)?;
+    let select = outer.trim_start().strip_prefix("SELECT")?;
+    let end = ["WHERE", "FACET", "SINCE", "UNTIL", "LIMIT", "TIMESERIES"]
+        .into_iter()
+        .filter_map(|keyword| take_until_top_level(keyword)(select).ok())
+        .map(|(_, clause)| clause.len())
+        .min()
+        .unwrap_or(select.len());
+
+    Some(select[..end].trim().to_owned())
+}
+
+// Splits a batch of queries on top-level `;`, ignoring any inside quoted strings
+pub fn split_statements(input: &str) -> Vec<String> {
+    let mut statements = vec![];
+    let mut current = String::new();
+    let mut chars = input.chars();
+    let mut quote: Option<char> = None;
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', Some(_)) => {
+                current.push(c);
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+                continue;
+            }
+            (c, Some(q)) if c == q => quote = None,
+            ('\'' | '"', None) => quote = Some(c),
+            (';', None) => {
+                statements.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    statements.push(current);
+
+    statements
+        .into_iter()
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+// Splits a WHERE clause into words, operators, parentheses and quoted literals
+fn tokenize_where(clause: &str) -> Result<Vec<String>> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut chars = clause.chars().peekable();
+    let mut depth = 0;
+
+    let flush = |current: &mut String, tokens: &mut Vec<String>| {
+        if !current.is_empty() {
+            tokens.push(std::mem::take(current));
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' | '`' => {
+                flush(&mut current, &mut tokens);
+                let mut literal = String::from(c);
+                let mut closed = false;
+                while let Some(next) = chars.next() {
+                    literal.push(next);
+                    if next == '\\' {
+                        if let Some(escaped) = chars.next() {
+                            literal.push(escaped);
+                        }
+                    } else if next == c {
+                        closed = true;
+                        break;
+                    }
+                }
+                if !closed {
+                    return Err(anyhow!("Parsing Error! : WHERE has an unbalanced quote"));
+                }
+                tokens.push(literal);
+            }
+            '(' | ')' | ',' => {
+                flush(&mut current, &mut tokens);
+                depth += if c == '(' {
+                    1
+                } else if c == ')' {
+                    -1
+                } else {
+                    0
+                };
+                if depth < 0 {
+                    return Err(anyhow!("Parsing Error! : WHERE has unbalanced parentheses"));
+                }
+                tokens.push(c.to_string());
+            }
+            '=' | '!' | '<' | '>' => {
+                flush(&mut current, &mut tokens);
+                let mut operator = String::from(c);
+                if let Some(next @ ('=' | '>')) = chars.peek() {
+                    operator.push(*next);
+                    chars.next();
+                }
+                tokens.push(operator);
+            }
+            c if c.is_whitespace() => flush(&mut current, &mut tokens),
+            c => current.push(c),
+        }
+    }
+    flush(&mut current, &mut tokens);
+
+    if depth != 0 {
+        return Err(anyhow!("Parsing Error! : WHERE has unbalanced parentheses"));
+    }
+
+    Ok(tokens)
 }
 
-fn parse_from(input: &str) -> IResult<&str, &str> {
-    let (remainder, _) = tag("FROM")(input)?;
-    take_until("SELECT")(remainder)
+// Rejects WHERE clauses with obvious mistakes rather than letting them silently return no data
+pub fn validate_where(clause: &str) -> Result<()> {
+    let tokens = tokenize_where(clause)?;
+    let upper = tokens
+        .iter()
+        .map(|t| t.to_uppercase())
+        .collect::<Vec<String>>();
+
+    let is_conjunction = |t: &str| matches!(t, "AND" | "OR");
+    let is_operator = |t: &str| {
+        matches!(
+            t,
+            "=" | "!=" | "<>" | "<" | ">" | "<=" | ">=" | "LIKE" | "RLIKE" | "IN" | "IS" | "NOT"
+        )
+    };
+
+    if let Some(first) = upper.first() {
+        if is_conjunction(first) {
+            return Err(anyhow!("Parsing Error! : WHERE starts with {}", first));
+        }
+    }
+    if let Some(last) = upper.last() {
+        if is_conjunction(last) || is_operator(last) {
+            return Err(anyhow!("Parsing Error! : WHERE ends with {}", last));
+        }
+    }
+
+    for (i, token) in upper.iter().enumerate() {
+        let next = upper.get(i + 1).map(String::as_str);
+        match token.as_str() {
+            t if is_conjunction(t) && next.is_some_and(is_conjunction) => {
+                return Err(anyhow!("Parsing Error! : WHERE has repeated {}", t));
+            }
+            "=" | "!=" | "<>" | "<" | ">" | "<=" | ">=" if next.is_some_and(is_operator) => {
+                return Err(anyhow!(
+                    "Parsing Error! : WHERE has repeated operator {}",
+                    token
+                ));
+            }
+            "IS" => {
+                let operand = match next {
+                    Some("NOT") => upper.get(i + 2).map(String::as_str),
+                    other => other,
+                };
+                if !matches!(operand, Some("NULL" | "TRUE" | "FALSE")) {
+                    return Err(anyhow!("Parsing Error! : WHERE has an invalid IS clause"));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
 }
 
-// TODO: Handle missing components
+// FROM and SELECT come first, then the other clauses in any order
 pub fn parse_nrql(input: &str) -> Result<HashMap<String, String>> {
-    let mut res = String::new();
-
-    let (remainder, from) = parse_from(input).map_err(|_| anyhow!("Parsing Error! : FROM"))?;
-    let (remainder, select) =
-        parse_select(remainder).map_err(|_| anyhow!("Parsing Error!: SELECT"))?;
-    let (remainder, r#where) =
-        parse_where(remainder).map_err(|_| anyhow!("Parsing Error! : WHERE"))?;
-    let (remainder, facet) = parse_facet(remainder).unwrap_or((remainder, ""));
-    let (remainder, since) =
-        parse_since(remainder).map_err(|_| anyhow!("Parsing Error! : SINCE"))?;
-    let (remainder, until) =
-        parse_until(remainder).map_err(|_| anyhow!("Parsing Error! : UNTIL"))?;
-    let (remainder, limit) =
-        parse_limit(remainder).map_err(|_| anyhow!("Parsing Error! : LIMIT"))?;
-    let (_, mode) = parse_timeseries(remainder).map_err(|_| anyhow!("Parsing error! : MODE"))?;
+    let input = strip_comments(input);
+    let input = input.as_str();
+    if split_subquery(input).is_some() {
+        return Err(anyhow!("Parsing Error! : Subquery"));
+    }
+
+    let mut clauses = split_clauses(input).into_iter();
+    let from = match clauses.next() {
+        Some(("FROM", from)) => from,
+        _ => return Err(anyhow!("Parsing Error! : FROM")),
+    };
+    let select = match clauses.next() {
+        Some(("SELECT", select)) => select,
+        _ => return Err(anyhow!("Parsing Error!: SELECT")),
+    };
 
     let mut outputs = HashMap::new();
+    outputs.insert("FROM".to_owned(), from.to_owned());
+    outputs.insert("SELECT".to_owned(), select.to_owned());
+    for (keyword, body) in clauses {
+        let (key, value) = match keyword {
+            // The mode keeps its keyword, e.g. `TIMESERIES 5 minutes`
+            "TIMESERIES" | "TABLE" => ("MODE", format!("{} {}", keyword, body)),
+            "WITH TIMEZONE" => ("TIMEZONE", body.to_owned()),
+            keyword if CLAUSES.contains(&keyword) => (keyword, body.to_owned()),
+            keyword => return Err(anyhow!("Parsing Error! : Repeated {}", keyword)),
+        };
+        if outputs
+            .insert(key.to_owned(), value.trim().to_owned())
+            .is_some()
+        {
+            return Err(anyhow!("Parsing Error! : Repeated {}", keyword));
+        }
+    }
 
-    outputs.insert("FROM".to_owned(), from.trim().to_owned());
-    outputs.insert("SELECT".to_owned(), select.trim().to_owned());
-    outputs.insert("WHERE".to_owned(), r#where.trim().to_owned());
-    outputs.insert("FACET".to_owned(), facet.trim().to_owned());
-    outputs.insert("SINCE".to_owned(), since.trim().to_owned());
-    outputs.insert("UNTIL".to_owned(), until.trim().to_owned());
-    outputs.insert("LIMIT".to_owned(), limit.trim().to_owned());
-    outputs.insert("MODE".to_owned(), mode.trim().to_owned());
+    for required in ["WHERE", "SINCE", "UNTIL", "LIMIT", "MODE"] {
+        if !outputs.contains_key(required) {
+            return Err(anyhow!("Parsing Error! : {}", required));
+        }
+    }
+    validate_where(&outputs["WHERE"])?;
+    for optional in ["FACET", "TIMEZONE"] {
+        outputs.entry(optional.to_owned()).or_default();
+    }
 
     Ok(outputs)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::NRQL;
+
+    const CANONICAL: &str = "FROM Transaction SELECT count(*) WHERE appName = 'api' FACET host SINCE 2 days ago UNTIL 1 day ago LIMIT MAX WITH TIMEZONE 'Europe/London' TIMESERIES 1 hour";
+
+    #[test]
+    fn clause_orderings_parse_to_the_same_query() {
+        let canonical = parse_nrql(CANONICAL).unwrap();
+        for query in [
+            // As exported from the New Relic UI
+            "FROM Transaction SELECT count(*) WHERE appName = 'api' FACET host SINCE 2 days ago UNTIL 1 day ago TIMESERIES 1 hour WITH TIMEZONE 'Europe/London' LIMIT MAX",
+            // FACET after SINCE
+            "FROM Transaction SELECT count(*) WHERE appName = 'api' SINCE 2 days ago FACET host UNTIL 1 day ago LIMIT MAX TIMESERIES 1 hour WITH TIMEZONE 'Europe/London'",
+            "FROM Transaction SELECT count(*) SINCE 2 days ago UNTIL 1 day ago WITH TIMEZONE 'Europe/London' TIMESERIES 1 hour FACET host LIMIT MAX WHERE appName = 'api'",
+            "FROM Transaction\nSELECT count(*)\nWHERE appName = 'api'\nTIMESERIES 1 hour\nWITH   TIMEZONE 'Europe/London'\nUNTIL 1 day ago\nSINCE 2 days ago\nFACET host\nLIMIT MAX",
+        ] {
+            assert_eq!(parse_nrql(query).unwrap(), canonical, "{}", query);
+        }
+        assert_eq!(canonical["TIMEZONE"], "'Europe/London'");
+        assert_eq!(canonical["MODE"], "TIMESERIES 1 hour");
+    }
+
+    #[test]
+    fn timezone_survives_normalization() {
+        let nrql = CANONICAL.to_nrql().unwrap();
+        assert_eq!(nrql.timezone, "'Europe/London'");
+        let normalized = nrql.to_string().unwrap();
+        assert_eq!(
+            normalized.as_str().to_nrql().unwrap().to_string().unwrap(),
+            normalized
+        );
+    }
+
+    #[test]
+    fn keywords_inside_quotes_and_parentheses_stay_in_their_clause() {
+        let parts = parse_nrql(
+            "FROM Log SELECT filter(count(*), WHERE level = 'ERROR') WHERE message = 'SINCE restart' FACET CASES(WHERE code > 500 AS 'Failed') SINCE 1 hour ago UNTIL now LIMIT 10 TIMESERIES",
+        )
+        .unwrap();
+        assert_eq!(parts["SELECT"], "filter(count(*), WHERE level = 'ERROR')");
+        assert_eq!(parts["WHERE"], "message = 'SINCE restart'");
+        assert_eq!(parts["FACET"], "CASES(WHERE code > 500 AS 'Failed')");
+        assert_eq!(parts["SINCE"], "1 hour ago");
+    }
+
+    #[test]
+    fn missing_or_repeated_clauses_are_rejected() {
+        // Log queries, which are run as written
+        assert!(parse_nrql("SELECT * FROM Log").is_err());
+        assert!(parse_nrql("FROM Log SELECT * WHERE level = 'ERROR' SINCE 1 hour ago").is_err());
+        assert!(parse_nrql(
+            "FROM Log SELECT count(*) WHERE a = 1 SINCE 1 hour ago SINCE 2 hours ago UNTIL now LIMIT 10 TIMESERIES"
+        )
+        .is_err());
+        assert!(parse_nrql(
+            "FROM Log SELECT count(*) WHERE a = 1 SINCE 1 hour ago UNTIL now LIMIT 10 TIMESERIES TABLE"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn trailing_line_comment_is_stripped() {
+        let parts = parse_nrql(
+            "FROM Transaction SELECT count(*) WHERE appName = 'api' SINCE 1 hour ago UNTIL now LIMIT MAX TIMESERIES -- requests per minute",
+        )
+        .unwrap();
+        assert_eq!(parts["MODE"], "TIMESERIES");
+    }
+
+    #[test]
+    fn inline_block_comment_is_stripped() {
+        let parts = parse_nrql(
+            "FROM Transaction SELECT count(*) /* all requests */ WHERE appName = 'api' SINCE 1 hour ago UNTIL now LIMIT MAX TIMESERIES",
+        )
+        .unwrap();
+        assert_eq!(parts["SELECT"], "count(*)");
+    }
+
+    #[test]
+    fn comment_markers_inside_strings_are_kept() {
+        let query = "SELECT * FROM Log WHERE message = 'retrying -- attempt 2 /* of 3 */'";
+        assert_eq!(strip_comments(query), query);
+    }
+
+    #[test]
+    fn is_not_null_and_comparison_operators_are_valid() {
+        assert!(validate_where("traceId IS NOT NULL").is_ok());
+        assert!(validate_where("duration >= 1 AND (code != 200 OR error IS TRUE)").is_ok());
+        assert!(validate_where("message LIKE '%refused%' AND host <> 'api-1'").is_ok());
+    }
+
+    #[test]
+    fn malformed_where_clauses_are_rejected() {
+        assert!(validate_where("message = 'unterminated").is_err());
+        assert!(validate_where("(duration > 1").is_err());
+        assert!(validate_where("duration > 1 AND").is_err());
+        assert!(validate_where("code = = 200").is_err());
+        assert!(validate_where("traceId IS NOT").is_err());
+    }
+
+    #[test]
+    fn timeseries_auto_round_trips() {
+        let query = "FROM Transaction SELECT count(*) WHERE appName = 'api' SINCE 1 day ago UNTIL now LIMIT MAX TIMESERIES AUTO";
+        let nrql = query.to_nrql().unwrap();
+        assert_eq!(nrql.mode, "TIMESERIES AUTO");
+
+        let normalized = nrql.to_string().unwrap();
+        assert!(normalized.ends_with("TIMESERIES AUTO"));
+        assert_eq!(
+            normalized.as_str().to_nrql().unwrap().mode,
+            "TIMESERIES AUTO"
+        );
+    }
+
+    #[test]
+    fn cases_facet_round_trips() {
+        let facet = "CASES(WHERE duration < 0.1 AS 'fast', WHERE duration < 1 AS 'ok, slow', WHERE duration >= 1 AS 'slow')";
+        let query = format!(
+            "FROM Transaction SELECT count(*) WHERE appName = 'api' FACET {} SINCE 1 hour ago UNTIL now LIMIT MAX TIMESERIES",
+            facet
+        );
+        let nrql = query.as_str().to_nrql().unwrap();
+        assert_eq!(nrql.facet, facet);
+
+        let normalized = nrql.to_string().unwrap();
+        assert_eq!(normalized.as_str().to_nrql().unwrap().facet, facet);
+    }
+}
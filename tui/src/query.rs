@@ -1,17 +1,342 @@
-use anyhow::Result;
-use serde::Deserialize;
+use anyhow::{anyhow, Error, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::debug;
+use serde::{de, Deserialize, Deserializer, Serialize};
 
-use crate::parser::parse_nrql;
+use crate::parser::{is_subquery, parse_nrql};
+
+// New Relic's window for queries without a SINCE clause
+pub const DEFAULT_SINCE: &str = "1 hour ago";
+// Rows returned without a LIMIT clause, and the most `LIMIT MAX` returns
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 5000;
+// Windows bound to 1-5, as a label and the SINCE they set
+pub const WINDOW_PRESETS: [(&str, &str); 5] = [
+    ("15m", "15 minutes ago"),
+    ("1h", "1 hour ago"),
+    ("6h", "6 hours ago"),
+    ("24h", "24 hours ago"),
+    ("7d", "7 days ago"),
+];
+// Subquery rows have nowhere to be shown other than a chart
+pub const SUBQUERY_ERROR: &str = "Subqueries can only be run with TIMESERIES";
+const AGGREGATES: [&str; 14] = [
+    "count",
+    "sum",
+    "average",
+    "max",
+    "min",
+    "latest",
+    "median",
+    "percentile",
+    "percentage",
+    "rate",
+    "uniquecount",
+    "uniques",
+    "stddev",
+    "apdex",
+];
 
 #[derive(Debug, Deserialize, Clone)]
 pub enum QueryType {
     Timeseries(NRQLQuery),
+    Histogram(String),
     Log(String),
+    // A subquery, sent as written
+    Raw(String),
+}
+
+impl From<&str> for QueryType {
+    fn from(query: &str) -> Self {
+        if is_histogram(query) {
+            return QueryType::Histogram(query.to_owned());
+        }
+        if is_subquery(query) {
+            return QueryType::Raw(query.to_owned());
+        }
+
+        query
+            .to_nrql()
+            .map_or_else(|_| QueryType::Log(query.to_owned()), QueryType::Timeseries)
+    }
+}
+
+impl QueryType {
+    // Payloads are keyed by the normalised query for timeseries and the raw query otherwise
+    pub fn key(&self) -> String {
+        match self {
+            QueryType::Timeseries(nrql) => nrql.to_string().unwrap(),
+            QueryType::Histogram(query) | QueryType::Log(query) | QueryType::Raw(query) => {
+                query.to_owned()
+            }
+        }
+    }
+}
+
+// Distribution queries return buckets rather than a series
+pub fn is_histogram(query: &str) -> bool {
+    query.to_lowercase().contains("histogram(")
+}
+
+// Percentage selections always fall between 0 and 100
+pub fn is_percentage(select: &str) -> bool {
+    select.trim().to_lowercase().starts_with("percentage(")
+}
+
+// The event type a query reads from, e.g. `Transaction` in `FROM Transaction SELECT ...`
+pub fn from_source(query: &str) -> Option<String> {
+    let words = query.split_whitespace().collect::<Vec<&str>>();
+    words
+        .iter()
+        .position(|word| word.eq_ignore_ascii_case("FROM"))
+        .and_then(|i| words.get(i + 1))
+        .map(|source| source.trim_end_matches(';').to_owned())
+}
+
+pub fn is_timeseries(query: &str) -> bool {
+    query
+        .split_whitespace()
+        .any(|word| word.eq_ignore_ascii_case("TIMESERIES"))
+}
+
+// An aggregate SELECT without TIMESERIES or TABLE would otherwise be run as a Log query
+pub fn missing_timeseries(query: &str) -> bool {
+    let lowercase = query.to_lowercase();
+    if lowercase
+        .split_whitespace()
+        .any(|word| matches!(word, "timeseries" | "table"))
+    {
+        return false;
+    }
+
+    let select = match lowercase.find("select ") {
+        Some(i) => &lowercase[i + "select ".len()..],
+        None => return false,
+    };
+    let end = [
+        " from ", " where ", " facet ", " since ", " until ", " limit ",
+    ]
+    .iter()
+    .filter_map(|keyword| select.find(keyword))
+    .min()
+    .unwrap_or(select.len());
+
+    AGGREGATES
+        .iter()
+        .any(|name| !function_args(&select[..end], name).is_empty())
+}
+
+// Wraps a value as an NRQL string literal, escaping with backslashes as NRQL expects
+pub fn quote_value(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('\'');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '\'' => quoted.push_str("\\'"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+// Wraps an attribute name in backticks, which cannot themselves be escaped
+pub fn quote_identifier(name: &str) -> String {
+    format!("`{}`", name.replace('`', ""))
+}
+
+// `field LIKE 'pattern'`, where `%` in the pattern is the wildcard
+pub fn like_clause(field: &str, pattern: &str) -> String {
+    format!("{} LIKE {}", quote_identifier(field), quote_value(pattern))
+}
+
+// `field RLIKE 'regex'`, with the regex's backslashes escaped for the string literal
+pub fn rlike_clause(field: &str, regex: &str) -> String {
+    format!("{} RLIKE {}", quote_identifier(field), quote_value(regex))
+}
+
+// Attributes a trace id is reported under, by New Relic agents and OpenTelemetry
+const TRACE_ID_ATTRIBUTES: [&str; 3] = ["trace.id", "traceId", "trace_id"];
+
+pub fn is_trace_attribute(name: &str) -> bool {
+    TRACE_ID_ATTRIBUTES.contains(&name.trim().trim_matches('`'))
+}
+
+// Every log line of one trace
+pub fn trace_logs_query(trace_id: &str) -> String {
+    format!(
+        "SELECT * FROM Log WHERE {} = {}",
+        quote_identifier(TRACE_ID_ATTRIBUTES[0]),
+        quote_value(trace_id)
+    )
+}
+
+// Most rows a query can return, so a full page of results can be flagged as truncated
+pub fn result_cap(query: &str) -> usize {
+    let words = query.split_whitespace().collect::<Vec<&str>>();
+    let limit = words
+        .iter()
+        .position(|word| word.eq_ignore_ascii_case("LIMIT"))
+        .and_then(|i| words.get(i + 1));
+
+    match limit {
+        Some(limit) if limit.eq_ignore_ascii_case("MAX") => MAX_LIMIT,
+        Some(limit) => limit
+            .parse::<usize>()
+            .map_or(MAX_LIMIT, |n| n.min(MAX_LIMIT)),
+        None => DEFAULT_LIMIT,
+    }
+}
+
+// Clauses that can follow SINCE, ending its phrase
+const SINCE_END: [&str; 7] = [
+    "UNTIL",
+    "LIMIT",
+    "TIMESERIES",
+    "FACET",
+    "COMPARE",
+    "WITH",
+    "WHERE",
+];
+
+// The SINCE phrase of a query as written, e.g. "30 days ago"
+pub fn since_clause(query: &str) -> Option<String> {
+    let words = query.split_whitespace().collect::<Vec<&str>>();
+    let start = words
+        .iter()
+        .position(|word| word.eq_ignore_ascii_case("SINCE"))?;
+    let phrase = words[start + 1..]
+        .iter()
+        .take_while(|word| !SINCE_END.iter().any(|end| word.eq_ignore_ascii_case(end)))
+        .copied()
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    match phrase.is_empty() {
+        true => None,
+        false => Some(phrase),
+    }
+}
+
+// How far back a SINCE reaches, for epoch milliseconds and relative phrases such as
+// `30 days ago`, `last week` or `yesterday`. Dates and times are left alone
+pub fn since_age(since: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let since = since.trim().to_lowercase();
+    if let Ok(ms) = since.parse::<i64>() {
+        return Some(now - DateTime::<Utc>::from_timestamp_millis(ms)?);
+    }
+    if since == "yesterday" {
+        return Duration::try_days(1);
+    }
+
+    let parts = since
+        .trim_end_matches("ago")
+        .split_whitespace()
+        .collect::<Vec<&str>>();
+    let (count, unit) = match parts.as_slice() {
+        [count, unit] => (*count, *unit),
+        _ => return None,
+    };
+    let count = match count {
+        "last" | "a" | "an" => 1,
+        count => count.parse::<i64>().ok()?,
+    };
+
+    // None rather than a panic when a typed count is out of range
+    match unit.trim_end_matches('s') {
+        "second" | "sec" => Duration::try_seconds(count),
+        "minute" | "min" => Duration::try_minutes(count),
+        "hour" | "hr" => Duration::try_hours(count),
+        "day" => Duration::try_days(count),
+        "week" => Duration::try_weeks(count),
+        // Calendar units are rounded, which is close enough to compare against retention
+        "month" => Duration::try_days(count.checked_mul(30)?),
+        "year" => Duration::try_days(count.checked_mul(365)?),
+        _ => None,
+    }
+}
+
+// Warns of a SINCE further back than the account keeps data, which would come back empty
+pub fn retention_warning(query: &str, retention_days: u64, now: DateTime<Utc>) -> Option<String> {
+    let since = since_clause(query)?;
+    let age = since_age(&since, now)?;
+    let retention = i64::try_from(retention_days)
+        .ok()
+        .and_then(Duration::try_days)?;
+    if age <= retention {
+        return None;
+    }
+
+    Some(format!(
+        "SINCE {} is beyond the {} day retention, so older data may be missing from '{}'",
+        since, retention_days, query
+    ))
+}
+
+// Returns the arguments of the histogram function, split on top-level commas
+pub fn histogram_args(query: &str) -> Vec<String> {
+    function_args(query, "histogram")
+}
+
+// The interval of a `rate(value, 1 minute)` selection, e.g. "per minute"
+pub fn rate_unit(select: &str) -> Option<String> {
+    let args = function_args(select, "rate");
+    let interval = args.get(1)?.to_lowercase();
+    match interval
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .as_slice()
+    {
+        ["1", unit] => Some(format!("per {}", unit.trim_end_matches('s'))),
+        [count, unit] => Some(format!("per {} {}", count, unit)),
+        _ => None,
+    }
+}
+
+// Returns the arguments of the first call to `name`, split on top-level commas
+fn function_args(query: &str, name: &str) -> Vec<String> {
+    let call = format!("{}(", name);
+    let lowercase = query.to_lowercase();
+    // Skip matches that are the tail of a longer name, e.g. `accurate(`
+    let start = match lowercase.match_indices(&call).find(|(i, _)| {
+        !lowercase[..*i]
+            .chars()
+            .last()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_')
+    }) {
+        Some((i, _)) => i + call.len(),
+        None => return vec![],
+    };
+
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut depth = 0;
+    for c in query[start..].chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => break,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(current.trim().to_owned());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    args.push(current.trim().to_owned());
+
+    args
 }
 
 pub struct NRQLResult {}
 
-#[derive(Default, Debug, Deserialize, Clone)]
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct NRQLQuery {
     pub from: String,
     pub select: String,
@@ -20,15 +345,102 @@ pub struct NRQLQuery {
     pub since: String,
     pub until: String,
     pub limit: String,
+    // The quoted zone name of a WITH TIMEZONE clause, empty for UTC
+    #[serde(default)]
+    pub timezone: String,
     pub mode: String,
 }
 
 impl NRQLQuery {
+    pub fn range_summary(&self) -> String {
+        range_summary(&self.since, &self.until)
+    }
+
+    // Explicit SINCE/UNTIL given as epoch milliseconds, converted to seconds
+    pub fn epoch_window(&self) -> Option<(f64, f64)> {
+        let since = epoch_ms_to_seconds(&self.since)?;
+        let until = match self.until.as_str() {
+            "" | "now" => Utc::now().timestamp() as f64,
+            until => epoch_ms_to_seconds(until)?,
+        };
+
+        Some((since, until))
+    }
+
+    // Runs the query over the last `since`, up to now, keeping its other clauses
+    pub fn with_window(mut self, since: &str) -> Self {
+        self.since = since.to_owned();
+        self.until = String::from("now");
+        self
+    }
+
+    // Resolves a relative SINCE/UNTIL against `at` as epoch milliseconds, so refreshes stop
+    // sliding the window. None if either end isn't a form `since_age` understands
+    pub fn frozen_at(mut self, at: DateTime<Utc>) -> Option<Self> {
+        let since = match self.since.trim() {
+            "" => DEFAULT_SINCE,
+            since => since,
+        };
+        let since = at.checked_sub_signed(since_age(since, at)?)?;
+        let until = match self.until.trim() {
+            "" | "now" => at,
+            until => at.checked_sub_signed(since_age(until, at)?)?,
+        };
+        self.since = since.timestamp_millis().to_string();
+        self.until = until.timestamp_millis().to_string();
+        Some(self)
+    }
+
+    // ANDs a condition onto the WHERE clause, keeping the existing one grouped
+    pub fn and_where(mut self, clause: &str) -> Self {
+        self.r#where = match self.r#where.trim() {
+            "" => clause.to_owned(),
+            existing => format!("({}) AND {}", existing, clause),
+        };
+        self
+    }
+
+    pub fn where_like(self, field: &str, pattern: &str) -> Self {
+        self.and_where(&like_clause(field, pattern))
+    }
+
+    pub fn where_rlike(self, field: &str, regex: &str) -> Self {
+        self.and_where(&rlike_clause(field, regex))
+    }
+
+    // One clause per line for reading, whereas `to_string` is the compact form that's sent
+    pub fn pretty(&self) -> String {
+        let mut lines = vec![format!(
+            "SELECT {}",
+            self.select.trim_end_matches(" as value")
+        )];
+        for (clause, value) in [
+            ("FROM", &self.from),
+            ("WHERE", &self.r#where),
+            ("FACET", &self.facet),
+            ("SINCE", &self.since),
+            ("UNTIL", &self.until),
+            ("LIMIT", &self.limit),
+            ("WITH TIMEZONE", &self.timezone),
+        ] {
+            if !value.trim().is_empty() {
+                lines.push(format!("  {} {}", clause, value.trim()));
+            }
+        }
+        // Already includes its keyword, e.g. `TIMESERIES 5 minutes`
+        if !self.mode.trim().is_empty() {
+            lines.push(format!("  {}", self.mode.trim()));
+        }
+
+        lines.join("\n")
+    }
+
     pub fn to_string(&self) -> Result<String> {
         let mut query = String::new();
         query += format!("FROM {} ", self.from).as_str();
-        // TODO: Fix 'as value' duplication on save/load session
-        query += format!("SELECT {} as value ", self.select).as_str();
+        // Alias the selection once, so re-parsed queries don't repeat it
+        let select = self.select.trim_end_matches(" as value");
+        query += format!("SELECT {} as value ", select).as_str();
         query += format!("WHERE {} ", self.r#where).as_str();
         if !String::is_empty(&self.facet) {
             query += format!("FACET {} ", self.facet).as_str();
@@ -36,12 +448,61 @@ impl NRQLQuery {
         query += format!("SINCE {} ", self.since).as_str();
         query += format!("UNTIL {} ", self.until).as_str();
         query += format!("LIMIT {} ", self.limit).as_str();
+        if !self.timezone.is_empty() {
+            query += format!("WITH TIMEZONE {} ", self.timezone).as_str();
+        }
         query += self.mode.to_string().as_str();
 
         Ok(query.to_string())
     }
 }
 
+// Compact label for a query's time window, e.g. [1h] or [2d-1d]
+pub fn range_summary(since: &str, until: &str) -> String {
+    let since = match since.trim() {
+        "" => DEFAULT_SINCE,
+        since => since,
+    };
+
+    match until.trim() {
+        "" | "now" => format!("[{}]", compact_duration(since)),
+        until => format!("[{}-{}]", compact_duration(since), compact_duration(until)),
+    }
+}
+
+// Shortens relative phrases such as "8 days ago" to "8d", leaving others as they are
+fn compact_duration(phrase: &str) -> String {
+    let phrase = phrase.trim();
+    let parts = phrase
+        .trim_end_matches("ago")
+        .split_whitespace()
+        .collect::<Vec<&str>>();
+
+    let unit = match parts.as_slice() {
+        [_, unit] => match unit.trim_end_matches('s') {
+            "second" => "s",
+            "minute" => "m",
+            "hour" => "h",
+            "day" => "d",
+            "week" => "w",
+            "month" => "mo",
+            _ => return phrase.to_owned(),
+        },
+        _ => return phrase.to_owned(),
+    };
+
+    format!("{}{}", parts[0], unit)
+}
+
+// Epoch timestamps in NRQL are milliseconds, whereas the chart data uses seconds
+fn epoch_ms_to_seconds(value: &str) -> Option<f64> {
+    value
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|ms| ms as f64 / 1000_f64)
+}
+
 impl NRQL for &str {
     fn to_nrql(self) -> Result<NRQLQuery> {
         let parts = parse_nrql(self)?;
@@ -54,6 +515,7 @@ impl NRQL for &str {
             "SINCE" => nrql.since = value.to_owned(),
             "UNTIL" => nrql.until = value.to_owned(),
             "LIMIT" => nrql.limit = value.to_owned(),
+            "TIMEZONE" => nrql.timezone = value.to_owned(),
             "MODE" => nrql.mode = value.to_owned(),
             _ => panic!(),
         });
@@ -98,10 +560,41 @@ pub struct Nrql<T> {
 #[derive(Default, Debug, Deserialize, PartialEq, PartialOrd)]
 #[serde(rename_all = "camelCase")]
 pub struct TimeseriesResult {
-    pub begin_time_seconds: f64,
-    pub end_time_seconds: f64,
+    // Absent or null on malformed rows, which would otherwise plot at the epoch
+    #[serde(default)]
+    pub begin_time_seconds: Option<f64>,
+    #[serde(default)]
+    pub end_time_seconds: Option<f64>,
     pub facet: Option<String>,
-    pub value: f64,
+    // None for buckets with no data, which are skipped and charted as a gap
+    #[serde(default, deserialize_with = "coerce_value")]
+    pub value: Option<f64>,
+}
+
+// Accepts numbers sent as strings and booleans as 0/1, rather than failing the whole result
+fn coerce_value<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Null => Ok(None),
+        serde_json::Value::Number(n) => n
+            .as_f64()
+            .map(Some)
+            .ok_or_else(|| de::Error::custom(format!("value {} is out of range", n))),
+        serde_json::Value::String(s) => {
+            debug!("Coercing string value '{}' to a number", s);
+            s.trim()
+                .parse::<f64>()
+                .map(Some)
+                .map_err(|_| de::Error::custom(format!("value '{}' is not a number", s)))
+        }
+        serde_json::Value::Bool(b) => {
+            debug!("Coercing boolean value {} to {}", b, b as u8);
+            Ok(Some(if b { 1_f64 } else { 0_f64 }))
+        }
+        other => Err(de::Error::custom(format!(
+            "value {} cannot be charted",
+            other
+        ))),
+    }
 }
 
 #[derive(Debug)]
@@ -112,13 +605,246 @@ pub struct Timeseries {
     pub value: f64,
 }
 
-impl From<TimeseriesResult> for Timeseries {
-    fn from(val: TimeseriesResult) -> Timeseries {
-        Timeseries {
-            begin_time_seconds: val.begin_time_seconds,
-            end_time_seconds: val.end_time_seconds,
-            facet: val.facet.clone(),
-            value: val.value,
+impl TryFrom<TimeseriesResult> for Timeseries {
+    type Error = Error;
+
+    fn try_from(val: TimeseriesResult) -> Result<Timeseries> {
+        Ok(Timeseries {
+            begin_time_seconds: val
+                .begin_time_seconds
+                .ok_or(anyhow!("result has no beginTimeSeconds"))?,
+            end_time_seconds: val
+                .end_time_seconds
+                .ok_or(anyhow!("result has no endTimeSeconds"))?,
+            facet: val.facet,
+            value: val.value.ok_or(anyhow!("result has no value"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(value: &str) -> TimeseriesResult {
+        serde_json::from_str(&format!(
+            r#"{{"beginTimeSeconds": 0, "endTimeSeconds": 60, "value": {}}}"#,
+            value
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn string_encoded_number_is_coerced() {
+        assert_eq!(row(r#"" 12.5 ""#).value, Some(12.5));
+        assert!(serde_json::from_str::<TimeseriesResult>(r#"{"value": "n/a"}"#).is_err());
+    }
+
+    #[test]
+    fn boolean_is_coerced_to_zero_or_one() {
+        assert_eq!(row("true").value, Some(1.0));
+        assert_eq!(row("false").value, Some(0.0));
+    }
+
+    #[test]
+    fn null_value_is_skipped_rather_than_failing_the_result() {
+        let results: Nrql<TimeseriesResult> = serde_json::from_str(
+            r#"{"results": [
+                {"beginTimeSeconds": 0, "endTimeSeconds": 60, "value": 1},
+                {"beginTimeSeconds": 60, "endTimeSeconds": 120, "value": null},
+                {"beginTimeSeconds": 120, "endTimeSeconds": 180}
+            ]}"#,
+        )
+        .unwrap();
+        let points = results
+            .results
+            .into_iter()
+            .filter_map(|row| Timeseries::try_from(row).ok())
+            .collect::<Vec<Timeseries>>();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].value, 1.0);
+    }
+
+    #[test]
+    fn reparsed_query_is_aliased_once() {
+        let query = "FROM Transaction SELECT count(*) WHERE appName = 'api' SINCE 1 hour ago UNTIL now LIMIT MAX TIMESERIES";
+        let once = query.to_nrql().unwrap().to_string().unwrap();
+        let twice = once.as_str().to_nrql().unwrap().to_string().unwrap();
+
+        assert_eq!(twice, once);
+        assert_eq!(once.matches(" as value").count(), 1);
+    }
+
+    #[test]
+    fn range_summary_is_compact() {
+        assert_eq!(range_summary("", ""), "[1h]");
+        assert_eq!(range_summary("30 minutes ago", "now"), "[30m]");
+        assert_eq!(range_summary("8 days ago", ""), "[8d]");
+        assert_eq!(range_summary("2 days ago", "1 day ago"), "[2d-1d]");
+        // Phrases without a count are shown as written
+        assert_eq!(range_summary("yesterday", "now"), "[yesterday]");
+        assert_eq!(
+            range_summary("1700000000000", "1700003600000"),
+            "[1700000000000-1700003600000]"
+        );
+    }
+
+    #[test]
+    fn percentage_selections_are_detected() {
+        assert!(is_percentage("percentage(count(*), WHERE error IS true)"));
+        assert!(is_percentage(" Percentage(count(*), WHERE duration > 1)"));
+        assert!(!is_percentage("count(*)"));
+        assert!(!is_percentage("average(percentCpu)"));
+    }
+
+    #[test]
+    fn rate_interval_is_read_as_a_unit() {
+        assert_eq!(rate_unit("rate(count(*), 1 minute)").unwrap(), "per minute");
+        assert_eq!(
+            rate_unit("rate(sum(bytes), 1 seconds)").unwrap(),
+            "per second"
+        );
+        assert_eq!(
+            rate_unit("rate(count(*), 5 MINUTES)").unwrap(),
+            "per 5 minutes"
+        );
+        assert_eq!(rate_unit("count(*)"), None);
+        assert_eq!(rate_unit("rate(count(*))"), None);
+    }
+
+    #[test]
+    fn aggregate_without_timeseries_is_detected() {
+        assert!(missing_timeseries(
+            "FROM Transaction SELECT count(*) FACET appName SINCE 1 hour ago"
+        ));
+        assert!(missing_timeseries(
+            "SELECT average(duration) FROM Transaction"
+        ));
+        assert!(!missing_timeseries(
+            "SELECT count(*) FROM Transaction SINCE 1 hour ago TIMESERIES"
+        ));
+        assert!(!missing_timeseries(
+            "SELECT count(*) FROM Transaction TABLE"
+        ));
+        assert!(!missing_timeseries(
+            "SELECT * FROM Log WHERE message LIKE '%count(%'"
+        ));
+    }
+
+    #[test]
+    fn quoted_values_escape_quotes_backslashes_and_newlines() {
+        assert_eq!(quote_value("checkout"), "'checkout'");
+        assert_eq!(quote_value("O'Brien"), r"'O\'Brien'");
+        assert_eq!(quote_value(r"C:\logs"), r"'C:\\logs'");
+        assert_eq!(quote_value("line one\nline two"), r"'line one\nline two'");
+        assert_eq!(quote_value(r"\' OR 1=1"), r"'\\\' OR 1=1'");
+    }
+
+    #[test]
+    fn subquery_is_passed_through_rather_than_run_as_logs() {
+        let subquery = "FROM (SELECT count(*) AS 'n' FROM Transaction FACET appName TIMESERIES) \
+                        SELECT max(n) SINCE 1 hour ago TIMESERIES";
+        assert!(matches!(QueryType::from(subquery), QueryType::Raw(query) if query == subquery));
+
+        let commented =
+            "-- busiest app\nFROM (SELECT count(*) FROM Log FACET host) SELECT max(count)";
+        assert!(matches!(QueryType::from(commented), QueryType::Raw(_)));
+
+        assert!(matches!(
+            QueryType::from("SELECT * FROM Log WHERE message = '(FROM (x))'"),
+            QueryType::Log(_)
+        ));
+    }
+
+    #[test]
+    fn like_patterns_are_quoted_and_and_merged() {
+        let nrql = NRQLQuery {
+            r#where: String::from("level = 'ERROR' OR level = 'WARN'"),
+            ..Default::default()
         }
+        .where_like("message", "%can't connect%")
+        .where_rlike("host", r"prod-\d+");
+
+        assert_eq!(
+            nrql.r#where,
+            r"((level = 'ERROR' OR level = 'WARN') AND `message` LIKE '%can\'t connect%') AND `host` RLIKE 'prod-\\d+'"
+        );
+        assert_eq!(
+            NRQLQuery::default().where_like("message", "100%").r#where,
+            "`message` LIKE '100%'"
+        );
+    }
+
+    #[test]
+    fn trace_id_becomes_a_quoted_log_query() {
+        assert_eq!(
+            trace_logs_query("4bf92f3577b34da6a3ce929d0e0e4736"),
+            "SELECT * FROM Log WHERE `trace.id` = '4bf92f3577b34da6a3ce929d0e0e4736'"
+        );
+        assert_eq!(
+            trace_logs_query("x' OR '1'='1"),
+            r"SELECT * FROM Log WHERE `trace.id` = 'x\' OR \'1\'=\'1'"
+        );
+        assert!(matches!(
+            QueryType::from(trace_logs_query("abc").as_str()),
+            QueryType::Log(_)
+        ));
+    }
+
+    #[test]
+    fn since_beyond_retention_warns() {
+        let now = DateTime::parse_from_rfc3339("2026-10-16T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let query = "SELECT * FROM Log SINCE 30 days ago";
+
+        assert_eq!(
+            retention_warning(query, 8, now).as_deref(),
+            Some(
+                "SINCE 30 days ago is beyond the 8 day retention, \
+                 so older data may be missing from 'SELECT * FROM Log SINCE 30 days ago'"
+            )
+        );
+        assert_eq!(
+            retention_warning("SELECT * FROM Log SINCE 7 days ago", 8, now),
+            None
+        );
+        assert_eq!(retention_warning("SELECT * FROM Log", 8, now), None);
+    }
+
+    #[test]
+    fn pretty_puts_each_clause_on_its_own_line() {
+        let nrql = "FROM Transaction SELECT count(*) WHERE duration > 0 FACET appName \
+                    SINCE 1 hour ago UNTIL now LIMIT MAX TIMESERIES 5 minutes"
+            .to_nrql()
+            .unwrap();
+
+        assert_eq!(
+            nrql.pretty(),
+            "SELECT count(*)\n  \
+               FROM Transaction\n  \
+               WHERE duration > 0\n  \
+               FACET appName\n  \
+               SINCE 1 hour ago\n  \
+               UNTIL now\n  \
+               LIMIT MAX\n  \
+               TIMESERIES 5 minutes"
+        );
+        assert!(!nrql.to_string().unwrap().contains('\n'));
+    }
+
+    #[test]
+    fn out_of_range_since_is_not_a_warning() {
+        let now = DateTime::parse_from_rfc3339("2026-10-16T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(since_age("99999999999999 days ago", now), None);
+        assert_eq!(since_age("9223372036854775807 years ago", now), None);
+        assert_eq!(
+            retention_warning("SELECT * FROM Log SINCE 99999999999999 days ago", 8, now),
+            None
+        );
+        assert!(retention_warning("SELECT * FROM Log SINCE 9 days ago", u64::MAX, now).is_none());
     }
 }
@@ -0,0 +1,44 @@
+use std::fmt;
+
+const REDACTED: &str = "***";
+
+// Holds a credential that must never appear in formatted output
+#[derive(Clone, PartialEq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Secret(value)
+    }
+
+    // The raw value, only for handing to the HTTP client
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatting_hides_the_value() {
+        let secret = Secret::new(String::from("NRAK-0123456789"));
+        assert_eq!(format!("{}", secret), "***");
+        assert_eq!(format!("{:?}", secret), "***");
+        assert_eq!(format!("{:?}", Some(&secret)), "Some(***)");
+        assert_eq!(secret.expose(), "NRAK-0123456789");
+    }
+}
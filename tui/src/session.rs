@@ -1,7 +1,54 @@
-use std::{collections::BTreeMap, path::PathBuf};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
 
 pub struct Session {
     pub is_loaded: bool,
     pub queries: Option<BTreeMap<String, String>>,
     pub session_path: PathBuf,
 }
+
+// On-disk layout of session.yaml, also used for the crash recovery snapshot
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionFile {
+    pub queries: BTreeMap<String, String>,
+    pub history: Vec<String>,
+    pub log_filters: BTreeMap<String, BTreeSet<String>>,
+    // Facets hidden from each query's chart
+    pub hidden_facets: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl SessionFile {
+    // Older sessions are a bare list of queries
+    pub fn parse(yaml: &str) -> serde_yaml::Result<Self> {
+        serde_yaml::from_str::<SessionFile>(yaml).or_else(|e| {
+            serde_yaml::from_str::<Vec<String>>(yaml)
+                .map(|history| SessionFile {
+                    history,
+                    ..SessionFile::default()
+                })
+                .map_err(|_| e)
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        Ok(SessionFile::parse(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty() && self.history.is_empty() && self.log_filters.is_empty()
+    }
+}
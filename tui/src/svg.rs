@@ -0,0 +1,174 @@
+use chrono::DateTime;
+use ratatui::style::Color;
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{
+    backend::{facet_label, Bounds},
+    dataset::split_at_gaps,
+};
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 400.0;
+const MARGIN: f64 = 60.0;
+const LEGEND_WIDTH: f64 = 160.0;
+
+// Re-plots a graph for reports, one path per facet in its chart colour
+pub fn render_svg(
+    title: &str,
+    facets: &BTreeMap<String, Vec<(f64, f64)>>,
+    bounds: Bounds,
+    colour: impl Fn(&str) -> Color,
+) -> String {
+    let (min_x, min_y) = bounds.mins;
+    let (max_x, max_y) = bounds.maxes;
+    // A flat series would otherwise divide by zero
+    let span_x = (max_x - min_x).max(f64::EPSILON);
+    let span_y = (max_y - min_y).max(f64::EPSILON);
+    let plot_width = WIDTH - 2.0 * MARGIN - LEGEND_WIDTH;
+    let plot_height = HEIGHT - 2.0 * MARGIN;
+    let to_x = |x: f64| MARGIN + (x - min_x) / span_x * plot_width;
+    let to_y = |y: f64| HEIGHT - MARGIN - (y - min_y) / span_y * plot_height;
+
+    let mut svg = String::new();
+    _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="monospace" font-size="12">"#,
+        w = WIDTH,
+        h = HEIGHT
+    );
+    _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+    _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" font-size="14">{}</text>"#,
+        MARGIN,
+        MARGIN / 2.0,
+        escape(title)
+    );
+
+    // Axes, labelled with their bounds
+    let (left, right) = (MARGIN, MARGIN + plot_width);
+    let (top, bottom) = (MARGIN, HEIGHT - MARGIN);
+    _ = writeln!(
+        svg,
+        r#"<path d="M {left} {top} V {bottom} H {right}" stroke="black" fill="none"/>"#
+    );
+    for (y, value) in [(bottom, min_y), (top, max_y)] {
+        _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="end">{:.2}</text>"#,
+            left - 4.0,
+            y + 4.0,
+            value
+        );
+    }
+    for (x, anchor, seconds) in [(left, "start", min_x), (right, "end", max_x)] {
+        let label = DateTime::from_timestamp(seconds as i64, 0)
+            .map(|time| time.format("%H:%M").to_string())
+            .unwrap_or_default();
+        _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="{}">{}</text>"#,
+            x,
+            bottom + 16.0,
+            anchor,
+            label
+        );
+    }
+
+    for (i, (facet, points)) in facets.iter().enumerate() {
+        let stroke = hex(colour(facet));
+        // Gaps start a new subpath, and a lone point is drawn as a dot
+        let d = split_at_gaps(points)
+            .into_iter()
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| {
+                let mut d = format!("M {:.1} {:.1}", to_x(segment[0].0), to_y(segment[0].1));
+                for (x, y) in &segment[1..] {
+                    _ = write!(d, " L {:.1} {:.1}", to_x(*x), to_y(*y));
+                }
+                if segment.len() == 1 {
+                    d += " h 0";
+                }
+                d
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+        _ = writeln!(
+            svg,
+            r#"<path d="{}" stroke="{}" stroke-width="1.5" stroke-linecap="round" fill="none"/>"#,
+            d, stroke
+        );
+        _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" fill="{}">{}</text>"#,
+            right + 16.0,
+            top + 16.0 * i as f64,
+            stroke,
+            escape(facet_label(facet))
+        );
+    }
+
+    svg += "</svg>\n";
+    svg
+}
+
+// Terminal colours approximated with the Tango palette
+fn hex(colour: Color) -> String {
+    let hex = match colour {
+        Color::Rgb(r, g, b) => return format!("#{:02x}{:02x}{:02x}", r, g, b),
+        Color::Black => "#000000",
+        Color::Red => "#cc0000",
+        Color::Green => "#4e9a06",
+        Color::Yellow => "#c4a000",
+        Color::Blue => "#3465a4",
+        Color::Magenta => "#75507b",
+        Color::Cyan => "#06989a",
+        Color::Gray => "#d3d7cf",
+        Color::DarkGray => "#555753",
+        Color::LightRed => "#ef2929",
+        Color::LightGreen => "#8ae234",
+        Color::LightYellow => "#fce94f",
+        Color::LightBlue => "#729fcf",
+        Color::LightMagenta => "#ad7fa8",
+        Color::LightCyan => "#34e2e2",
+        Color::White => "#eeeeec",
+        Color::Reset | Color::Indexed(_) => "#808080",
+    };
+    hex.to_owned()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_path_per_facet() {
+        let facets = BTreeMap::from([
+            (String::from("checkout"), vec![(0.0, 1.0), (60.0, 3.0)]),
+            (String::from("<search>"), vec![(0.0, 2.0), (60.0, 0.0)]),
+        ]);
+        let bounds = Bounds {
+            mins: (0.0, 0.0),
+            maxes: (60.0, 3.0),
+        };
+
+        let svg = render_svg("throughput", &facets, bounds, |facet| match facet {
+            "checkout" => Color::Red,
+            _ => Color::Rgb(0x12, 0x34, 0x56),
+        });
+
+        assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+        // The axes are a path too, drawn at the default width
+        assert_eq!(svg.matches(r#"stroke-width="1.5""#).count(), 2);
+        assert!(svg.contains(r##"stroke="#cc0000" stroke-width"##));
+        assert!(svg.contains(r##"stroke="#123456" stroke-width"##));
+        assert!(svg.contains("&lt;search&gt;"));
+    }
+}
@@ -1,12 +1,13 @@
 use chrono::{DateTime, NaiveDateTime, Utc};
+use log::debug;
 
 use ratatui::{
     prelude::*,
     symbols::Marker,
     widgets::{
-        Axis, Bar, BarChart, BarGroup, Block, BorderType, Borders, Chart, Clear, Dataset,
-        GraphType, LegendPosition, List, Padding, Paragraph, RenderDirection, Scrollbar,
-        ScrollbarOrientation, ScrollbarState, Sparkline, Tabs, Wrap,
+        Axis, Bar, BarChart, BarGroup, Block, BorderType, Borders, Cell, Chart, Clear, Dataset,
+        GraphType, LegendPosition, List, Padding, Paragraph, RenderDirection, Row, Scrollbar,
+        ScrollbarOrientation, ScrollbarState, Sparkline, Table, TableState, Tabs, Wrap,
     },
 };
 use style::palette::tailwind;
@@ -14,7 +15,10 @@ use throbber_widgets_tui::WhichUse;
 use tui_big_text::{BigText, PixelSize};
 
 use crate::{
-    app::{Focus, InputMode, Tab},
+    app::{Focus, InputMode, Tab, KEYBINDINGS},
+    backend::{facet_label, Bounds},
+    dataset::{sort_facets, split_at_gaps},
+    query::{is_percentage, NRQL},
     App,
 };
 
@@ -30,8 +34,32 @@ pub const PALETTES: [tailwind::Palette; 9] = [
     tailwind::SKY,
 ];
 
+// Maps a MARKER setting to the symbol used to plot timeseries
+pub fn parse_marker(name: &str) -> Option<Marker> {
+    match name.trim().to_lowercase().as_str() {
+        "braille" => Some(Marker::Braille),
+        "dot" => Some(Marker::Dot),
+        "block" => Some(Marker::Block),
+        _ => None,
+    }
+}
+
+// Smallest terminal the full layout can be drawn in
+const MIN_WIDTH: u16 = 40;
+const MIN_HEIGHT: u16 = 12;
+// Rows given to the log barchart, growing with the terminal between these
+const MIN_BARCHART_HEIGHT: u16 = 8;
+const MAX_BARCHART_HEIGHT: u16 = 24;
+// Rows below which a letterboxed graph's y-axis keeps only its end labels
+const SHORT_GRAPH_HEIGHT: u16 = 10;
+
 pub fn ui(app: &mut App, frame: &mut Frame) {
     let area = frame.area();
+    if is_too_small(area) {
+        render_too_small(app, frame, area);
+        return;
+    }
+
     let vertical = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]);
 
     // let [header_area, area] = vertical.areas(area);
@@ -48,75 +76,293 @@ pub fn ui(app: &mut App, frame: &mut Frame) {
             match app.focus.panel {
                 Focus::SessionSave => render_save_session(app, frame, area),
                 Focus::SessionLoad => render_load_session(app, frame, area),
+                Focus::RecoveryLoad => render_load_recovery(app, frame, area),
                 Focus::Dashboard => render_dashboard(app, frame, area),
                 Focus::Rename => {
                     render_query_box(app, frame, input_area);
                     render_query_list(app, frame, list_area);
                     render_rename_dialog(app, frame, graph_area);
                 }
+                Focus::DashboardSave | Focus::DashboardLoad => {
+                    render_query_box(app, frame, input_area);
+                    render_query_list(app, frame, list_area);
+                    render_dashboard_dialog(app, frame, graph_area);
+                }
                 Focus::Default
                 | Focus::QueryInput
                 | Focus::Log
                 | Focus::LogDetail
-                | Focus::NoResult => {
+                | Focus::NoResult
+                | Focus::Help
+                | Focus::QuitConfirm
+                | Focus::Keyset
+                | Focus::QueryDetail
+                | Focus::AppSearch
+                | Focus::MergeFacets
+                | Focus::FavoriteSave
+                | Focus::Favorites => {
                     render_query_box(app, frame, input_area);
                     render_query_list(app, frame, list_area);
                     if let Some(dataset) = app.datasets.selected() {
-                        if dataset.has_data {
+                        if dataset.has_points() {
                             render_graph(app, frame, graph_area);
+                        } else if dataset.has_data {
+                            render_no_data(app, frame, graph_area);
                         } else {
                             render_loading(app, frame, graph_area);
                         }
                     } else {
                         render_splash(app, frame, graph_area);
                     }
+                    match app.focus.panel {
+                        Focus::NoResult => render_no_result(app, frame, graph_area),
+                        Focus::MergeFacets => render_merge_dialog(app, frame, graph_area),
+                        _ => {}
+                    }
                 }
-                Focus::Search => {}
+                Focus::Search | Focus::GoToTime => {}
             }
         }
         Tab::Logs => {
-            let horizontal = Layout::horizontal([Constraint::Percentage(10), Constraint::Min(20)]);
+            let horizontal = Layout::horizontal([Constraint::Percentage(30), Constraint::Min(20)]);
             let vertical = Layout::vertical([Constraint::Length(3), Constraint::Min(20)]);
             let [input_area, rest] = vertical.areas(area);
             let [list_area, rest] = horizontal.areas(rest);
 
-            let [barchart_area, seek_area, log_area] =
-                Layout::vertical([Constraint::Max(10), Constraint::Max(1), Constraint::Min(30)])
-                    .areas(rest);
+            let [barchart_area, seek_area, log_area] = logs_layout(rest, app.logs.compact);
 
             match app.focus.panel {
                 Focus::SessionSave => render_save_session(app, frame, area),
                 Focus::SessionLoad => render_load_session(app, frame, area),
+                Focus::RecoveryLoad => render_load_recovery(app, frame, area),
                 Focus::Default
                 | Focus::QueryInput
                 | Focus::Log
                 | Focus::LogDetail
-                | Focus::NoResult => {
+                | Focus::NoResult
+                | Focus::Help
+                | Focus::QuitConfirm
+                | Focus::Keyset
+                | Focus::QueryDetail
+                | Focus::AppSearch
+                | Focus::FavoriteSave
+                | Focus::Favorites => {
                     render_query_box(app, frame, input_area);
                     if !app.logs.is_empty() {
                         render_log_list(app, frame, list_area);
-                        render_barchart(app, frame, barchart_area);
-                        render_seek(app, frame, seek_area);
+                        if !app.logs.compact {
+                            render_barchart(app, frame, barchart_area);
+                            render_seek(app, frame, seek_area);
+                        }
                         render_log(app, frame, log_area);
                         if app.focus.panel == Focus::LogDetail {
                             render_log_detail(app, frame, log_area);
                         }
                         if app.focus.panel == Focus::NoResult {
-                            render_no_result(app, frame, log_area);
+                            render_logs_no_result(app, frame, log_area);
                         }
                     } else if app.focus.loading {
                         render_loading(app, frame, area)
                     } else if app.focus.panel == Focus::NoResult {
-                        render_no_result(app, frame, log_area);
+                        render_logs_no_result(app, frame, log_area);
                     } else {
                         render_splash(app, frame, log_area);
                     }
                 }
                 Focus::Search => render_search(app, frame, area),
+                Focus::GoToTime => render_go_to_time(app, frame, area),
+                Focus::DashboardSave | Focus::DashboardLoad => {
+                    render_dashboard_dialog(app, frame, area)
+                }
                 _ => render_splash(app, frame, area),
             }
         }
+        Tab::Summary => {
+            let vertical = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]);
+            let [input_area, summary_area] = vertical.areas(area);
+
+            match app.focus.panel {
+                Focus::SessionSave => render_save_session(app, frame, area),
+                Focus::SessionLoad => render_load_session(app, frame, area),
+                Focus::RecoveryLoad => render_load_recovery(app, frame, area),
+                Focus::Dashboard => render_dashboard(app, frame, area),
+                Focus::Search => render_search(app, frame, area),
+                _ => {
+                    render_query_box(app, frame, input_area);
+                    render_summary(app, frame, summary_area);
+                    match app.focus.panel {
+                        Focus::Rename => render_rename_dialog(app, frame, summary_area),
+                        Focus::DashboardSave | Focus::DashboardLoad => {
+                            render_dashboard_dialog(app, frame, summary_area)
+                        }
+                        Focus::NoResult => render_no_result(app, frame, summary_area),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    match app.focus.panel {
+        Focus::Help => render_help(app, frame, area),
+        Focus::QuitConfirm => render_quit_confirm(app, frame, area),
+        Focus::Keyset => render_keyset(app, frame, area),
+        Focus::QueryDetail => render_query_detail(app, frame, area),
+        Focus::AppSearch => render_app_search(app, frame, area),
+        Focus::FavoriteSave => render_favorite_save(app, frame, area),
+        Focus::Favorites => render_favorites(app, frame, area),
+        _ => {}
+    }
+}
+
+// Red when a value rose since the previous refresh, green when it fell
+pub fn trend_style(delta: Option<f64>) -> Style {
+    match delta {
+        Some(d) if d > 0.0 => Style::default().fg(Color::LightRed),
+        Some(d) if d < 0.0 => Style::default().fg(Color::LightGreen),
+        _ => Style::default(),
+    }
+}
+
+pub fn trend(delta: Option<f64>) -> Span<'static> {
+    let arrow = match delta {
+        Some(d) if d > 0.0 => "▲ ",
+        Some(d) if d < 0.0 => "▼ ",
+        _ => "",
+    };
+    Span::styled(arrow, trend_style(delta))
+}
+
+// One row per query with its latest value and the change since the last refresh
+pub fn render_summary(app: &mut App, frame: &mut Frame, area: Rect) {
+    let rows = app
+        .datasets
+        .iter()
+        .map(|(query, data)| {
+            let label = data.query_alias.clone().unwrap_or(query.to_owned());
+            let value = data
+                .latest_value()
+                .map_or(String::from("-"), |v| format!("{:.2}", v));
+            let delta = Line::from(vec![
+                trend(data.delta()),
+                Span::styled(
+                    data.delta()
+                        .map_or(String::from("-"), |d| format!("{:+.2}", d)),
+                    trend_style(data.delta()),
+                ),
+            ]);
+            Row::new(vec![
+                Cell::from(label),
+                Cell::from(value),
+                Cell::from(delta),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Min(20),
+            Constraint::Length(14),
+            Constraint::Length(14),
+        ],
+    )
+    .header(
+        Row::new(vec!["Query", "Value", "Delta"])
+            .style(Style::default().bold().fg(app.config.theme.focus_fg)),
+    )
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .title("Summary"),
+    )
+    .highlight_style(Style::default().fg(app.config.theme.focus_fg).bold());
+
+    let mut state = TableState::default().with_selected(app.list_state.selected());
+    frame.render_stateful_widget(table, area, &mut state);
+}
+
+pub fn render_help(app: &mut App, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(60, 70, area);
+    let mut lines = vec![];
+    let mut context = "";
+    for (group, key, action) in KEYBINDINGS {
+        if *group != context {
+            if !context.is_empty() {
+                lines.push(Line::from(""));
+            }
+            lines.push(
+                Line::from(*group).style(Style::default().bold().fg(app.config.theme.focus_fg)),
+            );
+            context = group;
+        }
+        lines.push(Line::from(format!("  {:<16}{}", key, action)));
     }
+
+    let help = Paragraph::new(lines).scroll((app.help_scroll, 0)).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .title("Help"),
+    );
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(help, area);
+}
+
+pub fn render_query_detail(app: &mut App, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(70, 60, area);
+    let lines = app
+        .query_detail()
+        .into_iter()
+        .map(Line::from)
+        .collect::<Vec<_>>();
+
+    let detail = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .title("Query"),
+    );
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(detail, area);
+}
+
+pub fn is_too_small(area: Rect) -> bool {
+    area.width < MIN_WIDTH || area.height < MIN_HEIGHT
+}
+
+pub fn render_too_small(app: &mut App, frame: &mut Frame, area: Rect) {
+    let message = Paragraph::new(format!(
+        "Terminal too small ({}x{}), resize to at least {}x{}",
+        area.width, area.height, MIN_WIDTH, MIN_HEIGHT
+    ))
+    .style(Style::default().fg(app.config.theme.focus_fg))
+    .wrap(Wrap { trim: true })
+    .alignment(Alignment::Center);
+
+    frame.render_widget(message, area);
+}
+
+pub fn render_go_to_time(app: &mut App, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(60, 20, area);
+    let vertical = Layout::vertical([Constraint::Length(3), Constraint::Length(3)]);
+    let [prompt_area, input_area] = vertical.areas(area);
+
+    let prompt = Text::from("Go to time (UTC), e.g. 14:05, 2024-05-01 14:05 or 15 minutes ago");
+    let input = Paragraph::new(app.inputs.get(Focus::GoToTime))
+        .style(Style::default().fg(app.config.theme.focus_fg))
+        .block(
+            Block::default()
+                .padding(Padding::ZERO)
+                .borders(Borders::BOTTOM)
+                .border_type(BorderType::Rounded),
+        );
+    frame.render_widget(Clear, area);
+    frame.render_widget(prompt, prompt_area);
+    frame.render_widget(input, input_area);
 }
 
 pub fn render_search(app: &mut App, frame: &mut Frame, area: Rect) {
@@ -141,14 +387,51 @@ pub fn render_search(app: &mut App, frame: &mut Frame, area: Rect) {
     frame.render_widget(input, input_area);
 }
 
+// Log detail lines containing `substring` and everything in `with` are styled, first match wins
+pub struct Highlight {
+    pub substring: String,
+    pub with: Vec<String>,
+    // The theme's focus colour when unset
+    pub colour: Option<Color>,
+    pub bold: bool,
+}
+
+impl Highlight {
+    fn matches(&self, line: &str) -> bool {
+        line.contains(&self.substring) && self.with.iter().all(|s| line.contains(s.as_str()))
+    }
+}
+
+pub fn default_highlights() -> Vec<Highlight> {
+    let rule = |substring: &str, with: &[&str], colour| Highlight {
+        substring: substring.to_owned(),
+        with: with.iter().map(|s| s.to_string()).collect(),
+        colour,
+        bold: true,
+    };
+    vec![
+        rule("CorrelationId", &[], None),
+        rule("requestId", &[], None),
+        rule("Error", &["level"], Some(Color::LightRed)),
+        rule("Error", &["severity.text"], Some(Color::LightRed)),
+    ]
+}
+
+// Style of the first highlight matching the line, if any
+pub fn highlight_style(highlights: &[Highlight], line: &str, focus_fg: Color) -> Option<Style> {
+    let highlight = highlights.iter().find(|h| h.matches(line))?;
+    let style = Style::default().fg(highlight.colour.unwrap_or(focus_fg));
+    Some(match highlight.bold {
+        true => style.bold(),
+        false => style,
+    })
+}
+
 // Creates a widget::Line for a log detail with styling based on content
 pub fn style_detail_line<'a>(app: &App, value: String) -> Line<'a> {
-    if value.contains("CorrelationId") || value.contains("requestId") {
-        Line::from(value).style(Style::default().bold().fg(app.config.theme.focus_fg))
-    } else if (value.contains("level") && value.contains("Error"))
-        || (value.contains("severity.text") && value.contains("Error"))
+    if let Some(style) = highlight_style(&app.config.highlights, &value, app.config.theme.focus_fg)
     {
-        Line::from(value).style(Style::default().bold().fg(Color::LightRed))
+        Line::from(value).style(style)
     } else if !app.logs.filters.is_empty()
         && value.contains(app.logs.filters.iter().next().unwrap())
     {
@@ -159,7 +442,16 @@ pub fn style_detail_line<'a>(app: &App, value: String) -> Line<'a> {
 }
 
 pub fn render_seek(app: &mut App, frame: &mut Frame, area: Rect) {
-    let curr = app.logs.selected.clone().parse::<f64>().unwrap();
+    let curr = match app.logs.selected_timestamp() {
+        Some(curr) => curr,
+        None => {
+            debug!(
+                "Selected log '{}' has no timestamp to seek to",
+                app.logs.selected
+            );
+            return;
+        }
+    };
     let vec = vec![(curr, 0.5)];
     let dataset = Dataset::default()
         .data(&vec)
@@ -202,35 +494,58 @@ pub fn render_seek(app: &mut App, frame: &mut Frame, area: Rect) {
     frame.render_widget(chart, area);
 }
 
+// Barchart, seek marker and log areas, the compact layout giving the logs the full height
+pub fn logs_layout(area: Rect, compact: bool) -> [Rect; 3] {
+    let (barchart, seek) = match compact {
+        true => (0, 0),
+        false => (barchart_height(area.height), 1),
+    };
+    Layout::vertical([
+        Constraint::Length(barchart),
+        Constraint::Length(seek),
+        Constraint::Min(0),
+    ])
+    .areas(area)
+}
+
+// A quarter of the available height, within the barchart's limits
+pub fn barchart_height(height: u16) -> u16 {
+    (height / 4).clamp(MIN_BARCHART_HEIGHT, MAX_BARCHART_HEIGHT)
+}
+
 pub fn render_barchart(app: &mut App, frame: &mut Frame, area: Rect) {
+    let bounds = app.logs.bounds;
+    let (min_x, _) = bounds.mins;
+    let (max_x, _) = bounds.maxes;
+
+    // One bucket per column inside the borders and padding
+    let n_buckets = area.width.saturating_sub(6) as usize;
+    let chart_data = app
+        .logs
+        .chart_data
+        .bucketed(min_x, max_x, n_buckets, app.logs.log_scale);
+    let max_y = chart_data.y_bound().max(1_f64);
+
     let error_dataset = Dataset::default()
-        .data(&app.logs.chart_data.error)
+        .data(&chart_data.error)
         .marker(Marker::Block)
         .style(Style::default().red())
         .graph_type(GraphType::Bar);
     let debug_dataset = Dataset::default()
-        .data(&app.logs.chart_data.debug)
+        .data(&chart_data.debug)
         .marker(Marker::Block)
         .style(Style::default().magenta())
         .graph_type(GraphType::Bar);
     let info_dataset = Dataset::default()
-        .data(&app.logs.chart_data.info)
+        .data(&chart_data.info)
         .marker(Marker::Block)
         .style(Style::default().blue())
         .graph_type(GraphType::Bar);
 
-    let bounds = app.logs.bounds;
-    let (min_x, _) = bounds.mins;
-    let (max_x, _) = bounds.maxes;
-
-    let selected_date_time = DateTime::<Utc>::from_utc(
-        NaiveDateTime::from_timestamp_opt(
-            app.logs.selected.clone().parse::<i64>().unwrap() / 1000,
-            0,
-        )
-        .unwrap(),
-        Utc,
-    );
+    let selected_date_time = app
+        .logs
+        .selected_timestamp()
+        .and_then(|ms| DateTime::from_timestamp(ms as i64 / 1000, 0));
 
     let min_x_date_time = DateTime::<Utc>::from_utc(
         NaiveDateTime::from_timestamp_opt(min_x as i64 / 1000, 0).unwrap(),
@@ -260,9 +575,15 @@ pub fn render_barchart(app: &mut App, frame: &mut Frame, area: Rect) {
         .bounds([min_x, max_x]);
 
     // Create the Y axis and define its properties
+    // Label the tallest bar with its count, undoing the log scale
+    let max_count = match app.logs.log_scale {
+        true => 10_f64.powf(max_y) - 1_f64,
+        false => max_y,
+    };
     let y_axis = Axis::default()
         .style(Style::default().white())
-        .bounds([0.0, 3.0]);
+        .labels([String::from("0"), format!("{:.0}", max_count)])
+        .bounds([0.0, max_y]);
 
     // Create the chart and link all the parts together
     let chart = Chart::new(vec![info_dataset, debug_dataset, error_dataset])
@@ -274,14 +595,17 @@ pub fn render_barchart(app: &mut App, frame: &mut Frame, area: Rect) {
                     //     selected_date_time.date_naive(),
                     //     selected_date_time.time(),
                     // ))
-                    Line::from(format!(
-                        "{}, {} ({} hours ago)",
-                        selected_date_time.date_naive(),
-                        selected_date_time.time(),
-                        Utc::now()
-                            .signed_duration_since(selected_date_time)
-                            .num_hours(),
-                    ))
+                    Line::from(match selected_date_time {
+                        Some(selected_date_time) => format!(
+                            "{}, {} ({} hours ago)",
+                            selected_date_time.date_naive(),
+                            selected_date_time.time(),
+                            Utc::now()
+                                .signed_duration_since(selected_date_time)
+                                .num_hours(),
+                        ),
+                        None => String::new(),
+                    })
                     .bold()
                     .red()
                     .centered(),
@@ -298,10 +622,42 @@ pub fn render_barchart(app: &mut App, frame: &mut Frame, area: Rect) {
 
 pub fn render_log_detail(app: &mut App, frame: &mut Frame, area: Rect) {
     let area = centered_rect(60, 20, area);
-    let key_idx = app.logs.log_item_list_state.selected().unwrap();
-    let log = &app.logs.selected().unwrap()[key_idx];
+    let log = match app
+        .logs
+        .log_item_list_state
+        .selected()
+        .and_then(|i| app.logs.selected_lines().get(i).cloned())
+    {
+        Some(log) => log,
+        None => return,
+    };
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded);
+
+    let paragraph = if app.logs.no_wrap {
+        let width = area.width.saturating_sub(2) as usize;
+        Paragraph::new(hslice(&log, app.logs.detail_offset, width)).block(block.title("[no wrap]"))
+    } else {
+        Paragraph::new(log).wrap(Wrap { trim: true }).block(block)
+    }
+    .style(Style::default());
 
-    let paragraph = Paragraph::new(log.clone())
+    frame.render_widget(Clear, area);
+    frame.render_widget(paragraph, area);
+}
+
+// Returns the `width` characters of a line visible after scrolling `offset` characters right
+pub fn hslice(line: &str, offset: usize, width: usize) -> String {
+    line.chars().skip(offset).take(width).collect()
+}
+
+pub fn render_no_result(app: &mut App, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(60, 20, area);
+
+    let message = app.error.clone().unwrap_or(String::from("No result!"));
+    let paragraph = Paragraph::new(message)
         .wrap(Wrap { trim: true })
         .block(
             Block::default()
@@ -314,32 +670,64 @@ pub fn render_log_detail(app: &mut App, frame: &mut Frame, area: Rect) {
     frame.render_widget(paragraph, area);
 }
 
-pub fn render_no_result(app: &mut App, frame: &mut Frame, area: Rect) {
-    let area = centered_rect(60, 20, area);
+// An empty log query, told apart from the never-queried splash. Errors are shown as usual
+pub fn render_logs_no_result(app: &mut App, frame: &mut Frame, area: Rect) {
+    if app.error.is_some() {
+        render_no_result(app, frame, area);
+        return;
+    }
 
-    let paragraph = Paragraph::new("No result!")
+    let area = centered_rect(60, 30, area);
+    let mut lines = vec![];
+    if let Some(query) = &app.last_log_query {
+        lines.push(Line::from(query.to_owned()).dark_gray());
+        lines.push(Line::default());
+    }
+    lines.push(Line::from("No logs in this window").bold());
+    lines.push(Line::from(
+        "Widen the SINCE/UNTIL range, or press C to clear filters",
+    ));
+    let paragraph = Paragraph::new(lines)
         .wrap(Wrap { trim: true })
+        .alignment(Alignment::Center)
         .block(
             Block::default()
+                .title("[No logs]")
                 .borders(Borders::ALL)
-                .border_type(BorderType::Rounded),
-        )
-        .style(Style::default());
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(app.config.theme.focus_fg)),
+        );
 
     frame.render_widget(Clear, area);
     frame.render_widget(paragraph, area);
 }
 
 pub fn render_log_list(app: &mut App, frame: &mut Frame, area: Rect) {
+    // Leave room for the borders and highlight symbol
+    let width = area.width.saturating_sub(4) as usize;
     let items = app
         .logs
         .logs
         .iter()
-        // .keys()
         .filter(|(_, v)| apply_filter(app, v))
-        .map(|(k, _)| k.to_owned())
+        .map(|(k, _)| {
+            app.logs
+                .columns
+                .get(k)
+                .unwrap_or(k)
+                .chars()
+                .take(width)
+                .collect::<String>()
+        })
         .collect::<Vec<String>>();
 
+    let title = match app.logs.is_truncated() {
+        true => Line::from(vec![
+            "[Logs]".bold(),
+            " results truncated, narrow your window ".fg(Color::LightYellow),
+        ]),
+        false => Line::from("[Logs]".bold()),
+    };
     let list = List::new(items)
         .block(
             Block::default()
@@ -350,7 +738,7 @@ pub fn render_log_list(app: &mut App, frame: &mut Frame, area: Rect) {
                 } else {
                     Color::White
                 }))
-                .title("[Timestamps]".bold()),
+                .title(title),
         )
         .highlight_style(
             Style::new()
@@ -395,13 +783,11 @@ pub fn apply_filter(app: &App, log_lines: &[String]) -> bool {
 }
 
 pub fn render_log(app: &mut App, frame: &mut Frame, area: Rect) {
-    let logs = app.logs.clone();
-    let default = vec![]; // TODO??
-    let lines = logs
-        .selected()
-        .unwrap_or(&default)
-        .iter()
-        .map(|v| style_detail_line(app, v.to_string()));
+    let lines = app
+        .logs
+        .selected_lines()
+        .into_iter()
+        .map(|v| style_detail_line(app, v));
     let list = List::new(lines)
         .block(
             Block::default()
@@ -443,6 +829,11 @@ pub fn render_tabs(app: &mut App, frame: &mut Frame, area: Rect) {
 }
 
 pub fn render_splash(app: &mut App, frame: &mut Frame, area: Rect) {
+    if !app.config.splash {
+        render_splash_hint(app, frame, area);
+        return;
+    }
+
     let dummy = BigText::builder()
         .pixel_size(PixelSize::Full)
         .style(Style::new().blue())
@@ -453,6 +844,35 @@ pub fn render_splash(app: &mut App, frame: &mut Frame, area: Rect) {
     frame.render_widget(dummy, center);
 }
 
+// One-line stand-in for the splash on small terminals
+pub fn render_splash_hint(app: &mut App, frame: &mut Frame, area: Rect) {
+    let [_, hint_area, _] = Layout::vertical([
+        Constraint::Fill(1),
+        Constraint::Length(1),
+        Constraint::Fill(1),
+    ])
+    .areas(area);
+    let hint = Paragraph::new("Old Relic - press e to add a query, ? for help")
+        .style(Style::default().fg(app.config.theme.focus_fg))
+        .alignment(Alignment::Center);
+
+    frame.render_widget(hint, hint_area);
+}
+
+pub fn render_no_data(app: &mut App, frame: &mut Frame, area: Rect) {
+    let [_, message_area, _] = Layout::vertical([
+        Constraint::Fill(1),
+        Constraint::Length(1),
+        Constraint::Fill(1),
+    ])
+    .areas(area);
+    let message = Paragraph::new("No data points returned")
+        .style(Style::default().fg(app.config.theme.focus_fg))
+        .alignment(Alignment::Center);
+
+    frame.render_widget(message, message_area);
+}
+
 pub fn render_loading(app: &mut App, frame: &mut Frame, area: Rect) {
     let center = centered_rect(5, 5, area);
     let throbber = throbber_widgets_tui::Throbber::default()
@@ -486,6 +906,25 @@ pub fn render_load_session(app: &mut App, frame: &mut Frame, area: Rect) {
     frame.render_widget(input, input_area);
 }
 
+pub fn render_load_recovery(app: &mut App, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(60, 20, area);
+    let vertical = Layout::vertical([Constraint::Length(3), Constraint::Length(3)]);
+    let [prompt_area, input_area] = vertical.areas(area);
+
+    let prompt = Text::from("The last run didn't exit cleanly. Restore its queries? y/n");
+    let input = Paragraph::new(app.inputs.get(Focus::RecoveryLoad))
+        .style(Style::default().fg(app.config.theme.focus_fg))
+        .block(
+            Block::default()
+                .padding(Padding::ZERO)
+                .borders(Borders::BOTTOM)
+                .border_type(BorderType::Rounded),
+        );
+    frame.render_widget(Clear, area);
+    frame.render_widget(prompt, prompt_area);
+    frame.render_widget(input, input_area);
+}
+
 pub fn render_save_session(app: &mut App, frame: &mut Frame, area: Rect) {
     let area = centered_rect(60, 20, area);
     let vertical = Layout::vertical([Constraint::Length(3), Constraint::Length(3)]);
@@ -508,9 +947,188 @@ pub fn render_save_session(app: &mut App, frame: &mut Frame, area: Rect) {
     frame.render_widget(input, input_area);
 }
 
+pub fn render_quit_confirm(app: &mut App, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(60, 20, area);
+    let vertical = Layout::vertical([Constraint::Length(3), Constraint::Length(3)]);
+    let [prompt_area, input_area] = vertical.areas(area);
+
+    let prompt = Text::from("Quit? Unsaved session will be lost y/n");
+    let input = Paragraph::new(app.inputs.get(Focus::QuitConfirm))
+        .style(Style::default().fg(app.config.theme.focus_fg))
+        .block(
+            Block::default()
+                .padding(Padding::ZERO)
+                .borders(Borders::BOTTOM)
+                .border_type(BorderType::Rounded),
+        );
+    frame.render_widget(Clear, area);
+    frame.render_widget(prompt, prompt_area);
+    frame.render_widget(input, input_area);
+}
+
+// Searchable list of the attributes available on the query's source
+pub fn render_keyset(app: &mut App, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(50, 60, area);
+    let vertical = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]);
+    let [input_area, list_area] = vertical.areas(area);
+
+    let input = Paragraph::new(app.inputs.get(Focus::Keyset))
+        .style(Style::default().fg(app.config.theme.focus_fg))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .title("Attribute"),
+        );
+
+    let items = app
+        .filtered_keyset()
+        .into_iter()
+        .map(|key| key.to_owned())
+        .collect::<Vec<String>>();
+    let title = match (app.keyset.is_empty(), items.len()) {
+        (true, _) => String::from("Loading keys..."),
+        (false, n) => format!("{} keys", n),
+    };
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .title(title),
+        )
+        .highlight_style(
+            Style::new()
+                .add_modifier(Modifier::REVERSED)
+                .fg(app.config.theme.chart_fg),
+        )
+        .highlight_symbol(">>");
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(input, input_area);
+    frame.render_stateful_widget(list, list_area, &mut app.keyset_state);
+}
+
+// Optional name for the query being added to favorites
+pub fn render_favorite_save(app: &mut App, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(50, 20, area);
+    let input = Paragraph::new(app.inputs.get(Focus::FavoriteSave))
+        .style(Style::default().fg(app.config.theme.focus_fg))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .title("Favorite name (optional)"),
+        );
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(input, area);
+}
+
+// Searchable list of favorite queries, Enter puts the selected one in the query box
+pub fn render_favorites(app: &mut App, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(60, 60, area);
+    let vertical = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]);
+    let [input_area, list_area] = vertical.areas(area);
+
+    let input = Paragraph::new(app.inputs.get(Focus::Favorites))
+        .style(Style::default().fg(app.config.theme.focus_fg))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .title("Favorite"),
+        );
+
+    let items = app
+        .filtered_favorites()
+        .into_iter()
+        .map(|favorite| match &favorite.name {
+            Some(name) => Line::from(vec![
+                name.to_owned().bold(),
+                "  ".into(),
+                favorite.query.to_owned().dark_gray(),
+            ]),
+            None => Line::from(favorite.query.to_owned()),
+        })
+        .collect::<Vec<Line>>();
+    let title = match items.len() {
+        0 if app.favorites.is_empty() => String::from("No favorites, press + to add one"),
+        n => format!("{} favorites", n),
+    };
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .title(title),
+        )
+        .highlight_style(
+            Style::new()
+                .add_modifier(Modifier::REVERSED)
+                .fg(app.config.theme.chart_fg),
+        )
+        .highlight_symbol(">>");
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(input, input_area);
+    frame.render_stateful_widget(list, list_area, &mut app.favorite_state);
+}
+
+// Application name search, Enter searches and then inserts the selected entity guid
+pub fn render_app_search(app: &mut App, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(60, 60, area);
+    let vertical = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]);
+    let [input_area, list_area] = vertical.areas(area);
+
+    let input = Paragraph::new(app.inputs.get(Focus::AppSearch))
+        .style(Style::default().fg(app.config.theme.focus_fg))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .title("Application name"),
+        );
+
+    let items = app
+        .applications
+        .iter()
+        .map(|application| format!("{}  {}", application.name, application.guid))
+        .collect::<Vec<String>>();
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .title("Applications"),
+        )
+        .highlight_style(
+            Style::new()
+                .add_modifier(Modifier::REVERSED)
+                .fg(app.config.theme.chart_fg),
+        )
+        .highlight_symbol(">>");
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(input, input_area);
+    frame.render_stateful_widget(list, list_area, &mut app.application_state);
+}
+
 pub fn render_dashboard(app: &mut App, frame: &mut Frame, area: Rect) {
-    let n_graphs = &app.datasets.len();
-    let areas = match n_graphs {
+    let areas = dashboard_areas(app.datasets.len(), area);
+    if areas.is_empty() {
+        render_empty_dashboard(app, frame, area);
+        return;
+    }
+
+    (0..areas.len()).for_each(|i| {
+        render_ith_graph(app, frame, areas[i], i);
+    });
+}
+
+// Splits the dashboard area into one area per graph
+pub fn dashboard_areas(n_graphs: usize, area: Rect) -> Vec<Rect> {
+    match n_graphs {
         0 => vec![],
         1 => vec![area],
         2 => {
@@ -527,25 +1145,100 @@ pub fn render_dashboard(app: &mut App, frame: &mut Frame, area: Rect) {
             let [first, second] = horizontal.areas(bottom);
             vec![top, first, second]
         }
-        _ => panic!(),
-    };
+        // A grid two graphs wide, with an odd last graph taking its whole row
+        n => {
+            let n_rows = n.div_ceil(2);
+            let rows =
+                Layout::vertical(vec![Constraint::Ratio(1, n_rows as u32); n_rows]).split(area);
+            rows.iter()
+                .enumerate()
+                .flat_map(|(i, row)| {
+                    let n_columns = (n - 2 * i).min(2);
+                    Layout::horizontal(vec![Constraint::Ratio(1, n_columns as u32); n_columns])
+                        .split(*row)
+                        .to_vec()
+                })
+                .collect()
+        }
+    }
+}
 
-    (0..areas.len()).for_each(|i| {
-        render_ith_graph(app, frame, areas[i], i);
-    });
+pub fn render_empty_dashboard(app: &mut App, frame: &mut Frame, area: Rect) {
+    let center = centered_rect(60, 20, area);
+    let hint = Paragraph::new("No queries yet — press e to add one")
+        .style(Style::default().fg(app.config.theme.focus_fg).bold())
+        .alignment(Alignment::Center);
+
+    frame.render_widget(hint, center);
+}
+
+// One line per unbroken run of a facet's points, named once so the legend lists it once
+fn facet_datasets<'a>(
+    points: &'a [(f64, f64)],
+    name: Line<'a>,
+    style: Style,
+    marker: Marker,
+) -> Vec<Dataset<'a>> {
+    split_at_gaps(points)
+        .into_iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            // A lone point between gaps has no line to draw, so plot it as a dot
+            let graph_type = match segment.len() {
+                1 => GraphType::Scatter,
+                _ => GraphType::Line,
+            };
+            let dataset = Dataset::default()
+                .data(segment)
+                .marker(marker)
+                .graph_type(graph_type)
+                .style(style);
+            match i {
+                0 => dataset.name(name.clone()),
+                _ => dataset,
+            }
+        })
+        .collect()
 }
 
 pub fn render_ith_graph(app: &mut App, frame: &mut Frame, area: Rect, i: usize) {
-    let datasets = app.datasets.iter().nth(i).map(|(_, data)| {
-        data.facets
+    if let Some(buckets) = app
+        .datasets
+        .iter()
+        .nth(i)
+        .and_then(|(_, d)| d.histogram.clone())
+    {
+        render_histogram(app, frame, area, &buckets);
+        return;
+    }
+
+    if let Some((_, data)) = app.datasets.iter().nth(i) {
+        if !data.has_points() {
+            match data.has_data {
+                true => render_no_data(app, frame, area),
+                false => render_loading(app, frame, area),
+            }
+            return;
+        }
+        let facets = data.facets.keys().cloned().collect::<Vec<String>>();
+        register_facet_colours(app, facets);
+    }
+
+    let datasets = app.datasets.iter().nth(i).map(|(query, data)| {
+        let facets = data
+            .facets
             .iter()
-            .map(|(facet, points)| {
-                Dataset::default()
-                    .name(facet.to_owned())
-                    .data(&points[..])
-                    .marker(Marker::Braille)
-                    .graph_type(GraphType::Line)
-                    .style(Style::default().fg(app.facet_colours.get(facet).unwrap().to_owned()))
+            .filter(|(facet, points)| !points.is_empty() && !app.is_hidden(query, facet))
+            .collect();
+        sort_facets(facets, app.config.facet_sort)
+            .into_iter()
+            .flat_map(|(facet, points)| {
+                facet_datasets(
+                    points,
+                    Line::from(facet_label(facet).to_owned()),
+                    Style::default().fg(facet_colour(app, facet)),
+                    app.config.marker,
+                )
             })
             .collect::<Vec<_>>()
     });
@@ -560,20 +1253,16 @@ pub fn render_ith_graph(app: &mut App, frame: &mut Frame, area: Rect, i: usize)
 
             let bounds = dataset.bounds;
             let selection = &dataset.selection;
+            let max_x = dataset.max_x(Utc::now().timestamp() as f64);
 
-            let (min_x, mut min_y) = bounds.mins;
-            let (_, mut max_y) = bounds.maxes;
-            let mut half_y = (max_y - min_y) / 2_f64;
-
-            min_y = f64::round(min_y);
-            max_y = f64::round(max_y);
-            half_y = f64::round(half_y);
+            let (min_x, _) = bounds.mins;
+            let (y_bounds, y_labels) = y_axis_scale(selection, bounds, app.config.precision);
 
             // Create the X axis and define its properties
             let x_axis = Axis::default()
                 .title("Time".red())
                 .style(Style::default().fg(app.config.theme.chart_fg))
-                .bounds([min_x, Utc::now().timestamp() as f64])
+                .bounds([min_x, max_x])
                 .labels(vec![
                     DateTime::from_timestamp(min_x as i64, 0)
                         .unwrap()
@@ -581,7 +1270,7 @@ pub fn render_ith_graph(app: &mut App, frame: &mut Frame, area: Rect, i: usize)
                         .to_string()
                         .fg(app.config.theme.chart_fg)
                         .bold(),
-                    DateTime::from_timestamp(Utc::now().timestamp(), 0)
+                    DateTime::from_timestamp(max_x as i64, 0)
                         .unwrap()
                         .to_string()
                         .fg(app.config.theme.chart_fg)
@@ -590,16 +1279,20 @@ pub fn render_ith_graph(app: &mut App, frame: &mut Frame, area: Rect, i: usize)
 
             // Create the Y axis and define its properties
             let y_axis = Axis::default()
-                .title(selection.clone().fg(app.config.theme.chart_fg))
+                .title(
+                    y_axis_title(selection, dataset.rate_unit.as_deref())
+                        .fg(app.config.theme.chart_fg),
+                )
                 .style(Style::default().fg(app.config.theme.chart_fg))
-                .bounds([min_y, max_y])
-                .labels(vec![
-                    min_y.to_string().fg(app.config.theme.chart_fg).bold(),
-                    half_y.to_string().fg(app.config.theme.chart_fg).bold(),
-                    max_y.to_string().fg(app.config.theme.chart_fg).bold(),
-                ]);
-
-            let legend_position = match &datasets.len() {
+                .bounds(y_bounds)
+                .labels(
+                    y_labels
+                        .into_iter()
+                        .map(|label| label.fg(app.config.theme.chart_fg).bold())
+                        .collect::<Vec<_>>(),
+                );
+
+            let legend_position = match dataset.facets.len() {
                 1 => None,
                 _ => Some(LegendPosition::TopRight),
             };
@@ -614,6 +1307,7 @@ pub fn render_ith_graph(app: &mut App, frame: &mut Frame, area: Rect, i: usize)
                 .y_axis(y_axis);
             frame.render_widget(chart, area);
         }
+        None if !app.config.splash => render_splash_hint(app, frame, area),
         None => {
             let dummy = BigText::builder()
                 .pixel_size(PixelSize::Full)
@@ -627,6 +1321,24 @@ pub fn render_ith_graph(app: &mut App, frame: &mut Frame, area: Rect, i: usize)
     }
 }
 
+// Falls back to the theme colour for facets that arrived without one
+pub fn register_facet_colours(app: &mut App, facets: Vec<String>) {
+    for facet in facets {
+        app.facet_colours
+            .entry(facet)
+            .or_insert(app.config.theme.chart_fg);
+    }
+}
+
+// Pinned colours take precedence over generated ones
+pub fn facet_colour(app: &App, facet: &str) -> Color {
+    app.pinned_colours
+        .get(facet)
+        .or(app.facet_colours.get(facet))
+        .copied()
+        .unwrap_or(app.config.theme.chart_fg)
+}
+
 pub fn render_rename_dialog(app: &mut App, frame: &mut Frame, area: Rect) {
     let area = centered_rect(60, 20, area);
     let vertical = Layout::vertical([Constraint::Length(3), Constraint::Length(3)]);
@@ -649,13 +1361,85 @@ pub fn render_rename_dialog(app: &mut App, frame: &mut Frame, area: Rect) {
     frame.render_widget(input, input_area);
 }
 
+pub fn render_merge_dialog(app: &mut App, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(60, 20, area);
+    let vertical = Layout::vertical([Constraint::Length(3), Constraint::Length(3)]);
+    let [prompt_area, input_area] = vertical.areas(area);
+
+    let marked = app
+        .datasets
+        .selected()
+        .map(|dataset| {
+            dataset
+                .marked_facets
+                .iter()
+                .map(|facet| facet_label(facet))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let prompt = Text::from(format!("Merge {} into", marked.join(", ")));
+    let input = Paragraph::new(app.inputs.get(Focus::MergeFacets))
+        .style(Style::default().fg(app.config.theme.focus_fg))
+        .block(
+            Block::default()
+                .padding(Padding::ZERO)
+                .borders(Borders::BOTTOM),
+        );
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(prompt, prompt_area);
+    frame.render_widget(input, input_area);
+}
+
+pub fn render_dashboard_dialog(app: &mut App, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(60, 40, area);
+    let vertical = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(3),
+        Constraint::Min(0),
+    ]);
+    let [prompt_area, input_area, list_area] = vertical.areas(area);
+
+    let prompt = match app.focus.panel {
+        Focus::DashboardSave => Text::from("Save active queries as dashboard"),
+        _ => Text::from("Load dashboard"),
+    };
+    let input = Paragraph::new(app.inputs.get(app.focus.panel))
+        .style(Style::default().fg(app.config.theme.focus_fg))
+        .block(
+            Block::default()
+                .padding(Padding::ZERO)
+                .borders(Borders::BOTTOM),
+        );
+    let names = List::new(app.dashboard_names()).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .title("Saved dashboards"),
+    );
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(prompt, prompt_area);
+    frame.render_widget(input, input_area);
+    frame.render_widget(names, list_area);
+}
+
 pub fn render_query_list(app: &mut App, frame: &mut Frame, area: Rect) {
     let items = app
         .datasets
         .iter()
-        .map(|(query, data)| match &data.query_alias {
-            Some(alias) => alias.to_owned(),
-            None => query.to_owned(),
+        .map(|(query, data)| {
+            let label = match &data.query_alias {
+                Some(alias) => alias.to_owned(),
+                None => query.to_owned(),
+            };
+            match &data.nrql {
+                Some(nrql) => format!("{} {}", nrql.range_summary(), label),
+                None => match query.as_str().to_nrql() {
+                    Ok(nrql) => format!("{} {}", nrql.range_summary(), label),
+                    Err(_) => label,
+                },
+            }
         })
         .collect::<Vec<_>>();
     let list = List::new(items)
@@ -663,7 +1447,11 @@ pub fn render_query_list(app: &mut App, frame: &mut Frame, area: Rect) {
             Block::default()
                 .borders(Borders::ALL)
                 .border_type(BorderType::Rounded)
-                .title("Active Queries"),
+                .title(match (app.is_refreshing(), app.frozen_windows) {
+                    (true, _) => "Active Queries (refreshing...)",
+                    (false, true) => "Active Queries (frozen)",
+                    (false, false) => "Active Queries",
+                }),
         )
         .highlight_style(
             Style::new()
@@ -677,7 +1465,12 @@ pub fn render_query_list(app: &mut App, frame: &mut Frame, area: Rect) {
 }
 
 pub fn render_query_box(app: &mut App, frame: &mut Frame, area: Rect) {
+    // Scroll horizontally so the cursor stays inside the borders
+    let width = area.width.saturating_sub(2) as usize;
+    let offset = app.inputs.scroll_offset(Focus::QueryInput, width);
+
     let input = Paragraph::new(app.inputs.get(Focus::QueryInput).bold())
+        .scroll((0, offset as u16))
         .style(match app.focus.panel {
             Focus::QueryInput => Style::default().fg(app.config.theme.focus_fg),
             _ => Style::default(),
@@ -689,23 +1482,98 @@ pub fn render_query_box(app: &mut App, frame: &mut Frame, area: Rect) {
                 .title("[Query]".bold()),
         );
     frame.render_widget(input, area);
+
+    if app.focus.panel == Focus::QueryInput && app.focus.input_mode == InputMode::Input {
+        let cursor = app.inputs.get_cursor_position(Focus::QueryInput) - offset;
+        frame.set_cursor_position((area.x + 1 + cursor as u16, area.y + 1));
+    }
 }
 
 pub fn render_graph(app: &mut App, frame: &mut Frame, area: Rect) {
-    let datasets = app.datasets.selected().map(|data| {
-        data.facets
-            .iter()
-            .map(|(facet, points)| {
-                Dataset::default()
-                    .name(facet.to_owned())
-                    .data(&points[..])
-                    .marker(Marker::Braille)
-                    .graph_type(GraphType::Line)
-                    .style(Style::default().fg(app.facet_colours.get(facet).unwrap().to_owned()))
+    if let Some(buckets) = app.datasets.selected().and_then(|d| d.histogram.clone()) {
+        render_histogram(app, frame, area, &buckets);
+        return;
+    }
+
+    if let Some(data) = app.datasets.selected() {
+        let facets = data.facets.keys().cloned().collect::<Vec<String>>();
+        register_facet_colours(app, facets);
+    }
+
+    // Flat average/max lines for the selected facet across the visible window
+    let annotations = app
+        .datasets
+        .selected()
+        .filter(|_| app.show_annotations)
+        .and_then(|data| {
+            let min_x = data.bounds.mins.0;
+            let max_x = data.max_x(Utc::now().timestamp() as f64);
+            let (avg, max) = data.selected_stats(min_x, max_x)?;
+            Some([
+                (format!("avg {:.2}", avg), [(min_x, avg), (max_x, avg)]),
+                (format!("max {:.2}", max), [(min_x, max), (max_x, max)]),
+            ])
+        });
+
+    let mut datasets = app.datasets.selected().map(|data| {
+        let selected_facet = data.selected_facet();
+        data.charted_facets(app.config.facet_sort)
+            .into_iter()
+            .flat_map(|(facet, points)| {
+                // Mark the facet that facet actions apply to, and those picked for merging
+                let name = if data.facets.len() > 1 && selected_facet == Some(facet) {
+                    format!("> {}", facet_label(facet))
+                } else {
+                    facet_label(facet).to_owned()
+                };
+                let name = match data.marked_facets.contains(facet) {
+                    true => format!("{} *", name),
+                    false => name,
+                };
+                // Hidden facets stay in the legend so they can be shown again
+                if app.is_hidden(&app.datasets.selected, facet) {
+                    return facet_datasets(
+                        &[],
+                        Line::from(format!("{} (hidden)", name)).dark_gray(),
+                        Style::default(),
+                        app.config.marker,
+                    );
+                }
+                // Latest value, coloured by its change since the previous refresh
+                let name = match data.latest_facet_value(facet) {
+                    Some(value) => {
+                        let delta = data.facet_delta(facet);
+                        Line::from(vec![
+                            name.into(),
+                            Span::styled(format!(" {:.2} ", value), trend_style(delta)),
+                            trend(delta),
+                        ])
+                    }
+                    None => Line::from(name),
+                };
+                facet_datasets(
+                    points,
+                    name,
+                    Style::default().fg(facet_colour(app, facet)),
+                    app.config.marker,
+                )
             })
             .collect::<Vec<_>>()
     });
 
+    if let (Some(datasets), Some(annotations)) = (&mut datasets, &annotations) {
+        for (name, points) in annotations {
+            datasets.push(
+                Dataset::default()
+                    .name(name.clone())
+                    .data(points)
+                    .marker(app.config.marker)
+                    .graph_type(GraphType::Line)
+                    .style(Style::default().fg(Color::DarkGray)),
+            );
+        }
+    }
+
     if let Some(datasets) = datasets {
         let dataset = app
             .datasets
@@ -714,20 +1582,29 @@ pub fn render_graph(app: &mut App, frame: &mut Frame, area: Rect) {
 
         let bounds = dataset.bounds;
         let selection = &dataset.selection;
-
-        let (min_x, mut min_y) = bounds.mins;
-        let (_, mut max_y) = bounds.maxes;
-        let mut half_y = (max_y - min_y) / 2_f64;
-
-        min_y = f64::round(min_y);
-        max_y = f64::round(max_y);
-        half_y = f64::round(half_y);
+        let max_x = dataset.max_x(Utc::now().timestamp() as f64);
+
+        let (min_x, _) = bounds.mins;
+        let (y_bounds, y_labels) = y_axis_scale(selection, bounds, app.config.precision);
+
+        // Opt-in, keeps very wide, short graphs from flattening every line
+        let (area, y_labels) = match app.config.graph_min_ratio {
+            Some(ratio) => {
+                let area = letterbox(area, ratio);
+                let y_labels = match area.height < SHORT_GRAPH_HEIGHT {
+                    true => y_labels.into_iter().step_by(2).collect::<Vec<_>>(),
+                    false => y_labels.to_vec(),
+                };
+                (area, y_labels)
+            }
+            None => (area, y_labels.to_vec()),
+        };
 
         // Create the X axis and define its properties
         let x_axis = Axis::default()
             .title("Time".fg(app.config.theme.chart_fg))
             .style(Style::default().fg(app.config.theme.chart_fg))
-            .bounds([min_x, Utc::now().timestamp() as f64])
+            .bounds([min_x, max_x])
             .labels(vec![
                 DateTime::from_timestamp(min_x as i64, 0)
                     .unwrap()
@@ -735,7 +1612,7 @@ pub fn render_graph(app: &mut App, frame: &mut Frame, area: Rect) {
                     .to_string()
                     .fg(app.config.theme.chart_fg)
                     .bold(),
-                DateTime::from_timestamp(Utc::now().timestamp(), 0)
+                DateTime::from_timestamp(max_x as i64, 0)
                     .unwrap()
                     .to_string()
                     .fg(app.config.theme.chart_fg)
@@ -744,20 +1621,26 @@ pub fn render_graph(app: &mut App, frame: &mut Frame, area: Rect) {
 
         // Create the Y axis and define its properties
         let y_axis = Axis::default()
-            .title(selection.clone().fg(app.config.theme.chart_fg))
+            .title(
+                y_axis_title(selection, dataset.rate_unit.as_deref()).fg(app.config.theme.chart_fg),
+            )
             .style(Style::default().fg(app.config.theme.chart_fg))
-            .bounds([min_y, max_y])
-            .labels(vec![
-                min_y.to_string().fg(app.config.theme.chart_fg).bold(),
-                half_y.to_string().fg(app.config.theme.chart_fg).bold(),
-                max_y.to_string().fg(app.config.theme.chart_fg).bold(),
-            ]);
-
-        let legend_position = match &datasets.len() {
+            .bounds(y_bounds)
+            .labels(
+                y_labels
+                    .into_iter()
+                    .map(|label| label.fg(app.config.theme.chart_fg).bold())
+                    .collect::<Vec<_>>(),
+            );
+
+        let n_named = dataset.facets.len() + annotations.as_ref().map_or(0, |a| a.len());
+        let legend_position = match n_named {
             1 => None,
             _ => Some(LegendPosition::TopRight),
         };
 
+        let subtitle = dataset.subtitle().unwrap_or_default();
+
         // Create the chart and link all the parts together
         let chart = Chart::new(datasets)
             .block(
@@ -765,7 +1648,8 @@ pub fn render_graph(app: &mut App, frame: &mut Frame, area: Rect) {
                     .borders(Borders::ALL)
                     .border_style(Style::default().fg(app.config.theme.chart_fg))
                     .border_type(BorderType::Thick)
-                    .border_type(BorderType::Rounded),
+                    .border_type(BorderType::Rounded)
+                    .title_bottom(Line::from(subtitle).centered()),
             )
             .legend_position(legend_position)
             .x_axis(x_axis)
@@ -775,6 +1659,102 @@ pub fn render_graph(app: &mut App, frame: &mut Frame, area: Rect) {
     // frame.render_widget(chart, frame.size());
 }
 
+// Narrows an area to at most `1 / min_ratio` columns per row, centred, leaving others as they are
+pub fn letterbox(area: Rect, min_ratio: f64) -> Rect {
+    if min_ratio <= 0_f64 {
+        return area;
+    }
+    let max_width = (area.height as f64 / min_ratio).floor() as u16;
+    if area.width <= max_width {
+        return area;
+    }
+
+    Rect {
+        x: area.x + (area.width - max_width) / 2,
+        width: max_width,
+        ..area
+    }
+}
+
+// The selection, qualified with the interval of rate() queries
+fn y_axis_title(selection: &str, rate_unit: Option<&str>) -> String {
+    match rate_unit {
+        Some(unit) => format!("{} ({})", selection, unit),
+        None => selection.to_owned(),
+    }
+}
+
+// Percentages get a fixed 0-100 axis, everything else scales to the data
+fn y_axis_scale(
+    selection: &str,
+    bounds: Bounds,
+    precision: Option<usize>,
+) -> ([f64; 2], [String; 3]) {
+    if is_percentage(selection) {
+        return ([0.0, 100.0], ["0%".into(), "50%".into(), "100%".into()]);
+    }
+
+    let (min, max) = (bounds.mins.1, bounds.maxes.1);
+    let precision = precision.unwrap_or_else(|| auto_precision(min, max));
+    let round = |value: f64| {
+        let scale = 10_f64.powi(precision as i32);
+        (value * scale).round() / scale
+    };
+
+    let min_y = round(min);
+    let max_y = round(max);
+    let half_y = round((min + max) / 2_f64);
+
+    (
+        [min_y, max_y],
+        [min_y, half_y, max_y].map(|value| format!("{:.*}", precision, value)),
+    )
+}
+
+// Enough decimal places for two significant figures across the data's range
+pub fn auto_precision(min: f64, max: f64) -> usize {
+    let span = match max - min {
+        span if span > 0_f64 => span,
+        _ => f64::max(min.abs(), max.abs()),
+    };
+    if span <= 0_f64 || !span.is_finite() {
+        return 0;
+    }
+
+    (1 - span.log10().floor() as i32).clamp(0, 6) as usize
+}
+
+pub fn render_histogram(app: &mut App, frame: &mut Frame, area: Rect, buckets: &[(String, u64)]) {
+    let bars = buckets
+        .iter()
+        .map(|(label, count)| {
+            Bar::default()
+                .label(label.clone().into())
+                .value(*count)
+                .style(Style::default().fg(app.config.theme.chart_fg))
+        })
+        .collect::<Vec<_>>();
+
+    // Fit every bucket into the available width
+    let inner_width = area.width.saturating_sub(2) as usize;
+    let bar_width = (inner_width / buckets.len().max(1))
+        .saturating_sub(1)
+        .max(1) as u16;
+
+    let chart = BarChart::default()
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(app.config.theme.chart_fg))
+                .border_type(BorderType::Rounded),
+        )
+        .data(BarGroup::default().bars(&bars))
+        .bar_width(bar_width)
+        .bar_gap(1);
+
+    frame.render_widget(chart, area);
+}
+
 pub fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     let popup_layout = Layout::vertical([
         Constraint::Percentage((100 - percent_y) / 2),
@@ -790,3 +1770,291 @@ pub fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     ])
     .split(popup_layout[1])[1]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend::PayloadType, demo::DEMO_QUERIES, query::QueryType, tests::test_app};
+    use ratatui::backend::TestBackend;
+    use tokio::sync::mpsc::Sender;
+
+    const TIMESERIES_QUERY: &str = DEMO_QUERIES[0];
+
+    // Charts the demo data for `query` as if it had just arrived from the backend, and selects it
+    fn chart(app: &mut App, data_tx: &Sender<PayloadType>, query: &str) {
+        data_tx
+            .try_send(crate::demo::payload(query).unwrap())
+            .unwrap();
+        app.drain_payloads();
+        let key = QueryType::from(query).key();
+        let i = app.datasets.iter().position(|(k, _)| *k == key).unwrap();
+        app.datasets.select(i);
+    }
+
+    // Draws with `render` on a terminal of the given size, returning the screen as text
+    fn draw(
+        app: &mut App,
+        (width, height): (u16, u16),
+        render: impl FnOnce(&mut App, &mut Frame, Rect),
+    ) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal
+            .draw(|frame| render(app, frame, frame.area()))
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect::<String>()
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn empty_dashboard_shows_a_hint() {
+        assert!(dashboard_areas(0, Rect::new(0, 0, 80, 24)).is_empty());
+
+        let (mut app, _, _) = test_app("empty-dashboard");
+        let screen = draw(&mut app, (80, 24), render_dashboard);
+        assert!(screen.contains("No queries yet"));
+    }
+
+    #[test]
+    fn large_dashboards_are_laid_out_in_a_grid() {
+        let area = Rect::new(0, 0, 80, 24);
+
+        assert_eq!(
+            dashboard_areas(4, area),
+            vec![
+                Rect::new(0, 0, 40, 12),
+                Rect::new(40, 0, 40, 12),
+                Rect::new(0, 12, 40, 12),
+                Rect::new(40, 12, 40, 12),
+            ]
+        );
+        let areas = dashboard_areas(5, area);
+        assert_eq!(areas.len(), 5);
+        assert_eq!(areas[4], Rect::new(0, 16, 80, 8));
+
+        let (mut app, data_tx, _) = test_app("large-dashboard");
+        for limit in 1..=5 {
+            let query = format!(
+                "FROM Transaction SELECT count(*) WHERE duration > 0 FACET appName \
+                 SINCE 1 hour ago UNTIL now LIMIT {} TIMESERIES",
+                limit
+            );
+            chart(&mut app, &data_tx, &query);
+        }
+        assert_eq!(app.datasets.len(), 5);
+        draw(&mut app, (80, 24), render_dashboard);
+    }
+
+    #[test]
+    fn histogram_buckets_render_in_ascending_order() {
+        let data = vec![serde_json::json!({ "histogram.duration": { "buckets": [
+            { "minValue": 300.0, "maxValue": 400.0, "count": 2 },
+            { "minValue": 100.0, "maxValue": 200.0, "count": 4 },
+            { "minValue": 200.0, "maxValue": 300.0, "count": 6 },
+        ] } })];
+        let buckets =
+            crate::backend::parse_histogram("SELECT histogram(duration) FROM Transaction", &data);
+
+        let (mut app, _, _) = test_app("histogram-order");
+        let screen = draw(&mut app, (32, 10), |app, frame, area| {
+            render_histogram(app, frame, area, &buckets)
+        });
+        let labels = screen.lines().find(|line| line.contains("100")).unwrap();
+        let position = |label| labels.find(label).unwrap();
+        assert!(position("100") < position("200"));
+        assert!(position("200") < position("300"));
+    }
+
+    #[test]
+    fn unregistered_facet_is_given_a_colour() {
+        let (mut app, data_tx, _) = test_app("unregistered-facet");
+        chart(&mut app, &data_tx, TIMESERIES_QUERY);
+        app.datasets
+            .selected_mut()
+            .unwrap()
+            .facets
+            .insert(String::from("unregistered"), vec![(0.0, 1.0), (60.0, 2.0)]);
+        assert!(!app.facet_colours.contains_key("unregistered"));
+
+        draw(&mut app, (80, 24), render_graph);
+        assert!(app.facet_colours.contains_key("unregistered"));
+    }
+
+    #[test]
+    fn detail_is_sliced_at_the_scroll_offset() {
+        let line = "level=ERROR message=\"Connection refused\"";
+        assert_eq!(hslice(line, 0, 11), "level=ERROR");
+        assert_eq!(hslice(line, 12, 7), "message");
+        // Near or past the end, only what's left is shown
+        assert_eq!(hslice(line, 38, 20), "d\"");
+        assert_eq!(hslice(line, 100, 20), "");
+        // Offsets count characters rather than bytes
+        assert_eq!(hslice("préfixe", 2, 3), "éfi");
+    }
+
+    #[test]
+    fn small_terminal_shows_a_resize_hint() {
+        assert!(!is_too_small(Rect::new(0, 0, MIN_WIDTH, MIN_HEIGHT)));
+        assert!(is_too_small(Rect::new(0, 0, MIN_WIDTH - 1, MIN_HEIGHT)));
+        assert!(is_too_small(Rect::new(0, 0, MIN_WIDTH, MIN_HEIGHT - 1)));
+
+        let (mut app, _, _) = test_app("too-small");
+        let screen = draw(&mut app, (30, 8), |app, frame, _| ui(app, frame));
+        assert!(screen.contains("Terminal too small"));
+    }
+
+    #[test]
+    fn help_lists_every_action() {
+        let (mut app, _, _) = test_app("help");
+        let screen = draw(&mut app, (200, 100), render_help);
+        for (_, key, action) in KEYBINDINGS {
+            assert!(
+                screen.contains(&format!("{:<16}{}", key, action)),
+                "{} is missing",
+                action
+            );
+        }
+    }
+
+    #[test]
+    fn rate_unit_is_shown_in_the_axis_title() {
+        let (mut app, data_tx, _) = test_app("rate-axis");
+        chart(
+            &mut app,
+            &data_tx,
+            "FROM Transaction SELECT rate(count(*), 1 minute) WHERE duration > 0 SINCE 1 hour ago UNTIL now LIMIT MAX TIMESERIES",
+        );
+
+        let screen = draw(&mut app, (120, 30), render_graph);
+        assert!(screen.contains("rate(count(*), 1 minute) (per minute)"));
+    }
+
+    #[test]
+    fn disabled_splash_renders_the_hint() {
+        let (mut app, _, _) = test_app("splash");
+        let hint = "Old Relic - press e to add a query, ? for help";
+        assert!(!draw(&mut app, (80, 24), render_splash).contains(hint));
+
+        app.config.splash = false;
+        assert!(draw(&mut app, (80, 24), render_splash).contains(hint));
+    }
+
+    #[test]
+    fn facet_trend_follows_the_previous_refresh() {
+        let (mut app, data_tx, _) = test_app("facet-trend");
+        let key = QueryType::from(TIMESERIES_QUERY).key();
+        for (checkout, search) in [(3.0, 4.0), (5.0, 1.0)] {
+            let data = [("checkout", checkout), ("search", search)]
+                .iter()
+                .map(|(facet, value)| (facet.to_string(), vec![(0.0, 0.0), (60.0, *value)]))
+                .collect();
+            data_tx
+                .try_send(PayloadType::Timeseries(crate::backend::Payload {
+                    query: key.clone(),
+                    data,
+                    ..Default::default()
+                }))
+                .unwrap();
+            app.drain_payloads();
+        }
+        let data = &app.datasets.datasets[&key];
+
+        let rose = data.facet_delta("checkout");
+        assert_eq!(rose, Some(2.0));
+        assert_eq!(trend(rose).content, "▲ ");
+        assert_eq!(trend_style(rose).fg, Some(Color::LightRed));
+
+        let fell = data.facet_delta("search");
+        assert_eq!(fell, Some(-3.0));
+        assert_eq!(trend(fell).content, "▼ ");
+        assert_eq!(trend_style(fell).fg, Some(Color::LightGreen));
+    }
+
+    #[test]
+    fn barchart_grows_with_the_terminal() {
+        assert_eq!(barchart_height(20), MIN_BARCHART_HEIGHT);
+        assert_eq!(barchart_height(60), 15);
+        assert_eq!(barchart_height(200), MAX_BARCHART_HEIGHT);
+    }
+
+    #[test]
+    fn small_values_get_fractional_axis_labels() {
+        let bounds = Bounds {
+            mins: (0.0, 0.0),
+            maxes: (60.0, 0.05),
+        };
+
+        let (y_bounds, labels) = y_axis_scale("average(errorRate)", bounds, None);
+        assert_eq!(y_bounds, [0.0, 0.05]);
+        assert_eq!(labels, ["0.000", "0.025", "0.050"].map(String::from));
+
+        let (_, labels) = y_axis_scale("average(errorRate)", bounds, Some(1));
+        assert_eq!(labels, ["0.0", "0.0", "0.1"].map(String::from));
+    }
+
+    #[test]
+    fn compact_logs_give_the_barchart_no_rows() {
+        let area = Rect::new(0, 0, 80, 40);
+
+        let [barchart, seek, logs] = logs_layout(area, true);
+        assert_eq!((barchart.height, seek.height, logs.height), (0, 0, 40));
+
+        let [barchart, seek, logs] = logs_layout(area, false);
+        assert_eq!((barchart.height, seek.height, logs.height), (10, 1, 29));
+    }
+
+    #[test]
+    fn non_numeric_selection_renders_without_a_seek_marker() {
+        let (mut app, _, _) = test_app("non-numeric-selection");
+        app.logs
+            .logs
+            .insert(String::from("not-a-time"), vec![String::from("log")]);
+        app.logs.select_first();
+        app.logs.bounds = Bounds {
+            mins: (1_700_000_000_000.0, 0.0),
+            maxes: (1_700_000_060_000.0, 1.0),
+        };
+
+        let seek = draw(&mut app, (60, 1), render_seek);
+        assert!(seek.trim().is_empty());
+        draw(&mut app, (60, 12), render_barchart);
+    }
+
+    #[test]
+    fn empty_log_query_shows_the_logs_no_result_panel() {
+        let (mut app, data_tx, _) = test_app("logs-no-result");
+        app.focus.tab = Tab::Logs;
+        let screen = draw(&mut app, (120, 40), |app, frame, _| ui(app, frame));
+        assert!(!screen.contains("No logs in this window"));
+
+        // As left by dispatching the query
+        let query = "SELECT * FROM Log WHERE level = 'FATAL'";
+        app.last_log_query = Some(query.to_owned());
+        data_tx
+            .try_send(PayloadType::None(query.to_owned()))
+            .unwrap();
+        app.drain_payloads();
+
+        let screen = draw(&mut app, (120, 40), |app, frame, _| ui(app, frame));
+        assert!(screen.contains("[No logs]"));
+        assert!(screen.contains("No logs in this window"));
+        assert!(screen.contains(query));
+    }
+
+    #[test]
+    fn only_extremely_wide_areas_are_letterboxed() {
+        // 300 columns by 10 rows, at most 10 columns per row
+        let wide = Rect::new(5, 2, 300, 10);
+        assert_eq!(letterbox(wide, 0.1), Rect::new(105, 2, 100, 10));
+
+        let tall = Rect::new(0, 0, 40, 60);
+        assert_eq!(letterbox(tall, 0.1), tall);
+        assert_eq!(letterbox(wide, 0.0), wide);
+    }
+}
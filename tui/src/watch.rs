@@ -0,0 +1,241 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use std::time::Duration;
+use tokio::time;
+
+use crate::{
+    backend::{facet_label, query_timeseries, Payload},
+    client::NewRelicClient,
+    query::NRQL,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Above,
+    AtLeast,
+    Below,
+    AtMost,
+    Equal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Threshold {
+    pub comparison: Comparison,
+    pub value: f64,
+}
+
+impl Threshold {
+    pub fn parse(op: &str, value: &str) -> Result<Self> {
+        let comparison = match op {
+            ">" => Comparison::Above,
+            ">=" => Comparison::AtLeast,
+            "<" => Comparison::Below,
+            "<=" => Comparison::AtMost,
+            "==" | "=" => Comparison::Equal,
+            _ => return Err(anyhow!("Invalid threshold operator '{}'", op)),
+        };
+        let value = value
+            .parse::<f64>()
+            .map_err(|_| anyhow!("Invalid threshold value '{}'", value))?;
+
+        Ok(Threshold { comparison, value })
+    }
+
+    pub fn is_breached(&self, value: f64) -> bool {
+        match self.comparison {
+            Comparison::Above => value > self.value,
+            Comparison::AtLeast => value >= self.value,
+            Comparison::Below => value < self.value,
+            Comparison::AtMost => value <= self.value,
+            Comparison::Equal => value == self.value,
+        }
+    }
+}
+
+pub struct WatchArgs {
+    pub nrql: String,
+    pub threshold: Threshold,
+}
+
+// Parses `--watch --nrql <query> --threshold <op> <value>`, returning None without --watch
+pub fn parse_args(args: &[String]) -> Result<Option<WatchArgs>> {
+    if !args.iter().any(|arg| arg == "--watch") {
+        return Ok(None);
+    }
+
+    // Offline modes never build a client, so there'd be nothing to poll
+    if let Some(flag) = args
+        .iter()
+        .find(|arg| *arg == "--demo" || *arg == "--import")
+    {
+        return Err(anyhow!("--watch can't be combined with {}", flag));
+    }
+
+    let position = |flag: &str| args.iter().position(|arg| arg == flag);
+
+    let nrql = position("--nrql")
+        .and_then(|i| args.get(i + 1))
+        .ok_or(anyhow!("--watch requires --nrql <query>"))?;
+    let threshold = match position("--threshold") {
+        Some(i) => match (args.get(i + 1), args.get(i + 2)) {
+            (Some(op), Some(value)) => Threshold::parse(op, value)?,
+            _ => return Err(anyhow!("--threshold requires <op> <value>")),
+        },
+        None => return Err(anyhow!("--watch requires --threshold <op> <value>")),
+    };
+
+    Ok(Some(WatchArgs {
+        nrql: nrql.to_owned(),
+        threshold,
+    }))
+}
+
+// Latest value of each facet that crosses the threshold
+pub fn breaches(payload: &Payload, threshold: &Threshold) -> Vec<(String, f64)> {
+    payload
+        .data
+        .iter()
+        .filter_map(|(facet, points)| {
+            points
+                .iter()
+                .max_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(_, value)| (facet.to_owned(), *value))
+        })
+        .filter(|(_, value)| threshold.is_breached(*value))
+        .collect()
+}
+
+// Exit code once the threshold is breached, set apart from 2 for a fatal error
+pub const BREACH_EXIT_CODE: i32 = 1;
+
+// The exit code a poll ends the watch with, None to keep polling. A failed request is
+// reported and retried on the next tick, so only a breach exits with BREACH_EXIT_CODE
+pub fn poll_outcome(result: Result<Payload>, threshold: &Threshold) -> Option<i32> {
+    let payload = match result {
+        Ok(payload) => payload,
+        Err(e) => {
+            warn!("Watch query failed, retrying: {}", e);
+            eprintln!("WARNING: Query failed, retrying: {}", e);
+            return None;
+        }
+    };
+
+    let breaches = breaches(&payload, threshold);
+    if breaches.is_empty() {
+        return None;
+    }
+    for (facet, value) in breaches {
+        println!("{}: {}", facet_label(&facet), value);
+    }
+    Some(BREACH_EXIT_CODE)
+}
+
+// Polls the query until it breaches the threshold, returning the process exit code
+pub async fn watch(args: WatchArgs, client: NewRelicClient, interval: Duration) -> Result<i32> {
+    let query = args.nrql.as_str().to_nrql()?;
+    let mut interval = time::interval(interval);
+
+    loop {
+        interval.tick().await;
+        let result = query_timeseries(query.clone(), client.clone()).await;
+        if let Some(code) = poll_outcome(result, &args.threshold) {
+            return Ok(code);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn series(values: &[f64]) -> Payload {
+        let points = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| (i as f64 * 60.0, *value))
+            .collect();
+        Payload {
+            data: BTreeMap::from([(String::from("web"), points)]),
+            ..Payload::default()
+        }
+    }
+
+    #[test]
+    fn breach_exits_non_zero() {
+        let threshold = Threshold::parse(">", "100").unwrap();
+
+        assert_eq!(
+            poll_outcome(Ok(series(&[10.0, 150.0])), &threshold),
+            Some(BREACH_EXIT_CODE)
+        );
+    }
+
+    #[test]
+    fn series_below_the_threshold_keeps_polling() {
+        let threshold = Threshold::parse(">", "100").unwrap();
+
+        // Only the latest value counts
+        assert_eq!(poll_outcome(Ok(series(&[150.0, 90.0])), &threshold), None);
+    }
+
+    #[test]
+    fn failed_poll_keeps_polling() {
+        let threshold = Threshold::parse(">", "100").unwrap();
+
+        assert_eq!(
+            poll_outcome(Err(anyhow!("502 Bad Gateway")), &threshold),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_watch_arguments() {
+        let args = [
+            "tui",
+            "--watch",
+            "--nrql",
+            "FROM Log",
+            "--threshold",
+            ">=",
+            "5",
+        ]
+        .map(String::from);
+
+        let watch = parse_args(&args).unwrap().unwrap();
+
+        assert_eq!(watch.nrql, "FROM Log");
+        assert_eq!(
+            watch.threshold,
+            Threshold {
+                comparison: Comparison::AtLeast,
+                value: 5.0
+            }
+        );
+        assert!(parse_args(&args[..4]).is_err());
+    }
+
+    #[test]
+    fn watch_rejects_offline_modes() {
+        for offline in [&["--demo"][..], &["--import", "bundle.yaml"]] {
+            let args = [
+                "tui",
+                "--watch",
+                "--nrql",
+                "FROM Log",
+                "--threshold",
+                ">",
+                "5",
+            ]
+            .into_iter()
+            .chain(offline.iter().copied())
+            .map(String::from)
+            .collect::<Vec<String>>();
+
+            assert_eq!(
+                parse_args(&args).err().unwrap().to_string(),
+                format!("--watch can't be combined with {}", offline[0])
+            );
+        }
+    }
+}